@@ -68,6 +68,15 @@ impl AABB {
         t0 < t_max && t1 > t_min
     }
 
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
     pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
         let small = Vec3::new(
             box0.min.x.min(box1.min.x),
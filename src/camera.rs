@@ -24,7 +24,51 @@ fn random_in_unit_disk<R: Rng>(rng: &mut R) -> Vec3 {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct CameraSettings {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub vup: Vec3,
+    pub vfov: f32,
+    pub aperture: f32,
+    pub focus_dist: f32,
+    pub time_0: f32,
+    pub time_1: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> CameraSettings {
+        CameraSettings {
+            look_from: Vec3::new(0., 0., 0.),
+            look_at: Vec3::new(0., 0., -1.),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 40.,
+            aperture: 0.,
+            focus_dist: 10.,
+            time_0: 0.,
+            time_1: 1.,
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect: f32) -> Camera {
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.vup,
+            self.vfov,
+            aspect,
+            self.aperture,
+            self.focus_dist,
+            self.time_0,
+            self.time_1,
+        )
+    }
+}
+
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         look_from: Vec3,
         look_at: Vec3,
@@ -60,6 +104,12 @@ impl Camera {
         }
     }
 
+    /// The angle a pixel covers when the image is `height` pixels high.
+    pub fn pixel_spread(&self, height: usize) -> f32 {
+        let center = self.lower_left_corner + self.horizontal * 0.5 + self.vertical * 0.5;
+        self.vertical.length() / ((center - self.origin).length() * height as f32)
+    }
+
     pub fn get_ray<R: Rng>(&self, s: f32, t: f32, rng: &mut R) -> Ray {
         let rd = random_in_unit_disk(rng) * self.lens_radius;
         let offset = self.uvw.0 * rd.x + self.uvw.1 * rd.y;
@@ -0,0 +1,270 @@
+use crate::camera::CameraSettings;
+use crate::hitables::bvh::BvhBuilder;
+use crate::output::OutputFormat;
+use crate::render::available_threads;
+use crate::scenes::Scene;
+use crate::textures::MissingTextures;
+use crate::vectors::Vec3;
+use std::str::FromStr;
+
+pub const USAGE: &str = "Usage: ray-tracing-the-next-week [OPTIONS]
+
+Options:
+    -s, --scene <NAME>        scene to render (default: final, see --list-scenes)
+    -f, --file <PATH>         render a .toml or .json scene description instead of a built-in scene
+    -w, --width <PIXELS>      image width (default: 500)
+        --height <PIXELS>     image height (default: 500)
+    -n, --samples <N>         samples per pixel (default: 200)
+    -d, --max-depth <N>       maximum number of bounces per path (default: 60)
+    -o, --output <PATH>       output file (.png, .ppm, .exr or .hdr), `-` for PPM on stdout (default: -)
+        --seed <N>            seed for the random number generator (default: random)
+    -t, --threads <N>         number of render threads (default: all cores)
+        --bvh <BUILDER>       BVH construction, `sah` or `random` (default: sah)
+        --spectral            trace sampled wavelengths instead of RGB, for dispersion
+        --placeholder-textures
+                              render missing or unreadable images as a magenta checker instead of failing
+        --look-from <X,Y,Z>   camera position (default: scene specific)
+        --look-at <X,Y,Z>     camera target (default: scene specific)
+        --vfov <DEGREES>      vertical field of view (default: scene specific)
+        --aperture <F>        lens aperture (default: scene specific)
+        --focus-dist <F>      focus distance (default: scene specific)
+        --list-scenes         print the names of the built-in scenes and exit
+    -h, --help                print this message and exit";
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub scene: Scene,
+    pub scene_file: Option<String>,
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub max_depth: i32,
+    pub output: String,
+    pub seed: Option<u64>,
+    pub threads: usize,
+    pub bvh: BvhBuilder,
+    pub spectral: bool,
+    pub missing_textures: MissingTextures,
+    pub look_from: Option<Vec3>,
+    pub look_at: Option<Vec3>,
+    pub vfov: Option<f32>,
+    pub aperture: Option<f32>,
+    pub focus_dist: Option<f32>,
+    pub list_scenes: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scene: Scene::Final,
+            scene_file: None,
+            width: 500,
+            height: 500,
+            samples: 200,
+            max_depth: 60,
+            output: String::from("-"),
+            seed: None,
+            threads: available_threads(),
+            bvh: BvhBuilder::Sah,
+            spectral: false,
+            missing_textures: MissingTextures::Fail,
+            look_from: None,
+            look_at: None,
+            vfov: None,
+            aperture: None,
+            focus_dist: None,
+            list_scenes: false,
+            help: false,
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value `{}` for {}", value, flag))
+}
+
+fn parse_vec3(flag: &str, value: &str) -> Result<Vec3, String> {
+    let parts = value
+        .split(',')
+        .map(|part| parse_number::<f32>(flag, part.trim()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts.as_slice() {
+        [x, y, z] => Ok(Vec3::new(*x, *y, *z)),
+        _ => Err(format!("expected X,Y,Z for {}, got `{}`", flag, value)),
+    }
+}
+
+fn next_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("missing value for {}", flag))
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(flag) = args.next() {
+            let flag = flag.as_str();
+            match flag {
+                "--list-scenes" => options.list_scenes = true,
+                "-h" | "--help" => options.help = true,
+                "--spectral" => options.spectral = true,
+                "--placeholder-textures" => options.missing_textures = MissingTextures::Placeholder,
+                "-s" | "--scene" => {
+                    let value = next_value(&mut args, flag)?;
+                    options.scene =
+                        Scene::from_name(&value).ok_or_else(|| format!("unknown scene `{}`", value))?;
+                }
+                "-f" | "--file" => options.scene_file = Some(next_value(&mut args, flag)?),
+                "-w" | "--width" => options.width = parse_number(flag, &next_value(&mut args, flag)?)?,
+                "--height" => options.height = parse_number(flag, &next_value(&mut args, flag)?)?,
+                "-n" | "--samples" => options.samples = parse_number(flag, &next_value(&mut args, flag)?)?,
+                "-d" | "--max-depth" => {
+                    options.max_depth = parse_number(flag, &next_value(&mut args, flag)?)?
+                }
+                "-o" | "--output" => options.output = next_value(&mut args, flag)?,
+                "--seed" => options.seed = Some(parse_number(flag, &next_value(&mut args, flag)?)?),
+                "-t" | "--threads" => options.threads = parse_number(flag, &next_value(&mut args, flag)?)?,
+                "--bvh" => {
+                    let value = next_value(&mut args, flag)?;
+                    options.bvh =
+                        BvhBuilder::from_name(&value).ok_or_else(|| format!("unknown BVH builder `{}`", value))?;
+                }
+                "--look-from" => options.look_from = Some(parse_vec3(flag, &next_value(&mut args, flag)?)?),
+                "--look-at" => options.look_at = Some(parse_vec3(flag, &next_value(&mut args, flag)?)?),
+                "--vfov" => options.vfov = Some(parse_number(flag, &next_value(&mut args, flag)?)?),
+                "--aperture" => options.aperture = Some(parse_number(flag, &next_value(&mut args, flag)?)?),
+                "--focus-dist" => {
+                    options.focus_dist = Some(parse_number(flag, &next_value(&mut args, flag)?)?)
+                }
+                _ => return Err(format!("unknown option `{}`", flag)),
+            }
+        }
+        if options.width == 0 || options.height == 0 || options.samples == 0 || options.threads == 0 {
+            return Err(String::from("width, height, samples and threads must be positive"));
+        }
+        if options.output != "-" && OutputFormat::from_path(&options.output).is_none() {
+            return Err(format!("unsupported output format `{}`", options.output));
+        }
+        Ok(options)
+    }
+
+    /// The scene's camera with any command-line overrides applied.
+    pub fn camera_settings(&self, defaults: CameraSettings) -> CameraSettings {
+        CameraSettings {
+            look_from: self.look_from.unwrap_or(defaults.look_from),
+            look_at: self.look_at.unwrap_or(defaults.look_at),
+            vfov: self.vfov.unwrap_or(defaults.vfov),
+            aperture: self.aperture.unwrap_or(defaults.aperture),
+            focus_dist: self.focus_dist.unwrap_or(defaults.focus_dist),
+            ..defaults
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults_apply_without_arguments() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.scene, Scene::Final);
+        assert_eq!(options.scene_file, None);
+        assert_eq!((options.width, options.height, options.samples, options.max_depth), (500, 500, 200, 60));
+        assert_eq!(options.output, "-");
+        assert_eq!(options.seed, None);
+        assert_eq!(options.threads, available_threads());
+        assert_eq!(options.bvh, BvhBuilder::Sah);
+        assert_eq!(options.missing_textures, MissingTextures::Fail);
+        assert!(!options.spectral && !options.list_scenes && !options.help);
+        assert!(options.look_from.is_none() && options.look_at.is_none());
+        assert!(options.vfov.is_none() && options.aperture.is_none() && options.focus_dist.is_none());
+    }
+
+    #[test]
+    fn every_flag_is_read() {
+        let options = parse(&[
+            "--scene", "cornell_box", "--file", "scenes/glass.toml", "--width", "64", "--height", "48", "--samples",
+            "9", "--max-depth", "7", "--output", "out.exr", "--seed", "42", "--threads", "3", "--bvh", "random",
+            "--spectral", "--placeholder-textures", "--look-from", "1,2,3", "--look-at", " 4, 5 ,6 ", "--vfov", "30",
+            "--aperture", "0.1", "--focus-dist", "10", "--list-scenes", "--help",
+        ])
+        .unwrap();
+        assert_eq!(options.scene, Scene::CornellBox);
+        assert_eq!(options.scene_file.as_deref(), Some("scenes/glass.toml"));
+        assert_eq!((options.width, options.height, options.samples, options.max_depth), (64, 48, 9, 7));
+        assert_eq!(options.output, "out.exr");
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.threads, 3);
+        assert_eq!(options.bvh, BvhBuilder::RandomMedian);
+        assert_eq!(options.missing_textures, MissingTextures::Placeholder);
+        assert!(options.spectral && options.list_scenes && options.help);
+        assert!((options.look_from.unwrap() - Vec3::new(1., 2., 3.)).length() < 1e-6);
+        assert!((options.look_at.unwrap() - Vec3::new(4., 5., 6.)).length() < 1e-6);
+        assert_eq!((options.vfov, options.aperture, options.focus_dist), (Some(30.), Some(0.1), Some(10.)));
+
+        let short = parse(&[
+            "-s", "simple_light", "-f", "a.json", "-w", "8", "-n", "2", "-d", "3", "-o", "a.png", "-t", "1",
+        ])
+        .unwrap();
+        assert_eq!(short.scene, Scene::SimpleLight);
+        assert_eq!(short.scene_file.as_deref(), Some("a.json"));
+        assert_eq!((short.width, short.samples, short.max_depth, short.threads), (8, 2, 3, 1));
+        assert_eq!(short.output, "a.png");
+        assert!(parse(&["-h"]).unwrap().help);
+    }
+
+    #[test]
+    fn vectors_need_three_numbers() {
+        assert_eq!(parse_vec3("--look-at", "1,2").unwrap_err(), "expected X,Y,Z for --look-at, got `1,2`");
+        assert_eq!(parse_vec3("--look-at", "1,2,3,4").unwrap_err(), "expected X,Y,Z for --look-at, got `1,2,3,4`");
+        assert_eq!(parse_vec3("--look-at", "1,x,3").unwrap_err(), "invalid value `x` for --look-at");
+        assert_eq!(parse_vec3("--look-at", "").unwrap_err(), "invalid value `` for --look-at");
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        assert_eq!(parse(&["--frobnicate"]).unwrap_err(), "unknown option `--frobnicate`");
+        assert_eq!(parse(&["--width"]).unwrap_err(), "missing value for --width");
+        assert_eq!(parse(&["-o"]).unwrap_err(), "missing value for -o");
+        assert_eq!(parse(&["--samples", "many"]).unwrap_err(), "invalid value `many` for --samples");
+        assert_eq!(parse(&["--scene", "nowhere"]).unwrap_err(), "unknown scene `nowhere`");
+        assert_eq!(parse(&["--bvh", "octree"]).unwrap_err(), "unknown BVH builder `octree`");
+        for flag in &["--width", "--height", "--samples", "--threads"] {
+            assert_eq!(parse(&[flag, "0"]).unwrap_err(), "width, height, samples and threads must be positive");
+        }
+        assert_eq!(parse(&["-o", "image.jpg"]).unwrap_err(), "unsupported output format `image.jpg`");
+        assert_eq!(parse(&["-o", "image"]).unwrap_err(), "unsupported output format `image`");
+        assert!(parse(&["-o", "IMAGE.PNG"]).is_ok());
+    }
+
+    #[test]
+    fn camera_overrides_replace_only_what_is_given() {
+        let defaults = CameraSettings {
+            look_from: Vec3::new(0., 0., 10.),
+            look_at: Vec3::empty(),
+            vup: Vec3::new(0., 1., 0.),
+            vfov: 40.,
+            aperture: 0.5,
+            focus_dist: 10.,
+            time_0: 0.,
+            time_1: 1.,
+        };
+        let unchanged = parse(&[]).unwrap().camera_settings(defaults.clone());
+        assert!((unchanged.look_from - defaults.look_from).length() < 1e-6);
+        assert_eq!((unchanged.vfov, unchanged.aperture, unchanged.focus_dist), (40., 0.5, 10.));
+
+        let options = parse(&["--look-from", "1,1,1", "--vfov", "90", "--focus-dist", "3"]).unwrap();
+        let camera = options.camera_settings(defaults);
+        assert!((camera.look_from - Vec3::new(1., 1., 1.)).length() < 1e-6);
+        assert!(camera.look_at.length() < 1e-6);
+        assert_eq!((camera.vfov, camera.aperture, camera.focus_dist), (90., 0.5, 3.));
+        assert_eq!((camera.time_0, camera.time_1), (0., 1.));
+    }
+}
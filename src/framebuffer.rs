@@ -0,0 +1,62 @@
+use crate::vectors::Vec3;
+
+/// Linear radiance for every pixel of a rendered image, stored row-major from the top row.
+#[derive(Clone, Debug)]
+pub struct FrameBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+fn gamma_correct(value: f32) -> u8 {
+    (value.max(0.).sqrt() * 255.99).min(255.) as u8
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            pixels: vec![Vec3::empty(); width * height],
+        }
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Vec3>) -> FrameBuffer {
+        assert_eq!(pixels.len(), width * height, "pixel count does not match image size");
+        FrameBuffer {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Vec3) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Gamma-corrected (gamma 2) 8-bit RGB triplets, clamped to [0, 255].
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for color in &self.pixels {
+            data.push(gamma_correct(color.x));
+            data.push(gamma_correct(color.y));
+            data.push(gamma_correct(color.z));
+        }
+        data
+    }
+
+    /// Linear RGB triplets as rendered, without tone mapping or clamping.
+    pub fn to_rgb32f(&self) -> Vec<f32> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for color in &self.pixels {
+            data.push(color.x);
+            data.push(color.y);
+            data.push(color.z);
+        }
+        data
+    }
+}
@@ -0,0 +1,230 @@
+use crate::bboxes::AABB;
+use crate::hitables::{HitRecord, Hitable};
+use crate::onb::ONB;
+use crate::rays::Ray;
+use crate::textures::Texture;
+use crate::vectors::Vec3;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// World-space distance over which the height of a bump map is differentiated.
+const BUMP_STEP: f32 = 1e-3;
+
+/// An object whose shading normals follow the bumps of a height field: the first channel of
+/// `height` times `scale`, in world units along the normal.
+///
+/// Only the normal changes, so the bumps don't cast shadows or show on the silhouette.
+#[derive(Clone, Debug)]
+pub struct BumpMap {
+    hitable: Arc<dyn Hitable>,
+    height: Arc<dyn Texture>,
+    scale: f32,
+}
+
+impl BumpMap {
+    pub fn new(hitable: Arc<dyn Hitable>, height: Arc<dyn Texture>, scale: f32) -> BumpMap {
+        BumpMap { hitable, height, scale }
+    }
+
+    fn bumped_normal(&self, record: &HitRecord) -> Vec3 {
+        let (dpdu_length, dpdv_length) = (record.dpdu.length(), record.dpdv.length());
+        if dpdu_length == 0. || dpdv_length == 0. {
+            return record.normal;
+        }
+        let height = |u: f32, v: f32, p: Vec3| self.scale * self.height.surface_value(u, v, p, record.normal, 0.).x;
+        let (du, dv) = (BUMP_STEP / dpdu_length, BUMP_STEP / dpdv_length);
+        let here = height(record.u, record.v, record.p);
+        let along_u = height(record.u + du, record.v, record.p + record.dpdu * du);
+        let along_v = height(record.u, record.v + dv, record.p + record.dpdv * dv);
+        // The tangents of the displaced surface, ignoring how the normal itself turns.
+        let dpdu = record.dpdu + record.normal * ((along_u - here) / du);
+        let dpdv = record.dpdv + record.normal * ((along_v - here) / dv);
+        let normal = dpdu.cross(dpdv);
+        if normal.length() == 0. {
+            return record.normal;
+        }
+        let normal = normal.unit_vector();
+        if normal.dot(record.normal) < 0. {
+            -normal
+        } else {
+            normal
+        }
+    }
+}
+
+impl Hitable for BumpMap {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        self.hitable.hit(ray, t_min, t_max, rng).map(|record| HitRecord {
+            normal: self.bumped_normal(&record),
+            ..record
+        })
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.hitable.bounding_box(t0, t1)
+    }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        self.hitable.pdf_value(origin, direction, rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        self.hitable.random(origin, rng)
+    }
+}
+
+/// An object whose shading normals are read from a tangent-space normal map: red, green and blue
+/// from 0 to 1 stand for -1 to 1 along `dpdu`, along `dpdv` and out of the surface.
+#[derive(Clone, Debug)]
+pub struct NormalMap {
+    hitable: Arc<dyn Hitable>,
+    map: Arc<dyn Texture>,
+}
+
+impl NormalMap {
+    pub fn new(hitable: Arc<dyn Hitable>, map: Arc<dyn Texture>) -> NormalMap {
+        NormalMap { hitable, map }
+    }
+
+    fn mapped_normal(&self, record: &HitRecord) -> Vec3 {
+        let normal = record.normal;
+        let tangent = record.dpdu - normal * normal.dot(record.dpdu);
+        let tangent = if tangent.length() > 0. {
+            tangent.unit_vector()
+        } else {
+            ONB::from_w(normal).u
+        };
+        let mut bitangent = normal.cross(tangent);
+        if bitangent.dot(record.dpdv) < 0. {
+            bitangent = -bitangent;
+        }
+        let local = self.map.surface_value(record.u, record.v, record.p, normal, 0.) * 2. - Vec3::new(1., 1., 1.);
+        let mapped = tangent * local.x + bitangent * local.y + normal * local.z;
+        if mapped.length() == 0. {
+            return normal;
+        }
+        mapped.unit_vector()
+    }
+}
+
+impl Hitable for NormalMap {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        self.hitable.hit(ray, t_min, t_max, rng).map(|record| HitRecord {
+            normal: self.mapped_normal(&record),
+            ..record
+        })
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.hitable.bounding_box(t0, t1)
+    }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        self.hitable.pdf_value(origin, direction, rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        self.hitable.random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitables::bvh::BvhBuilder;
+    use crate::hitables::rectangles::XZRectangle;
+    use crate::hitables::spheres::Sphere;
+    use crate::hitables::transforms::Transform;
+    use crate::hitables::triangles::{MeshData, TriangleMesh};
+    use crate::matrices::Matrix4;
+    use crate::test_fixtures::{down, material};
+    use crate::textures::ConstantTexture;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    fn floor() -> Arc<dyn Hitable> {
+        Arc::new(XZRectangle::new(-1., 1., -1., 1., 0., material()))
+    }
+
+    /// A height growing along x, for a slope of a known angle.
+    #[derive(Debug)]
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, _u: f32, _v: f32, p: Vec3) -> Vec3 {
+            Vec3::new(p.x, p.x, p.x)
+        }
+    }
+
+    #[test]
+    fn hitables_report_tangents_matching_their_uvs() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mesh = MeshData {
+            positions: vec![Vec3::new(-1., 0., -1.), Vec3::new(1., 0., -1.), Vec3::new(0., 0., 1.)],
+            normals: None,
+            uvs: Some(vec![(0.2, 0.1), (0.9, 0.3), (0.4, 0.8)]),
+            indices: vec![[0, 1, 2]],
+            material: material(),
+        };
+        let sphere = Arc::new(Sphere::new(Vec3::new(0., -0.5, 0.), 1., material()));
+        let matrix = Matrix4::rotation_x(30.) * Matrix4::scaling(Vec3::new(1., 2., 0.5));
+        let objects: Vec<Arc<dyn Hitable>> = vec![
+            floor(),
+            sphere.clone(),
+            Arc::new(Transform::with_matrix(sphere, matrix)),
+            Arc::new(TriangleMesh::new(mesh, BvhBuilder::Sah, &mut rng)),
+        ];
+        for object in &objects {
+            let record = object.hit(&down(0.1, 0.2), 0.001, f32::MAX, &mut rng).unwrap();
+            let nearby = object.hit(&down(0.11, 0.19), 0.001, f32::MAX, &mut rng).unwrap();
+            assert!(record.dpdu.dot(record.normal).abs() < 1e-4 * record.dpdu.length(), "{:?}", object);
+            assert!(record.dpdv.dot(record.normal).abs() < 1e-4 * record.dpdv.length(), "{:?}", object);
+            // The first order change of the point with the texture coordinates.
+            let predicted = record.p + record.dpdu * (nearby.u - record.u) + record.dpdv * (nearby.v - record.v);
+            let step = (nearby.p - record.p).length();
+            assert!((predicted - nearby.p).length() < 0.05 * step, "{:?}: {:?} vs {:?}", object, predicted, nearby.p);
+        }
+    }
+
+    #[test]
+    fn bump_map_tilts_the_normal_along_the_slope() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let slope = 0.5;
+        let bumped = BumpMap::new(floor(), Arc::new(Ramp), slope);
+        let record = bumped.hit(&down(0.3, -0.4), 0.001, f32::MAX, &mut rng).unwrap();
+        let expected = Vec3::new(-slope, 1., 0.).unit_vector();
+        assert!((record.normal - expected).length() < 1e-3, "{:?}", record.normal);
+        assert!((record.p - Vec3::new(0.3, 0., -0.4)).length() < 1e-6);
+        let flat = BumpMap::new(floor(), Arc::new(ConstantTexture::new(Vec3::new(0.7, 0.7, 0.7))), 1.);
+        let record = flat.hit(&down(0.3, -0.4), 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.normal - Vec3::new(0., 1., 0.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn normal_map_reads_directions_in_the_tangent_frame() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let normal_for = |color: Vec3, rng: &mut XorShiftRng| {
+            let mapped = NormalMap::new(floor(), Arc::new(ConstantTexture::new(color)));
+            mapped.hit(&down(0.3, -0.4), 0.001, f32::MAX, rng).unwrap().normal
+        };
+        // The floor's u runs along x and its v along z.
+        let cases = [
+            (Vec3::new(0.5, 0.5, 1.), Vec3::new(0., 1., 0.)),
+            (Vec3::new(1., 0.5, 0.5), Vec3::new(1., 0., 0.)),
+            (Vec3::new(0.5, 1., 0.5), Vec3::new(0., 0., 1.)),
+            (Vec3::new(0.5, 0., 1.), Vec3::new(0., 1., -1.).unit_vector()),
+        ];
+        for &(color, expected) in &cases {
+            let normal = normal_for(color, &mut rng);
+            assert!((normal - expected).length() < 1e-5, "{:?} gave {:?}", color, normal);
+        }
+    }
+}
@@ -1,15 +1,17 @@
 use crate::bboxes::AABB;
+use crate::hitables::linear_bvh::LinearBVH;
 use crate::hitables::{HitRecord, Hitable};
 use crate::rays::Ray;
-use rand::Rng;
+use crate::vectors::Vec3;
+use rand::{Rng, RngCore};
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::sync::Arc;
 
 macro_rules! box_compare {
     ($axis:ident, $left:ident, $right:ident) => {
         match ($left.bounding_box(0., 0.), $right.bounding_box(0., 0.)) {
             (Some(box_left), Some(box_right)) => {
-                let difference = &box_left.min[$axis] - &box_right.min[$axis];
+                let difference = box_left.min[$axis] - box_right.min[$axis];
                 if difference < 0. {
                     Ordering::Less
                 } else if difference > 0. {
@@ -25,16 +27,16 @@ macro_rules! box_compare {
 
 #[derive(Clone, Debug)]
 pub struct BVHNode {
-    pub left: Rc<dyn Hitable>,
-    pub right: Rc<dyn Hitable>,
+    pub left: Arc<dyn Hitable>,
+    pub right: Arc<dyn Hitable>,
     pub bbox: AABB,
 }
 
 impl Hitable for BVHNode {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if self.bbox.hit(&ray, t_min, t_max) {
-            let hit_left = self.left.hit(&ray, t_min, t_max);
-            let hit_right = self.right.hit(&ray, t_min, t_max);
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        if self.bbox.hit(ray, t_min, t_max) {
+            let hit_left = self.left.hit(ray, t_min, t_max, rng);
+            let hit_right = self.right.hit(ray, t_min, t_max, rng);
             match (hit_left, hit_right) {
                 (Some(left_record), Some(right_record)) => {
                     if left_record.t < right_record.t {
@@ -58,11 +60,25 @@ impl Hitable for BVHNode {
 }
 
 impl BVHNode {
-    pub fn new(left: Rc<dyn Hitable>, right: Rc<dyn Hitable>, bbox: AABB) -> Self {
+    pub fn new(left: Arc<dyn Hitable>, right: Arc<dyn Hitable>, bbox: AABB) -> Self {
         BVHNode { left, right, bbox }
     }
+
+    fn from_children(left: Arc<dyn Hitable>, right: Arc<dyn Hitable>, time_0: f32, time_1: f32) -> Self {
+        match (
+            left.bounding_box(time_0, time_1),
+            right.bounding_box(time_0, time_1),
+        ) {
+            (Some(box_left), Some(box_right)) => {
+                BVHNode::new(left, right, AABB::surrounding_box(&box_left, &box_right))
+            }
+            _ => panic!("no bounding box in bvh node constructor"),
+        }
+    }
+
+    #[allow(clippy::self_named_constructors)]
     pub fn bvh_node<R: Rng>(
-        list: &mut Vec<Rc<dyn Hitable>>,
+        list: &mut Vec<Arc<dyn Hitable>>,
         time_0: f32,
         time_1: f32,
         rng: &mut R,
@@ -71,30 +87,327 @@ impl BVHNode {
         list.sort_by(|left, right| box_compare!(axis, left, right));
         let n = list.len();
         let (left, right) = match n {
-            1 => (Rc::clone(&list[0]), Rc::clone(&list[0])),
-            2 => (Rc::clone(&list[0]), Rc::clone(&list[1])),
+            1 => (Arc::clone(&list[0]), Arc::clone(&list[0])),
+            2 => (Arc::clone(&list[0]), Arc::clone(&list[1])),
             _ => {
-                let (l_left, l_right) = list.split_at(n / 2 as usize);
+                let (l_left, l_right) = list.split_at(n / 2);
                 (
-                    Rc::new(BVHNode::bvh_node(&mut l_left.to_vec(), time_0, time_1, rng))
-                        as Rc<dyn Hitable>,
-                    Rc::new(BVHNode::bvh_node(
+                    Arc::new(BVHNode::bvh_node(&mut l_left.to_vec(), time_0, time_1, rng))
+                        as Arc<dyn Hitable>,
+                    Arc::new(BVHNode::bvh_node(
                         &mut l_right.to_vec(),
                         time_0,
                         time_1,
                         rng,
-                    )) as Rc<dyn Hitable>,
+                    )) as Arc<dyn Hitable>,
                 )
             }
         };
-        match (
-            left.bounding_box(time_0, time_1),
-            right.bounding_box(time_0, time_1),
-        ) {
-            (Some(box_left), Some(box_right)) => {
-                BVHNode::new(left, right, AABB::surrounding_box(&box_left, &box_right))
+        BVHNode::from_children(left, right, time_0, time_1)
+    }
+
+    /// Builds the tree top-down with the binned surface area heuristic: each node is split
+    /// where the surface area times primitive count, summed over both halves, is smallest.
+    /// Unlike `bvh_node` this draws no random numbers, so the tree only depends on `list`.
+    pub fn sah_node(list: &mut [Arc<dyn Hitable>], time_0: f32, time_1: f32) -> Self {
+        let n = list.len();
+        let (left, right) = match n {
+            1 => (Arc::clone(&list[0]), Arc::clone(&list[0])),
+            2 => (Arc::clone(&list[0]), Arc::clone(&list[1])),
+            _ => {
+                let (mid, _) = sah_partition(list, time_0, time_1);
+                let (l_left, l_right) = list.split_at_mut(mid);
+                (
+                    Arc::new(BVHNode::sah_node(l_left, time_0, time_1)) as Arc<dyn Hitable>,
+                    Arc::new(BVHNode::sah_node(l_right, time_0, time_1)) as Arc<dyn Hitable>,
+                )
             }
-            _ => panic!("no bounding box in bvh node constructor"),
+        };
+        BVHNode::from_children(left, right, time_0, time_1)
+    }
+}
+
+const SAH_BINS: usize = 16;
+
+pub(crate) fn bounding_box(hitable: &Arc<dyn Hitable>, time_0: f32, time_1: f32) -> AABB {
+    hitable
+        .bounding_box(time_0, time_1)
+        .expect("no bounding box in bvh node constructor")
+}
+
+pub(crate) fn merge(bbox: Option<AABB>, other: &AABB) -> Option<AABB> {
+    Some(match bbox {
+        Some(bbox) => AABB::surrounding_box(&bbox, other),
+        None => other.clone(),
+    })
+}
+
+/// Reorders `list` so that `list[..mid]` and `list[mid..]` are the two halves of the cheapest
+/// binned split and returns `(mid, axis)`. Falls back to splitting in the middle when all the
+/// centroids coincide.
+fn sah_partition(list: &mut [Arc<dyn Hitable>], time_0: f32, time_1: f32) -> (usize, usize) {
+    let boxes: Vec<AABB> = list
+        .iter()
+        .map(|hitable| bounding_box(hitable, time_0, time_1))
+        .collect();
+    let centroids: Vec<Vec3> = boxes.iter().map(AABB::centroid).collect();
+    let mut centroid_box = AABB::new(centroids[0], centroids[0]);
+    for centroid in &centroids[1..] {
+        centroid_box = AABB::surrounding_box(&centroid_box, &AABB::new(*centroid, *centroid));
+    }
+    let extent = centroid_box.max - centroid_box.min;
+    let bin_of = |centroid: Vec3, axis: usize| {
+        let offset = (centroid[axis] - centroid_box.min[axis]) / extent[axis];
+        ((offset * SAH_BINS as f32) as usize).min(SAH_BINS - 1)
+    };
+
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if extent[axis] <= 0. {
+            continue;
+        }
+        let mut counts = [0usize; SAH_BINS];
+        let mut bin_boxes: Vec<Option<AABB>> = vec![None; SAH_BINS];
+        for (bbox, centroid) in boxes.iter().zip(&centroids) {
+            let bin = bin_of(*centroid, axis);
+            counts[bin] += 1;
+            bin_boxes[bin] = merge(bin_boxes[bin].take(), bbox);
+        }
+        // right_costs[split] is the cost of the bins `split..` going to the right child.
+        let mut right_costs = [0.; SAH_BINS];
+        let (mut right_box, mut right_count) = (None, 0);
+        for split in (1..SAH_BINS).rev() {
+            if let Some(bbox) = &bin_boxes[split] {
+                right_box = merge(right_box, bbox);
+            }
+            right_count += counts[split];
+            right_costs[split] = right_box.as_ref().map_or(0., AABB::surface_area) * right_count as f32;
+        }
+        let (mut left_box, mut left_count) = (None, 0);
+        for split in 1..SAH_BINS {
+            if let Some(bbox) = &bin_boxes[split - 1] {
+                left_box = merge(left_box, bbox);
+            }
+            left_count += counts[split - 1];
+            if left_count == 0 || left_count == list.len() {
+                continue;
+            }
+            let cost = left_box.as_ref().map_or(0., AABB::surface_area) * left_count as f32 + right_costs[split];
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    match best {
+        Some((_, axis, split)) => {
+            let (left, right): (Vec<_>, Vec<_>) = list
+                .iter()
+                .zip(&centroids)
+                .partition(|(_, centroid)| bin_of(**centroid, axis) < split);
+            let mid = left.len();
+            let reordered: Vec<Arc<dyn Hitable>> = left
+                .into_iter()
+                .chain(right)
+                .map(|(hitable, _)| Arc::clone(hitable))
+                .collect();
+            list.clone_from_slice(&reordered);
+            (mid, axis)
+        }
+        None => (list.len() / 2, 0),
+    }
+}
+
+/// How bounding volume hierarchies over a scene's objects are built.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BvhBuilder {
+    /// Sort along a random axis and split at the median (`BVHNode::bvh_node`).
+    RandomMedian,
+    /// Binned surface area heuristic (`BVHNode::sah_node`).
+    Sah,
+}
+
+impl BvhBuilder {
+    pub fn name(self) -> &'static str {
+        match self {
+            BvhBuilder::RandomMedian => "random",
+            BvhBuilder::Sah => "sah",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<BvhBuilder> {
+        [BvhBuilder::RandomMedian, BvhBuilder::Sah]
+            .iter()
+            .cloned()
+            .find(|builder| builder.name() == name)
+    }
+
+    /// Builds a flattened hierarchy over `list`, see `LinearBVH`.
+    pub fn build<R: Rng>(self, list: &mut [Arc<dyn Hitable>], time_0: f32, time_1: f32, rng: &mut R) -> LinearBVH {
+        LinearBVH::new(list, self, time_0, time_1, rng)
+    }
+
+    /// Reorders `list` (at least two objects) into the two children of a node, returning
+    /// `(mid, axis)`: the left child gets `list[..mid]`, and the split was made along `axis`.
+    pub(crate) fn partition<R: Rng>(
+        self,
+        list: &mut [Arc<dyn Hitable>],
+        time_0: f32,
+        time_1: f32,
+        rng: &mut R,
+    ) -> (usize, usize) {
+        match self {
+            BvhBuilder::RandomMedian => {
+                let axis = (3. * rng.gen::<f32>()) as usize;
+                list.sort_by(|left, right| box_compare!(axis, left, right));
+                (list.len() / 2, axis)
+            }
+            BvhBuilder::Sah => sah_partition(list, time_0, time_1),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitables::cuboids::Cuboid;
+    use crate::hitables::spheres::Sphere;
+    use crate::render::{self, RenderSettings};
+    use crate::scenes::Scene;
+    use crate::test_fixtures;
+    use crate::textures::MissingTextures;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    fn random_spheres(rng: &mut XorShiftRng, count: usize) -> Vec<Arc<dyn Hitable>> {
+        let material = test_fixtures::material();
+        (0..count)
+            .map(|_| {
+                let center = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 20.;
+                Arc::new(Sphere::new(center, 0.1 + rng.gen::<f32>(), Arc::clone(&material))) as Arc<dyn Hitable>
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut XorShiftRng) -> Ray {
+        let origin = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 30. - Vec3::new(5., 5., 5.);
+        let direction = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) - Vec3::new(0.5, 0.5, 0.5);
+        Ray::new(origin, direction, 0.)
+    }
+
+    fn closest_t(world: &dyn Hitable, ray: &Ray, rng: &mut XorShiftRng) -> Option<f32> {
+        world.hit(ray, 0.001, f32::MAX, rng).map(|record| record.t)
+    }
+
+    #[test]
+    fn sah_tree_finds_the_same_hits_as_the_median_split() {
+        let mut rng = XorShiftRng::seed_from_u64(3);
+        let mut spheres = random_spheres(&mut rng, 300);
+        let median = BVHNode::bvh_node(&mut spheres.clone(), 0., 1., &mut rng);
+        let sah = BVHNode::sah_node(&mut spheres, 0., 1.);
+        for _ in 0..2000 {
+            let ray = random_ray(&mut rng);
+            assert_eq!(closest_t(&median, &ray, &mut rng), closest_t(&sah, &ray, &mut rng));
+        }
+    }
+
+    #[test]
+    fn sah_tree_does_not_depend_on_the_generator() {
+        let mut rng = XorShiftRng::seed_from_u64(5);
+        let spheres = random_spheres(&mut rng, 100);
+        let first = BvhBuilder::Sah.build(&mut spheres.clone(), 0., 1., &mut XorShiftRng::seed_from_u64(1));
+        let second = BvhBuilder::Sah.build(&mut spheres.clone(), 0., 1., &mut XorShiftRng::seed_from_u64(2));
+        assert_eq!(format!("{:?}", first), format!("{:?}", second));
+    }
+
+    /// Counts the intersection tests that reach the wrapped primitive.
+    #[derive(Debug)]
+    struct Counted {
+        inner: Arc<dyn Hitable>,
+        tests: Arc<AtomicUsize>,
+    }
+
+    impl Hitable for Counted {
+        fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+            self.tests.fetch_add(1, Ordering::Relaxed);
+            self.inner.hit(ray, t_min, t_max, rng)
+        }
+
+        fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+            self.inner.bounding_box(t0, t1)
+        }
+    }
+
+    /// The bulk of `final_scene` as one flat list: the 20x20 floor of boxes and the cube of 1000 spheres.
+    fn final_scene_primitives(rng: &mut XorShiftRng, tests: &Arc<AtomicUsize>) -> Vec<Arc<dyn Hitable>> {
+        let material = test_fixtures::material();
+        let mut list: Vec<Arc<dyn Hitable>> = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let p_min = Vec3::new(-1000. + (i as f32) * 100., 0., -1000. + (j as f32) * 100.);
+                let p_max = p_min + Vec3::new(100., 100. * (rng.gen::<f32>() + 0.01), 100.);
+                list.push(Arc::new(Cuboid::new(p_min, p_max, &material)));
+            }
+        }
+        for _ in 0..1000 {
+            let center = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 165.
+                + Vec3::new(-100., 270., 395.);
+            list.push(Arc::new(Sphere::new(center, 10., Arc::clone(&material))));
+        }
+        list.into_iter()
+            .map(|inner| {
+                Arc::new(Counted {
+                    inner,
+                    tests: Arc::clone(tests),
+                }) as Arc<dyn Hitable>
+            })
+            .collect()
+    }
+
+    /// Compares the two builders: primitive intersection tests and time per camera ray on the
+    /// primitives of `final_scene`, and the time to render `final_scene` itself.
+    /// Run with `cargo test --release -- --ignored --nocapture bvh_builder_benchmark`.
+    #[test]
+    #[ignore]
+    fn bvh_builder_benchmark() {
+        let camera_settings = Scene::Final.default_camera();
+        let camera = camera_settings.build(1.);
+        for &builder in &[BvhBuilder::RandomMedian, BvhBuilder::Sah] {
+            let mut rng = XorShiftRng::seed_from_u64(0);
+            let tests = Arc::new(AtomicUsize::new(0));
+            let mut primitives = final_scene_primitives(&mut rng, &tests);
+            let world = builder.build(&mut primitives, 0., 1., &mut rng);
+            let rays = 200_000;
+            let start = Instant::now();
+            for _ in 0..rays {
+                let ray = camera.get_ray(rng.gen(), rng.gen(), &mut rng);
+                world.hit(&ray, 0.001, f32::MAX, &mut rng);
+            }
+            let trace_time = start.elapsed();
+            println!(
+                "{:>6}: {:.1} primitive tests/ray, {:.0} ns/ray",
+                builder.name(),
+                tests.load(Ordering::Relaxed) as f32 / rays as f32,
+                trace_time.as_nanos() as f32 / rays as f32,
+            );
+
+            let settings = RenderSettings {
+                width: 200,
+                height: 200,
+                samples: 16,
+                max_depth: 50,
+                external_light: false,
+                threads: 1,
+                bvh: builder,
+                spectral: false,
+            };
+            let start = Instant::now();
+            // The final scene's earth.png is not part of the repository, so a placeholder stands in for it.
+            render::render_scene(Scene::Final, &camera_settings, &settings, MissingTextures::Placeholder, 0).unwrap();
+            println!("{:>6}: final scene rendered in {:.2?}", builder.name(), start.elapsed());
         }
     }
 }
@@ -1,84 +1,90 @@
-use crate::hitables::{bvh::BVHNode, rectangles::*, transforms::*};
 use crate::bboxes::AABB;
 use crate::hitables::{HitRecord, Hitable};
 use crate::materials::Material;
 use crate::rays::Ray;
 use crate::vectors::Vec3;
-use rand::Rng;
-use std::rc::Rc;
+use rand::RngCore;
+use std::sync::Arc;
 
+/// An axis-aligned box from `p_min` to `p_max` whose six walls all have outward normals, so it can
+/// be hit from outside or from within.
+///
+/// Each wall is mapped like the rectangle in its plane: `u` and `v` run along the first and the
+/// second of the other two axes.
 #[derive(Clone, Debug)]
 pub struct Cuboid {
-    walls: BVHNode,
     p_min: Vec3,
     p_max: Vec3,
+    material: Arc<Material>,
 }
 
 impl Cuboid {
-    pub fn new<R: Rng>(p_min: Vec3, p_max: Vec3, material: &Rc<Material>, rng: &mut R) -> Cuboid {
-        let mut walls = Vec::with_capacity(6);
-        walls.push(Rc::new(XYRectangle::new(
-            p_min.x,
-            p_max.x,
-            p_min.y,
-            p_max.y,
-            p_max.z,
-            Rc::clone(material),
-        )) as Rc<dyn Hitable>);
-        let xy_rect = XYRectangle::new(
-            p_min.x,
-            p_max.x,
-            p_min.y,
-            p_max.y,
-            p_min.z,
-            Rc::clone(material),
-        );
-        walls.push(Rc::new(FlipNormals::new(Rc::new(xy_rect))) as Rc<dyn Hitable>);
-        walls.push(Rc::new(XZRectangle::new(
-            p_min.x,
-            p_max.x,
-            p_min.z,
-            p_max.z,
-            p_max.y,
-            Rc::clone(material),
-        )) as Rc<dyn Hitable>);
-        let xz_rect = XZRectangle::new(
-            p_min.x,
-            p_max.x,
-            p_min.z,
-            p_max.z,
-            p_min.y,
-            Rc::clone(material),
-        );
-        walls.push(Rc::new(FlipNormals::new(Rc::new(xz_rect))) as Rc<dyn Hitable>);
-        walls.push(Rc::new(YZRectangle::new(
-            p_min.y,
-            p_max.y,
-            p_min.z,
-            p_max.z,
-            p_max.x,
-            Rc::clone(material),
-        )) as Rc<dyn Hitable>);
-        let yz_rect = YZRectangle::new(
-            p_min.y,
-            p_max.y,
-            p_min.z,
-            p_max.z,
-            p_min.x,
-            Rc::clone(material),
-        );
-        walls.push(Rc::new(FlipNormals::new(Rc::new(yz_rect))) as Rc<dyn Hitable>);
+    pub fn new(p_min: Vec3, p_max: Vec3, material: &Arc<Material>) -> Cuboid {
         Cuboid {
-            walls: BVHNode::bvh_node(&mut walls, 0., 1., rng),
             p_min,
             p_max,
+            material: Arc::clone(material),
+        }
+    }
+
+    /// The hit at `t` on the wall across `axis`, on the `p_max` side if `far_wall`.
+    fn wall_hit(&self, ray: &Ray, t: f32, axis: usize, far_wall: bool) -> HitRecord {
+        let (a, b) = match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        let p = ray.point_at_parameter(t);
+        let size = self.p_max - self.p_min;
+        let mut normal = Vec3::empty();
+        normal[axis] = if far_wall { 1. } else { -1. };
+        let (mut dpdu, mut dpdv) = (Vec3::empty(), Vec3::empty());
+        dpdu[a] = size[a];
+        dpdv[b] = size[b];
+        HitRecord {
+            t,
+            u: (p[a] - self.p_min[a]) / size[a],
+            v: (p[b] - self.p_min[b]) / size[b],
+            p,
+            normal,
+            geometric_normal: normal,
+            front_face: ray.direction[axis] * normal[axis] < 0.,
+            dpdu,
+            dpdv,
+            material: Arc::clone(&self.material),
         }
     }
 }
 
 impl Hitable for Cuboid {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        self.walls.hit(ray, t_min, t_max)
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        // Where the ray enters and leaves the box, and the axes of the walls it crosses there.
+        let (mut near, mut far) = (-f32::MAX, f32::MAX);
+        let (mut near_axis, mut far_axis) = (0, 0);
+        for axis in 0..3 {
+            let t0 = (self.p_min[axis] - ray.origin[axis]) * ray.inv_direction[axis];
+            let t1 = (self.p_max[axis] - ray.origin[axis]) * ray.inv_direction[axis];
+            let (t0, t1) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
+            if t0 > near {
+                near = t0;
+                near_axis = axis;
+            }
+            if t1 < far {
+                far = t1;
+                far_axis = axis;
+            }
+        }
+        if near > far {
+            return None;
+        }
+        if near > t_min && near < t_max {
+            Some(self.wall_hit(ray, near, near_axis, ray.direction[near_axis] < 0.))
+        } else if far > t_min && far < t_max {
+            Some(self.wall_hit(ray, far, far_axis, ray.direction[far_axis] > 0.))
+        } else {
+            None
+        }
     }
 
     #[allow(unused_variables)]
@@ -86,3 +92,38 @@ impl Hitable for Cuboid {
         Some(AABB::new(self.p_min, self.p_max))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn walls_face_outwards_from_either_side() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let material = test_fixtures::material();
+        let cuboid = Cuboid::new(Vec3::new(-1., -2., -3.), Vec3::new(1., 2., 3.), &material);
+        for axis in 0..3 {
+            for &sign in &[-1., 1.] {
+                let mut outward = Vec3::empty();
+                outward[axis] = sign;
+                let from_outside = Ray::new(outward * 10. + Vec3::new(0.1, 0.2, 0.3), -outward, 0.);
+                let record = cuboid.hit(&from_outside, 0.001, f32::MAX, &mut rng).unwrap();
+                assert!((record.normal - outward).length() < 1e-6);
+                assert!((record.geometric_normal - outward).length() < 1e-6);
+                assert!(record.front_face);
+                assert!((record.p[axis] - sign * cuboid.p_max[axis]).abs() < 1e-4);
+                assert!(record.u > 0. && record.u < 1. && record.v > 0. && record.v < 1.);
+
+                let from_inside = Ray::new(Vec3::new(0.1, 0.2, 0.3), outward, 0.);
+                let record = cuboid.hit(&from_inside, 0.001, f32::MAX, &mut rng).unwrap();
+                assert!((record.normal - outward).length() < 1e-6);
+                assert!(!record.front_face);
+            }
+        }
+        let past = Ray::new(Vec3::new(5., 5., 0.), Vec3::new(0., 0., 1.), 0.);
+        assert!(cuboid.hit(&past, 0.001, f32::MAX, &mut rng).is_none());
+    }
+}
@@ -0,0 +1,88 @@
+use crate::bboxes::AABB;
+use crate::hitables::{HitRecord, Hitable};
+use crate::rays::Ray;
+use crate::textures::Texture;
+use rand::RngCore;
+use std::sync::Arc;
+
+/// An object with holes: hits where the first channel of `alpha` is below `threshold` are passed
+/// through, for leaves, fences and other shapes drawn on simple geometry.
+///
+/// Holes would make the object's light sampling wrong, so a cutout light is only found by the rays
+/// that hit it.
+#[derive(Clone, Debug)]
+pub struct AlphaCutout {
+    hitable: Arc<dyn Hitable>,
+    alpha: Arc<dyn Texture>,
+    threshold: f32,
+}
+
+impl AlphaCutout {
+    pub fn new(hitable: Arc<dyn Hitable>, alpha: Arc<dyn Texture>, threshold: f32) -> AlphaCutout {
+        AlphaCutout {
+            hitable,
+            alpha,
+            threshold,
+        }
+    }
+}
+
+impl Hitable for AlphaCutout {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let mut t_min = t_min;
+        loop {
+            let record = self.hitable.hit(ray, t_min, t_max, rng)?;
+            let alpha = self.alpha.surface_value(record.u, record.v, record.p, record.normal, 0.);
+            if alpha.x >= self.threshold {
+                return Some(record);
+            }
+            // Look again just past the hole, for the surfaces of the object behind it.
+            t_min = record.t + 1e-4 * record.t.abs().max(1.);
+        }
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.hitable.bounding_box(t0, t1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitables::rectangles::XYRectangle;
+    use crate::hitables::HitableList;
+    use crate::test_fixtures;
+    use crate::vectors::Vec3;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    /// Opaque on the left half of the front square, and everywhere on the back one.
+    #[derive(Debug)]
+    struct HalfOpaque;
+
+    impl Texture for HalfOpaque {
+        fn value(&self, u: f32, _v: f32, p: Vec3) -> Vec3 {
+            let alpha = if u < 0.5 || p.z < -0.5 { 1. } else { 0. };
+            Vec3::new(alpha, alpha, alpha)
+        }
+    }
+
+    #[test]
+    fn rays_pass_through_the_holes_to_the_surfaces_behind() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let material = test_fixtures::material();
+        let mut squares = HitableList::empty();
+        for &z in &[0., -1.] {
+            squares.push(XYRectangle::new(-1., 1., -1., 1., z, Arc::clone(&material)));
+        }
+        let cutout = AlphaCutout::new(Arc::new(squares), Arc::new(HalfOpaque), 0.5);
+        let hit_z = |x: f32, t_max: f32, rng: &mut XorShiftRng| {
+            let ray = Ray::new(Vec3::new(x, 0., 5.), Vec3::new(0., 0., -1.), 0.);
+            cutout.hit(&ray, 0.001, t_max, rng).map(|record| record.p.z)
+        };
+        assert_eq!(hit_z(-0.5, f32::MAX, &mut rng), Some(0.));
+        assert_eq!(hit_z(0.5, f32::MAX, &mut rng), Some(-1.));
+        // The hole doesn't let the ray reach beyond its end.
+        assert_eq!(hit_z(0.5, 5.5, &mut rng), None);
+    }
+}
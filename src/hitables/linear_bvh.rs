@@ -0,0 +1,183 @@
+use crate::bboxes::AABB;
+use crate::hitables::bvh::{bounding_box, BvhBuilder};
+use crate::hitables::{collect_lights, HitRecord, Hitable};
+use crate::rays::Ray;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
+
+/// Nodes holding this many objects or fewer become leaves.
+const MAX_LEAF_SIZE: usize = 2;
+/// Depth of the traversal stack. Nodes deeper than `FORCED_MEDIAN_DEPTH` are split in half
+/// whatever the builder, so no tree over fewer than 2^32 objects gets deeper than this.
+const MAX_DEPTH: usize = 64;
+const FORCED_MEDIAN_DEPTH: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+enum NodeKind {
+    /// `count` objects starting at `primitives[first]`.
+    Leaf { first: usize, count: usize },
+    /// The first child directly follows its parent in `nodes`; the second one is at `second_child`.
+    Interior { second_child: usize, axis: usize },
+}
+
+#[derive(Clone, Debug)]
+struct LinearNode {
+    bbox: AABB,
+    kind: NodeKind,
+}
+
+/// A bounding volume hierarchy stored as an array of nodes in depth-first order.
+///
+/// Traversal uses an explicit stack, visits the child nearer to the ray origin first and
+/// shrinks `t_max` to the closest hit so far, so far children are usually culled by their box.
+#[derive(Clone, Debug)]
+pub struct LinearBVH {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Arc<dyn Hitable>>,
+}
+
+impl LinearBVH {
+    pub fn new<R: Rng>(
+        list: &mut [Arc<dyn Hitable>],
+        builder: BvhBuilder,
+        time_0: f32,
+        time_1: f32,
+        rng: &mut R,
+    ) -> LinearBVH {
+        assert!(!list.is_empty(), "a bvh needs at least one object");
+        let mut nodes = Vec::with_capacity(2 * list.len());
+        build(&mut nodes, list, 0, 0, builder, time_0, time_1, rng);
+        LinearBVH {
+            nodes,
+            primitives: list.to_vec(),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build<R: Rng>(
+    nodes: &mut Vec<LinearNode>,
+    list: &mut [Arc<dyn Hitable>],
+    first: usize,
+    depth: usize,
+    builder: BvhBuilder,
+    time_0: f32,
+    time_1: f32,
+    rng: &mut R,
+) {
+    let bbox = list[1..].iter().fold(bounding_box(&list[0], time_0, time_1), |bbox, hitable| {
+        AABB::surrounding_box(&bbox, &bounding_box(hitable, time_0, time_1))
+    });
+    if list.len() <= MAX_LEAF_SIZE {
+        nodes.push(LinearNode {
+            bbox,
+            kind: NodeKind::Leaf {
+                first,
+                count: list.len(),
+            },
+        });
+        return;
+    }
+    let (mid, axis) = if depth < FORCED_MEDIAN_DEPTH {
+        builder.partition(list, time_0, time_1, rng)
+    } else {
+        (list.len() / 2, 0)
+    };
+    let index = nodes.len();
+    nodes.push(LinearNode {
+        bbox,
+        kind: NodeKind::Leaf { first, count: 0 },
+    });
+    let (left, right) = list.split_at_mut(mid);
+    build(nodes, left, first, depth + 1, builder, time_0, time_1, rng);
+    let second_child = nodes.len();
+    build(nodes, right, first + mid, depth + 1, builder, time_0, time_1, rng);
+    nodes[index].kind = NodeKind::Interior { second_child, axis };
+}
+
+impl Hitable for LinearBVH {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+        let mut closest = t_max;
+        let mut record = None;
+        loop {
+            let node = &self.nodes[index];
+            if node.bbox.hit(ray, t_min, closest) {
+                match node.kind {
+                    NodeKind::Leaf { first, count } => {
+                        for primitive in &self.primitives[first..first + count] {
+                            if let Some(hit) = primitive.hit(ray, t_min, closest, rng) {
+                                closest = hit.t;
+                                record = Some(hit);
+                            }
+                        }
+                    }
+                    NodeKind::Interior { second_child, axis } => {
+                        let (near, far) = if ray.direction[axis] < 0. {
+                            (second_child, index + 1)
+                        } else {
+                            (index + 1, second_child)
+                        };
+                        stack[stack_len] = far;
+                        stack_len += 1;
+                        index = near;
+                        continue;
+                    }
+                }
+            }
+            if stack_len == 0 {
+                return record;
+            }
+            stack_len -= 1;
+            index = stack[stack_len];
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        Some(self.nodes[0].bbox.clone())
+    }
+
+    fn lights(&self) -> Vec<Arc<dyn Hitable>> {
+        collect_lights(&self.primitives).objects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitables::bvh::BVHNode;
+    use crate::hitables::spheres::Sphere;
+    use crate::test_fixtures;
+    use crate::vectors::Vec3;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn linear_bvh_finds_the_same_hits_as_the_recursive_one() {
+        let mut rng = XorShiftRng::seed_from_u64(11);
+        let material = test_fixtures::material();
+        let mut spheres: Vec<Arc<dyn Hitable>> = (0..500)
+            .map(|_| {
+                let center = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 20.;
+                Arc::new(Sphere::new(center, 0.1 + rng.gen::<f32>(), Arc::clone(&material))) as Arc<dyn Hitable>
+            })
+            .collect();
+        let recursive = BVHNode::sah_node(&mut spheres.clone(), 0., 1.);
+        let flat: Vec<LinearBVH> = [BvhBuilder::RandomMedian, BvhBuilder::Sah]
+            .iter()
+            .map(|builder| builder.build(&mut spheres, 0., 1., &mut rng))
+            .collect();
+        for _ in 0..2000 {
+            let origin = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 30. - Vec3::new(5., 5., 5.);
+            let direction = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) - Vec3::new(0.5, 0.5, 0.5);
+            let ray = Ray::new(origin, direction, 0.);
+            let expected = recursive.hit(&ray, 0.001, f32::MAX, &mut rng).map(|record| record.t);
+            for bvh in &flat {
+                assert_eq!(expected, bvh.hit(&ray, 0.001, f32::MAX, &mut rng).map(|record| record.t));
+            }
+        }
+    }
+}
@@ -4,31 +4,30 @@ use crate::materials::Material;
 use crate::rays::Ray;
 use crate::textures::Texture;
 use crate::vectors::Vec3;
-use rand;
-use std::f32::MAX;
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub struct ConstantMedium {
     pub density: f32,
-    pub boundary: Rc<dyn Hitable>,
-    pub phase_function: Rc<Material>,
+    pub boundary: Arc<dyn Hitable>,
+    pub phase_function: Arc<Material>,
 }
 
 impl ConstantMedium {
-    pub fn new(boundary: Rc<dyn Hitable>, density: f32, albedo: Rc<dyn Texture>) -> Self {
+    pub fn new(boundary: Arc<dyn Hitable>, density: f32, albedo: Arc<dyn Texture>) -> Self {
         ConstantMedium {
             density,
             boundary,
-            phase_function: Rc::new(Material::isotropic(albedo)),
+            phase_function: Arc::new(Material::isotropic(albedo)),
         }
     }
 }
 
 impl Hitable for ConstantMedium {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        match self.boundary.hit(ray, -MAX, MAX) {
-            Some(record_1) => match self.boundary.hit(ray, record_1.t + 0.0001, MAX) {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        match self.boundary.hit(ray, -f32::MAX, f32::MAX, rng) {
+            Some(record_1) => match self.boundary.hit(ray, record_1.t + 0.0001, f32::MAX, rng) {
                 Some(record_2) => {
                     let mut record_1_t = record_1.t;
                     let mut record_2_t = record_2.t;
@@ -46,7 +45,7 @@ impl Hitable for ConstantMedium {
                     }
                     let distance_inside_boundary =
                         ray.direction.length() * (record_2_t - record_1_t);
-                    let hit_distance = -(1. / self.density) * rand::random::<f32>().ln();
+                    let hit_distance = -(1. / self.density) * rng.gen::<f32>().ln();
                     if hit_distance < distance_inside_boundary {
                         let t = record_1_t + hit_distance / ray.direction.length();
                         let p = ray.point_at_parameter(t);
@@ -56,7 +55,11 @@ impl Hitable for ConstantMedium {
                             v: 0.,
                             p,
                             normal: Vec3::new(1., 0., 0.),
-                            material: Rc::clone(&self.phase_function),
+                            geometric_normal: Vec3::new(1., 0., 0.),
+                            front_face: true,
+                            dpdu: Vec3::new(0., 1., 0.),
+                            dpdv: Vec3::new(0., 0., 1.),
+                            material: Arc::clone(&self.phase_function),
                         })
                     } else {
                         None
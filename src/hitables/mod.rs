@@ -1,46 +1,130 @@
+pub mod bump_maps;
 pub mod bvh;
 pub mod cuboids;
+pub mod cutouts;
+pub mod linear_bvh;
 pub mod mediums;
 pub mod rectangles;
 pub mod spheres;
 pub mod transforms;
+pub mod triangles;
 
 use crate::bboxes::AABB;
-use crate::materials::Material;
+use crate::materials::{Material, ScatterRecord};
 use crate::rays::Ray;
+use crate::spectrum::color_at;
 use crate::vectors::Vec3;
-use std::f32::MAX;
-use rand::Rng;
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
 use std::fmt::Debug;
 
-const MAX_DEPTH: i32 = 60;
-
-pub fn color_world<R: Rng, H: Hitable>(ray: &Ray, world: &H, depth: i32, external_light: bool, rng: &mut R) -> Vec3 {
-    match world.hit(ray, 0.001, MAX) {
-        Some(record) => {
-            let emitted = record.material.emitted(record.u, record.v, record.p);
-            match record.material.scatter(ray, &record, rng) {
-                Some((attenuation, scattered)) => {
-                    if depth < MAX_DEPTH {
-                        emitted + color_world(&scattered, world, depth + 1, external_light, rng) * attenuation
-                    } else {
-                        emitted
-                    }
+/// The balance between two sampling strategies that could both have produced a direction,
+/// with `pdf` the density of the one that did.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    pdf * pdf / (pdf * pdf + other_pdf * other_pdf)
+}
+
+/// Light arriving at `record.p` from a direction sampled towards `lights` and leaving along `ray`,
+/// weighted for combination with the material's own sample.
+fn sample_lights<H: Hitable>(ray: &Ray, record: &HitRecord, world: &H, lights: &HitableList, rng: &mut dyn RngCore) -> Vec3 {
+    let direction = lights.random(record.p, rng);
+    let light_pdf = lights.pdf_value(record.p, direction, rng);
+    let shadow_ray = Ray::new(record.p, direction, ray.time);
+    let scattering_pdf = record.material.scattering_pdf(ray, record, direction);
+    if light_pdf <= 0. || scattering_pdf <= 0. {
+        return Vec3::empty();
+    }
+    let light = match lights.hit(&shadow_ray, 0.001, f32::MAX, rng) {
+        Some(light) => light,
+        None => return Vec3::empty(),
+    };
+    if world.hit(&shadow_ray, 0.001, light.t * (1. - 1e-4), rng).is_some() {
+        return Vec3::empty();
+    }
+    let emitted = color_at(ray.wavelengths, light.material.emitted(&light));
+    emitted * record.material.eval(ray, record, direction) * (power_heuristic(light_pdf, scattering_pdf) / light_pdf)
+}
+
+/// Whether the emitter `record` found along `ray` is the nearest of `lights` there, so that sampling
+/// them could have picked the same point.
+fn is_sampled_light(ray: &Ray, record: &HitRecord, lights: &HitableList, rng: &mut dyn RngCore) -> bool {
+    match lights.hit(ray, 0.001, f32::MAX, rng) {
+        Some(light) => (light.t - record.t).abs() <= 1e-4 * record.t.max(1.),
+        None => false,
+    }
+}
+
+/// Traces a path from `ray`. At every diffuse bounce `lights` are sampled directly and combined with
+/// the material's scattered ray by multiple importance sampling; with no lights this is plain path tracing.
+pub fn color_world<R: Rng, H: Hitable>(
+    ray: &Ray,
+    world: &H,
+    lights: &HitableList,
+    max_depth: i32,
+    external_light: bool,
+    rng: &mut R,
+) -> Vec3 {
+    let mut color = Vec3::empty();
+    let mut throughput = Vec3::new(1., 1., 1.);
+    let mut ray = *ray;
+    // Density with which the last bounce sampled `ray`, if it could also have been sampled towards a light.
+    let mut scattering_pdf = None;
+    for depth in 0.. {
+        let record = match world.hit(&ray, 0.001, f32::MAX, rng) {
+            Some(record) => record,
+            None => {
+                if external_light {
+                    let unit_direction = ray.direction.unit_vector();
+                    let t = 0.5 * (unit_direction.y + 1.);
+                    let sky = Vec3::new(1., 1., 1.) * (1. - t) + Vec3::new(0.5, 0.7, 1.) * t;
+                    color += throughput * color_at(ray.wavelengths, sky);
                 }
-                None => emitted,
+                break;
             }
+        };
+        if record.material.emits() {
+            let weight = match scattering_pdf {
+                Some(pdf) if is_sampled_light(&ray, &record, lights, rng) => {
+                    power_heuristic(pdf, lights.pdf_value(ray.origin, ray.direction, rng))
+                }
+                _ => 1.,
+            };
+            let emitted = record.material.emitted(&record);
+            color += throughput * color_at(ray.wavelengths, emitted) * weight;
+        }
+        if depth >= max_depth {
+            break;
         }
-        None => {
-            if external_light {
-                let unit_direction = ray.direction.unit_vector();
-                let t = 0.5 * (unit_direction.y + 1.);
-                Vec3::new(1., 1., 1.) * (1. - t) + Vec3::new(0.5, 0.7, 1.) * t
-            } else {
-                Vec3::empty()
+        match record.material.scatter(&ray, &record, rng) {
+            Some(ScatterRecord::Specular { ray: scattered, attenuation }) => {
+                throughput *= attenuation;
+                scattering_pdf = None;
+                ray = scattered;
             }
+            Some(ScatterRecord::Diffuse { ray: scattered, pdf, eval }) => {
+                if !lights.objects.is_empty() {
+                    color += throughput * sample_lights(&ray, &record, world, lights, rng);
+                }
+                throughput *= eval / pdf;
+                scattering_pdf = Some(pdf);
+                ray = scattered;
+            }
+            None => break,
+        }
+    }
+    color
+}
+
+/// The objects in `objects`, and nested in them, that are lights.
+pub fn collect_lights(objects: &[Arc<dyn Hitable>]) -> HitableList {
+    let mut lights = HitableList::empty();
+    for object in objects {
+        if object.is_light() {
+            lights.objects.push(Arc::clone(object));
         }
+        lights.objects.extend(object.lights());
     }
+    lights
 }
 
 #[derive(Clone, Debug)]
@@ -49,22 +133,65 @@ pub struct HitRecord {
     pub u: f32,
     pub v: f32,
     pub p: Vec3,
+    /// The outward shading normal, pointing away from the inside of a closed surface. Bump and normal
+    /// maps and interpolated mesh normals bend it away from `geometric_normal`.
     pub normal: Vec3,
-    pub material: Rc<Material>,
+    /// The outward normal of the surface itself.
+    pub geometric_normal: Vec3,
+    /// Whether the ray hit the side `geometric_normal` points to, rather than coming from inside.
+    pub front_face: bool,
+    /// Derivatives of `p` along `u` and `v`: tangents of the surface, for bump and normal mapping.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub material: Arc<Material>,
+}
+
+impl HitRecord {
+    /// The shading normal turned towards the side the ray came from, for surfaces that look the same
+    /// from both sides.
+    pub fn facing_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
+        }
+    }
 }
 
-pub trait Hitable: Debug {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
+pub trait Hitable: Debug + Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
+
+    /// Whether the object emits light and can be sampled with `pdf_value` and `random`.
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    /// Solid-angle density with which `random` picks `direction` from `origin`.
+    #[allow(unused_variables)]
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        0.
+    }
+
+    /// A direction from `origin` towards a random point of the object.
+    #[allow(unused_variables)]
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        Vec3::new(1., 0., 0.)
+    }
+
+    /// Lights nested inside the object, for aggregates such as BVHs.
+    fn lights(&self) -> Vec<Arc<dyn Hitable>> {
+        Vec::new()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct HitableList {
-    pub objects: Vec<Rc<dyn Hitable>>,
+    pub objects: Vec<Arc<dyn Hitable>>,
 }
 
 impl HitableList {
-    pub fn new(objects: Vec<Rc<dyn Hitable>>) -> HitableList {
+    pub fn new(objects: Vec<Arc<dyn Hitable>>) -> HitableList {
         HitableList { objects }
     }
 
@@ -81,16 +208,16 @@ impl HitableList {
     }
 
     pub fn push<H: 'static + Hitable>(&mut self, object: H) {
-        self.objects.push(Rc::new(object));
+        self.objects.push(Arc::new(object));
     }
 }
 
 impl Hitable for HitableList {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let mut closest_so_far = t_max;
         let mut temp_record: Option<HitRecord> = None;
         for object in &self.objects {
-            if let Some(record) = object.hit(ray, t_min, closest_so_far) {
+            if let Some(record) = object.hit(ray, t_min, closest_so_far, rng) {
                     closest_so_far = record.t;
                     temp_record = Some(record);
             }
@@ -117,4 +244,26 @@ impl Hitable for HitableList {
             None => None,
         }
     }
+
+    /// The average density of the objects, for sampling a direction towards any of them.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        let sum: f32 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction, rng))
+            .sum();
+        sum / self.objects.len() as f32
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let index = ((rng.gen::<f32>() * self.objects.len() as f32) as usize).min(self.objects.len() - 1);
+        self.objects[index].random(origin, rng)
+    }
+
+    fn lights(&self) -> Vec<Arc<dyn Hitable>> {
+        collect_lights(&self.objects).objects
+    }
 }
\ No newline at end of file
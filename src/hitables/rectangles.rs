@@ -3,7 +3,27 @@ use crate::hitables::{HitRecord, Hitable};
 use crate::materials::Material;
 use crate::rays::Ray;
 use crate::vectors::Vec3;
-use std::rc::Rc;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
+
+/// Solid-angle density of sampling `direction` from `origin` by picking a uniform point on
+/// `rectangle`, whose area is `area`.
+fn pdf_value(
+    rectangle: &dyn Hitable,
+    area: f32,
+    origin: Vec3,
+    direction: Vec3,
+    rng: &mut dyn RngCore,
+) -> f32 {
+    match rectangle.hit(&Ray::new(origin, direction, 0.), 0.001, f32::MAX, rng) {
+        Some(record) => {
+            let distance_squared = record.t * record.t * direction.dot(direction);
+            let cosine = (direction.dot(record.normal) / direction.length()).abs();
+            distance_squared / (cosine * area)
+        }
+        None => 0.,
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct XYRectangle {
@@ -12,10 +32,10 @@ pub struct XYRectangle {
     pub y0: f32,
     pub y1: f32,
     pub k: f32,
-    pub material: Rc<Material>,
+    pub material: Arc<Material>,
 }
 impl XYRectangle {
-    pub fn new(x0: f32, x1: f32, y0: f32, y1: f32, k: f32, material: Rc<Material>) -> XYRectangle {
+    pub fn new(x0: f32, x1: f32, y0: f32, y1: f32, k: f32, material: Arc<Material>) -> XYRectangle {
         XYRectangle {
             x0,
             x1,
@@ -27,7 +47,8 @@ impl XYRectangle {
     }
 }
 impl Hitable for XYRectangle {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let t = (self.k - ray.origin.z) / ray.direction.z;
         if t < t_min || t > t_max {
             return None;
@@ -43,7 +64,11 @@ impl Hitable for XYRectangle {
             v: (y - self.y0) / (self.y1 - self.y0),
             p: ray.point_at_parameter(t),
             normal: Vec3::new(0., 0., 1.),
-            material: Rc::clone(&self.material),
+            geometric_normal: Vec3::new(0., 0., 1.),
+            front_face: ray.direction.z < 0.,
+            dpdu: Vec3::new(self.x1 - self.x0, 0., 0.),
+            dpdv: Vec3::new(0., self.y1 - self.y0, 0.),
+            material: Arc::clone(&self.material),
         })
     }
 
@@ -54,6 +79,24 @@ impl Hitable for XYRectangle {
             Vec3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.emits()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        pdf_value(self, area, origin, direction, rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let point = Vec3::new(
+            self.x0 + rng.gen::<f32>() * (self.x1 - self.x0),
+            self.y0 + rng.gen::<f32>() * (self.y1 - self.y0),
+            self.k,
+        );
+        point - origin
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -63,10 +106,10 @@ pub struct XZRectangle {
     pub z0: f32,
     pub z1: f32,
     pub k: f32,
-    pub material: Rc<Material>,
+    pub material: Arc<Material>,
 }
 impl XZRectangle {
-    pub fn new(x0: f32, x1: f32, z0: f32, z1: f32, k: f32, material: Rc<Material>) -> XZRectangle {
+    pub fn new(x0: f32, x1: f32, z0: f32, z1: f32, k: f32, material: Arc<Material>) -> XZRectangle {
         XZRectangle {
             x0,
             x1,
@@ -78,7 +121,8 @@ impl XZRectangle {
     }
 }
 impl Hitable for XZRectangle {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let t = (self.k - ray.origin.y) / ray.direction.y;
         if t < t_min || t > t_max {
             return None;
@@ -94,7 +138,11 @@ impl Hitable for XZRectangle {
             v: (z - self.z0) / (self.z1 - self.z0),
             p: ray.point_at_parameter(t),
             normal: Vec3::new(0., 1., 0.),
-            material: Rc::clone(&self.material),
+            geometric_normal: Vec3::new(0., 1., 0.),
+            front_face: ray.direction.y < 0.,
+            dpdu: Vec3::new(self.x1 - self.x0, 0., 0.),
+            dpdv: Vec3::new(0., 0., self.z1 - self.z0),
+            material: Arc::clone(&self.material),
         })
     }
 
@@ -105,6 +153,24 @@ impl Hitable for XZRectangle {
             Vec3::new(self.x1, self.k + 0.0001, self.z1),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.emits()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        pdf_value(self, area, origin, direction, rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let point = Vec3::new(
+            self.x0 + rng.gen::<f32>() * (self.x1 - self.x0),
+            self.k,
+            self.z0 + rng.gen::<f32>() * (self.z1 - self.z0),
+        );
+        point - origin
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -114,10 +180,10 @@ pub struct YZRectangle {
     pub z0: f32,
     pub z1: f32,
     pub k: f32,
-    pub material: Rc<Material>,
+    pub material: Arc<Material>,
 }
 impl YZRectangle {
-    pub fn new(y0: f32, y1: f32, z0: f32, z1: f32, k: f32, material: Rc<Material>) -> YZRectangle {
+    pub fn new(y0: f32, y1: f32, z0: f32, z1: f32, k: f32, material: Arc<Material>) -> YZRectangle {
         YZRectangle {
             y0,
             y1,
@@ -129,7 +195,8 @@ impl YZRectangle {
     }
 }
 impl Hitable for YZRectangle {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let t = (self.k - ray.origin.x) / ray.direction.x;
         if t < t_min || t > t_max {
             return None;
@@ -145,7 +212,11 @@ impl Hitable for YZRectangle {
             v: (z - self.z0) / (self.z1 - self.z0),
             p: ray.point_at_parameter(t),
             normal: Vec3::new(1., 0., 0.),
-            material: Rc::clone(&self.material),
+            geometric_normal: Vec3::new(1., 0., 0.),
+            front_face: ray.direction.x < 0.,
+            dpdu: Vec3::new(0., self.y1 - self.y0, 0.),
+            dpdv: Vec3::new(0., 0., self.z1 - self.z0),
+            material: Arc::clone(&self.material),
         })
     }
 
@@ -156,4 +227,22 @@ impl Hitable for YZRectangle {
             Vec3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.emits()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        pdf_value(self, area, origin, direction, rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let point = Vec3::new(
+            self.k,
+            self.y0 + rng.gen::<f32>() * (self.y1 - self.y0),
+            self.z0 + rng.gen::<f32>() * (self.z1 - self.z0),
+        );
+        point - origin
+    }
 }
@@ -1,10 +1,12 @@
 use crate::bboxes::AABB;
 use crate::hitables::{HitRecord, Hitable};
 use crate::materials::Material;
+use crate::onb::ONB;
 use crate::rays::Ray;
 use crate::vectors::Vec3;
+use rand::{Rng, RngCore};
 use std::f32::consts::PI;
-use std::rc::Rc;
+use std::sync::Arc;
 
 fn get_sphere_uv(p: &Vec3) -> (f32, f32) {
     let phi = p.z.atan2(p.x);
@@ -12,15 +14,85 @@ fn get_sphere_uv(p: &Vec3) -> (f32, f32) {
     (1. - (phi + PI) / (2. * PI), (theta + PI / 2.) / PI)
 }
 
+/// The derivatives of a point along `u` and `v` of `get_sphere_uv`, for the point `p` of the unit
+/// sphere scaled by `radius`.
+fn get_sphere_tangents(p: &Vec3, radius: f32) -> (Vec3, Vec3) {
+    // Clamped so that the poles, where u is undefined, still get a usable frame.
+    let cos_theta = (p.x * p.x + p.z * p.z).sqrt().max(1e-6);
+    let dpdu = Vec3::new(p.z, 0., -p.x) * (2. * PI * radius);
+    let dpdv = Vec3::new(-p.y * p.x / cos_theta, cos_theta, -p.y * p.z / cos_theta) * (PI * radius);
+    (dpdu, dpdv)
+}
+
+/// The parameters at which `ray` crosses the sphere of `radius` about `center`, nearest first.
+///
+/// The discriminant comes from the distance between the center and the ray's line rather than
+/// from `|origin - center|²`, which loses the radius entirely for small spheres seen from far
+/// away, and the roots come from the form that never subtracts nearly equal numbers.
+fn sphere_crossings(center: Vec3, radius: f32, ray: &Ray) -> Option<(f32, f32)> {
+    let oc = ray.origin - center;
+    let a = ray.direction.dot(ray.direction);
+    let half_b = oc.dot(ray.direction);
+    let c = oc.dot(oc) - radius * radius;
+    let closest = oc - ray.direction * (half_b / a);
+    let discriminant = a * (radius * radius - closest.dot(closest));
+    if discriminant < 0. {
+        return None;
+    }
+    let q = -(half_b + half_b.signum() * discriminant.sqrt());
+    if q == 0. {
+        // Grazing the sphere from a point on its surface.
+        return Some((0., 0.));
+    }
+    let (t0, t1) = (q / a, c / q);
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// The first crossing of `ray` with the sphere between `t_min` and `t_max`: where the ray enters,
+/// or where it leaves if it starts inside.
+fn hit_sphere(
+    center: Vec3,
+    radius: f32,
+    material: &Arc<Material>,
+    ray: &Ray,
+    t_min: f32,
+    t_max: f32,
+) -> Option<HitRecord> {
+    let (near, far) = sphere_crossings(center, radius, ray)?;
+    let t = if near > t_min && near < t_max {
+        near
+    } else if far > t_min && far < t_max {
+        far
+    } else {
+        return None;
+    };
+    let p = ray.point_at_parameter(t);
+    let normal = (p - center) / radius;
+    let (u, v) = get_sphere_uv(&normal);
+    let (dpdu, dpdv) = get_sphere_tangents(&normal, radius);
+    Some(HitRecord {
+        t,
+        u,
+        v,
+        p,
+        normal,
+        geometric_normal: normal,
+        front_face: ray.direction.dot(normal) < 0.,
+        dpdu,
+        dpdv,
+        material: Arc::clone(material),
+    })
+}
+
 #[derive(Clone, Debug)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
-    pub material: Rc<Material>,
+    pub material: Arc<Material>,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f32, material: Rc<Material>) -> Sphere {
+    pub fn new(center: Vec3, radius: f32, material: Arc<Material>) -> Sphere {
         Sphere {
             center,
             radius,
@@ -30,26 +102,9 @@ impl Sphere {
 }
 
 impl Hitable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
-        let (a, b, c) = (ray.direction.dot(ray.direction), oc.dot(ray.direction), oc.dot(oc) - self.radius * self.radius);
-        let discriminant = b * b - a * c;
-        if discriminant > 0. {
-            let temp = (-b - discriminant.sqrt()) / a;
-            let p = ray.point_at_parameter(temp);
-            let (u, v) = get_sphere_uv(&((p - self.center) / self.radius));
-            if temp < t_max && temp > t_min {
-                return Some(HitRecord {
-                    t: temp,
-                    u,
-                    v,
-                    p,
-                    normal: (ray.point_at_parameter(temp) - self.center) / self.radius,
-                    material: Rc::clone(&self.material),
-                });
-            }
-        }
-        None
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        hit_sphere(self.center, self.radius, &self.material, ray, t_min, t_max)
     }
 
     #[allow(unused_variables)]
@@ -59,6 +114,40 @@ impl Hitable for Sphere {
             self.center + Vec3::new(self.radius, self.radius, self.radius),
         ))
     }
+
+    fn is_light(&self) -> bool {
+        self.material.emits()
+    }
+
+    /// Uniform over the cone of directions from `origin` that hit the sphere.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        let to_center = self.center - origin;
+        let distance_squared = to_center.dot(to_center);
+        if distance_squared <= self.radius * self.radius {
+            return 0.;
+        }
+        match self.hit(&Ray::new(origin, direction, 0.), 0.001, f32::MAX, rng) {
+            Some(_) => {
+                let cos_theta_max = (1. - self.radius * self.radius / distance_squared).sqrt();
+                1. / (2. * PI * (1. - cos_theta_max))
+            }
+            None => 0.,
+        }
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        let to_center = self.center - origin;
+        let distance_squared = to_center.dot(to_center);
+        if distance_squared <= self.radius * self.radius {
+            return to_center;
+        }
+        let (r1, r2) = (rng.gen::<f32>(), rng.gen::<f32>());
+        let cos_theta_max = (1. - self.radius * self.radius / distance_squared).sqrt();
+        let z = 1. + r2 * (cos_theta_max - 1.);
+        let phi = 2. * PI * r1;
+        let sin_theta = (1. - z * z).sqrt();
+        ONB::from_w(to_center).local(Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -68,7 +157,7 @@ pub struct MovingSphere {
     pub time_0: f32,
     pub time_1: f32,
     pub radius: f32,
-    pub material: Rc<Material>,
+    pub material: Arc<Material>,
 }
 
 impl MovingSphere {
@@ -78,7 +167,7 @@ impl MovingSphere {
         time_0: f32,
         time_1: f32,
         radius: f32,
-        material: Rc<Material>,
+        material: Arc<Material>,
     ) -> MovingSphere {
         MovingSphere {
             center_0,
@@ -97,26 +186,9 @@ impl MovingSphere {
 }
 
 impl Hitable for MovingSphere {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let oc = ray.origin - self.center(ray.time);
-        let (a, b, c) = (ray.direction.dot(ray.direction), oc.dot(ray.direction), oc.dot(oc) - self.radius * self.radius);
-        let discriminant = b * b - a * c;
-        if discriminant > 0. {
-            let temp = (-b - discriminant.sqrt()) / a;
-            let p = ray.point_at_parameter(temp);
-            let (u, v) = get_sphere_uv(&((p - self.center(ray.time)) / self.radius));
-            if temp < t_max && temp > t_min {
-                return Some(HitRecord {
-                    t: temp,
-                    u,
-                    v,
-                    p,
-                    normal: (ray.point_at_parameter(temp) - self.center(ray.time)) / self.radius,
-                    material: Rc::clone(&self.material),
-                });
-            }
-        }
-        None
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        hit_sphere(self.center(ray.time), self.radius, &self.material, ray, t_min, t_max)
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
@@ -132,3 +204,90 @@ impl Hitable for MovingSphere {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitables::mediums::ConstantMedium;
+    use crate::test_fixtures;
+    use crate::textures::ConstantTexture;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    fn unit_sphere() -> Sphere {
+        Sphere::new(Vec3::empty(), 1., test_fixtures::material())
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn rays_from_outside_hit_the_near_side() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let sphere = unit_sphere();
+        let ray = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., -2.), 0.);
+        let record = sphere.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 2.).abs() < 1e-6);
+        assert!(close(record.normal, Vec3::new(0., 0., 1.)));
+        assert!(record.front_face);
+        // Past the near side, the same ray finds the far one from inside.
+        let record = sphere.hit(&ray, 2.5, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 3.).abs() < 1e-6);
+        assert!(!record.front_face);
+        let away = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0., 1.), 0.);
+        assert!(sphere.hit(&away, 0.001, f32::MAX, &mut rng).is_none());
+    }
+
+    #[test]
+    fn rays_from_inside_hit_the_far_side() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let sphere = unit_sphere();
+        let ray = Ray::new(Vec3::new(0., 0.5, 0.), Vec3::new(0., 1., 0.), 0.);
+        let record = sphere.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 0.5).abs() < 1e-6);
+        // The normal still points out, and the flag says the ray came from inside.
+        assert!(close(record.normal, Vec3::new(0., 1., 0.)));
+        assert!(!record.front_face);
+
+        let moving = MovingSphere::new(Vec3::empty(), Vec3::new(0., 2., 0.), 0., 1., 1., test_fixtures::material());
+        let ray = Ray::new(Vec3::new(0., 1., 0.), Vec3::new(0., 0., 1.), 0.5);
+        let record = moving.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 1.).abs() < 1e-6);
+        assert!(!record.front_face);
+    }
+
+    #[test]
+    fn tangent_rays_graze_the_surface() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let sphere = unit_sphere();
+        let grazing = Ray::new(Vec3::new(1., 0., 5.), Vec3::new(0., 0., -1.), 0.);
+        let record = sphere.hit(&grazing, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 5.).abs() < 1e-4);
+        assert!(close(record.normal, Vec3::new(1., 0., 0.)));
+        let missing = Ray::new(Vec3::new(1.001, 0., 5.), Vec3::new(0., 0., -1.), 0.);
+        assert!(sphere.hit(&missing, 0.001, f32::MAX, &mut rng).is_none());
+    }
+
+    #[test]
+    fn distant_small_spheres_are_still_hit() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let ray = Ray::new(Vec3::new(0., 0., 1e5), Vec3::new(0., 0., -1.), 0.);
+        let record = unit_sphere().hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - (1e5 - 1.)).abs() < 0.02, "{}", record.t);
+        assert!(record.front_face);
+    }
+
+    #[test]
+    fn media_inside_spheres_scatter_rays_from_within() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let fog = ConstantMedium::new(
+            Arc::new(Sphere::new(Vec3::empty(), 5000., Arc::new(Material::dielectric(1.5)))),
+            1.,
+            Arc::new(ConstantTexture::new(Vec3::new(1., 1., 1.))),
+        );
+        let ray = Ray::new(Vec3::new(0., 0., 10.), Vec3::new(0., 0., -1.), 0.);
+        let scattered = (0..100).filter(|_| fog.hit(&ray, 0.001, f32::MAX, &mut rng).is_some()).count();
+        assert_eq!(scattered, 100);
+    }
+}
@@ -1,27 +1,32 @@
 use crate::bboxes::AABB;
 use crate::hitables::{HitRecord, Hitable};
+use crate::matrices::{Matrix4, Quaternion};
 use crate::rays::Ray;
 use crate::vectors::Vec3;
+use rand::RngCore;
 use std::f32::consts::PI;
-use std::f32::MAX;
-use std::rc::Rc;
+use std::sync::Arc;
 
+/// `hitable` turned inside out: its normals point the other way and its back face becomes the front,
+/// which picks the side a one-sided light shines from.
 #[derive(Clone, Debug)]
 pub struct FlipNormals {
-    hitable: Rc<dyn Hitable>,
+    hitable: Arc<dyn Hitable>,
 }
 
 impl FlipNormals {
-    pub fn new(hitable: Rc<dyn Hitable>) -> Self {
+    pub fn new(hitable: Arc<dyn Hitable>) -> Self {
         FlipNormals { hitable }
     }
 }
 
 impl Hitable for FlipNormals {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        match self.hitable.hit(ray, t_min, t_max) {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        match self.hitable.hit(ray, t_min, t_max, rng) {
             Some(record) => Some(HitRecord {
                 normal: -record.normal,
+                geometric_normal: -record.geometric_normal,
+                front_face: !record.front_face,
                 ..record
             }),
             None => None,
@@ -30,22 +35,34 @@ impl Hitable for FlipNormals {
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
         self.hitable.bounding_box(t0, t1)
     }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        self.hitable.pdf_value(origin, direction, rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        self.hitable.random(origin, rng)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct Translate {
-    hitable: Rc<dyn Hitable>,
+    hitable: Arc<dyn Hitable>,
     offset: Vec3,
 }
 impl Translate {
-    pub fn new(hitable: Rc<dyn Hitable>, offset: Vec3) -> Translate {
+    pub fn new(hitable: Arc<dyn Hitable>, offset: Vec3) -> Translate {
         Translate { hitable, offset }
     }
 }
 impl Hitable for Translate {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let moved_ray = Ray::new(ray.origin - self.offset, ray.direction, ray.time);
-        match self.hitable.hit(&moved_ray, t_min, t_max) {
+        match self.hitable.hit(&moved_ray, t_min, t_max, rng) {
             Some(record) => Some(HitRecord {
                 p: record.p + self.offset,
                 ..record
@@ -56,28 +73,39 @@ impl Hitable for Translate {
 
     #[allow(unused_variables)]
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
-        match self.hitable.bounding_box(t0, t1) {
-            Some(bbox) => Some(AABB::new(bbox.min + self.offset, bbox.max + self.offset)),
-            None => None,
-        }
+        self.hitable
+            .bounding_box(t0, t1)
+            .map(|bbox| AABB::new(bbox.min + self.offset, bbox.max + self.offset))
+    }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        self.hitable.pdf_value(origin - self.offset, direction, rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        self.hitable.random(origin - self.offset, rng)
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct RotateY {
-    hitable: Rc<dyn Hitable>,
+    hitable: Arc<dyn Hitable>,
     sin_theta: f32,
     cos_theta: f32,
     bbox: Option<AABB>,
 }
 
 impl RotateY {
-    pub fn new(hitable: Rc<dyn Hitable>, angle: f32) -> RotateY {
+    pub fn new(hitable: Arc<dyn Hitable>, angle: f32) -> RotateY {
         let radians = (PI / 180.) * angle;
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
-        let mut min = Vec3::new(MAX, MAX, MAX);
-        let mut max = Vec3::new(-MAX, -MAX, -MAX);
+        let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vec3::new(-f32::MAX, -f32::MAX, -f32::MAX);
         let bbox = match hitable.bounding_box(0., 1.) {
             Some(p_bbox) => {
                 for i in 0..2 {
@@ -113,8 +141,26 @@ impl RotateY {
     }
 }
 
+impl RotateY {
+    fn to_object(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x - self.sin_theta * v.z,
+            v.y,
+            self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x + self.sin_theta * v.z,
+            v.y,
+            -self.sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+}
+
 impl Hitable for RotateY {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
         let mut origin = ray.origin;
         let mut direction = ray.direction;
         origin.x = self.cos_theta * ray.origin.x - self.sin_theta * ray.origin.z;
@@ -122,17 +168,17 @@ impl Hitable for RotateY {
         direction.x = self.cos_theta * ray.direction.x - self.sin_theta * ray.direction.z;
         direction.z = self.sin_theta * ray.direction.x + self.cos_theta * ray.direction.z;
         let rotated_ray = Ray::new(origin, direction, ray.time);
-        match self.hitable.hit(&rotated_ray, t_min, t_max) {
+        match self.hitable.hit(&rotated_ray, t_min, t_max, rng) {
             Some(record) => {
                 let mut p = record.p;
-                let mut normal = record.normal;
                 p.x = self.cos_theta * record.p.x + self.sin_theta * record.p.z;
                 p.z = -self.sin_theta * record.p.x + self.cos_theta * record.p.z;
-                normal.x = self.cos_theta * record.normal.x + self.sin_theta * record.normal.z;
-                normal.z = -self.sin_theta * record.normal.x + self.cos_theta * record.normal.z;
                 Some(HitRecord {
                     p,
-                    normal,
+                    normal: self.to_world(record.normal),
+                    geometric_normal: self.to_world(record.geometric_normal),
+                    dpdu: self.to_world(record.dpdu),
+                    dpdv: self.to_world(record.dpdv),
                     ..record
                 })
             }
@@ -144,4 +190,474 @@ impl Hitable for RotateY {
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
         self.bbox.clone()
     }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        self.hitable
+            .pdf_value(self.to_object(origin), self.to_object(direction), rng)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        self.to_world(self.hitable.random(self.to_object(origin), rng))
+    }
+}
+
+/// An instance of `hitable` placed by an affine transform from object to world space.
+///
+/// Rays are taken into object space with the inverse matrix and normals brought back with its
+/// transpose. The builder methods apply a further transform after the current one, so
+/// `Transform::new(object).scale(..).rotate_x(..).translate(..)` scales first.
+#[derive(Clone, Debug)]
+pub struct Transform {
+    hitable: Arc<dyn Hitable>,
+    matrix: Matrix4,
+    inverse: Matrix4,
+    bbox: Option<AABB>,
+}
+
+impl Transform {
+    pub fn new(hitable: Arc<dyn Hitable>) -> Transform {
+        Transform::with_matrix(hitable, Matrix4::identity())
+    }
+
+    /// Panics if `matrix` is not invertible.
+    pub fn with_matrix(hitable: Arc<dyn Hitable>, matrix: Matrix4) -> Transform {
+        let inverse = matrix
+            .inverse()
+            .expect("transform matrix is not invertible");
+        let bbox = hitable.bounding_box(0., 1.).map(|bbox| {
+            let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+            let mut max = Vec3::new(-f32::MAX, -f32::MAX, -f32::MAX);
+            for corner in 0..8 {
+                let point = Vec3::new(
+                    if corner & 1 == 0 {
+                        bbox.min.x
+                    } else {
+                        bbox.max.x
+                    },
+                    if corner & 2 == 0 {
+                        bbox.min.y
+                    } else {
+                        bbox.max.y
+                    },
+                    if corner & 4 == 0 {
+                        bbox.min.z
+                    } else {
+                        bbox.max.z
+                    },
+                );
+                let point = matrix.transform_point(point);
+                for c in 0..3 {
+                    min[c] = min[c].min(point[c]);
+                    max[c] = max[c].max(point[c]);
+                }
+            }
+            AABB::new(min, max)
+        });
+        Transform {
+            hitable,
+            matrix,
+            inverse,
+            bbox,
+        }
+    }
+
+    /// Applies `matrix` after the current transform.
+    pub fn compose(self, matrix: Matrix4) -> Transform {
+        Transform::with_matrix(self.hitable, matrix * self.matrix)
+    }
+
+    pub fn translate(self, offset: Vec3) -> Transform {
+        self.compose(Matrix4::translation(offset))
+    }
+
+    pub fn scale(self, factors: Vec3) -> Transform {
+        self.compose(Matrix4::scaling(factors))
+    }
+
+    pub fn rotate_x(self, angle: f32) -> Transform {
+        self.compose(Matrix4::rotation_x(angle))
+    }
+
+    pub fn rotate_y(self, angle: f32) -> Transform {
+        self.compose(Matrix4::rotation_y(angle))
+    }
+
+    pub fn rotate_z(self, angle: f32) -> Transform {
+        self.compose(Matrix4::rotation_z(angle))
+    }
+
+    /// See `Matrix4::look_at`.
+    pub fn look_at(self, from: Vec3, at: Vec3, up: Vec3) -> Transform {
+        self.compose(Matrix4::look_at(from, at, up))
+    }
+
+    pub fn matrix(&self) -> Matrix4 {
+        self.matrix
+    }
+
+    fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(normal).unit_vector()
+    }
+}
+
+impl Hitable for Transform {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let object_ray = Ray::new(
+            self.inverse.transform_point(ray.origin),
+            self.inverse.transform_vector(ray.direction),
+            ray.time,
+        );
+        self.hitable
+            .hit(&object_ray, t_min, t_max, rng)
+            .map(|record| HitRecord {
+                p: self.matrix.transform_point(record.p),
+                normal: self.normal_to_world(record.normal),
+                geometric_normal: self.normal_to_world(record.geometric_normal),
+                dpdu: self.matrix.transform_vector(record.dpdu),
+                dpdv: self.matrix.transform_vector(record.dpdv),
+                ..record
+            })
+    }
+
+    #[allow(unused_variables)]
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.bbox.clone()
+    }
+
+    fn is_light(&self) -> bool {
+        self.hitable.is_light()
+    }
+
+    /// The object's density for the direction in object space, times the change in solid angle
+    /// between the two spaces: `|det M| |M⁻¹ ω|³` for a unit world direction `ω`.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, rng: &mut dyn RngCore) -> f32 {
+        let object_direction = self.inverse.transform_vector(direction);
+        let pdf = self
+            .hitable
+            .pdf_value(self.inverse.transform_point(origin), object_direction, rng);
+        let stretch = object_direction.length() / direction.length();
+        pdf / (self.matrix.linear_determinant().abs() * stretch * stretch * stretch)
+    }
+
+    fn random(&self, origin: Vec3, rng: &mut dyn RngCore) -> Vec3 {
+        self.matrix
+            .transform_vector(self.hitable.random(self.inverse.transform_point(origin), rng))
+    }
+}
+
+/// The pose of an `AnimatedTransform` at `time`: scaled, then rotated, then translated.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f32,
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f32, translation: Vec3, rotation: Quaternion, scale: Vec3) -> Keyframe {
+        Keyframe {
+            time,
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    /// Interpolates translation and scale linearly and rotation by slerp.
+    fn lerp(&self, other: &Keyframe, t: f32) -> Keyframe {
+        Keyframe {
+            time: self.time + (other.time - self.time) * t,
+            translation: self.translation + (other.translation - self.translation) * t,
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+
+    fn apply(&self, p: Vec3) -> Vec3 {
+        self.translation + self.rotation.rotate(p * self.scale)
+    }
+}
+
+/// Rotation steps of at most this many radians are used to bound the motion.
+const MAX_BOUNDS_STEP: f32 = 0.02;
+
+/// An instance of `hitable` moving through `keyframes`, for motion blur of any object.
+///
+/// A ray is intersected with the object in the pose interpolated at `ray.time`; before the first
+/// and after the last keyframe the object holds still.
+///
+/// Light sampling doesn't know the time of the ray it samples for, so a moving light is only found
+/// by the rays that hit it.
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    hitable: Arc<dyn Hitable>,
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    /// Panics if `keyframes` is empty or has a zero scale.
+    pub fn new(hitable: Arc<dyn Hitable>, mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(
+            !keyframes.is_empty(),
+            "an animated transform needs at least one keyframe"
+        );
+        assert!(
+            keyframes
+                .iter()
+                .all(|key| key.scale.x * key.scale.y * key.scale.z != 0.),
+            "an animated transform can't scale by zero"
+        );
+        for key in &mut keyframes {
+            key.rotation = key.rotation.normalize();
+        }
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        AnimatedTransform { hitable, keyframes }
+    }
+
+    /// The pose at `time`.
+    pub fn pose(&self, time: f32) -> Keyframe {
+        let keys = &self.keyframes;
+        let next = keys.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return keys[0];
+        }
+        if next == keys.len() {
+            return keys[keys.len() - 1];
+        }
+        let (a, b) = (&keys[next - 1], &keys[next]);
+        a.lerp(b, (time - a.time) / (b.time - a.time))
+    }
+}
+
+/// A box around `bbox` in every pose between the first and the last keyframe.
+///
+/// Each segment is stepped finely enough that the rotation between steps stays below
+/// `MAX_BOUNDS_STEP`, and the boxes are padded by the most a corner can stray from the chord
+/// between two steps.
+fn motion_bounds(bbox: &AABB, keyframes: &[Keyframe]) -> AABB {
+    let corners: Vec<Vec3> = (0..8)
+        .map(|corner| {
+            Vec3::new(
+                if corner & 1 == 0 {
+                    bbox.min.x
+                } else {
+                    bbox.max.x
+                },
+                if corner & 2 == 0 {
+                    bbox.min.y
+                } else {
+                    bbox.max.y
+                },
+                if corner & 4 == 0 {
+                    bbox.min.z
+                } else {
+                    bbox.max.z
+                },
+            )
+        })
+        .collect();
+    let mut poses = vec![keyframes[0]];
+    for pair in keyframes.windows(2) {
+        let angle = pair[0].rotation.angle_to(pair[1].rotation);
+        let steps = ((angle / MAX_BOUNDS_STEP).ceil() as usize).max(1);
+        poses.extend((1..=steps).map(|step| pair[0].lerp(&pair[1], step as f32 / steps as f32)));
+    }
+    let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
+    let mut max = Vec3::new(-f32::MAX, -f32::MAX, -f32::MAX);
+    let mut radius: f32 = 0.;
+    for pose in &poses {
+        for corner in &corners {
+            radius = radius.max((*corner * pose.scale).length());
+            let point = pose.apply(*corner);
+            for c in 0..3 {
+                min[c] = min[c].min(point[c]);
+                max[c] = max[c].max(point[c]);
+            }
+        }
+    }
+    let padding = radius * (1. - (MAX_BOUNDS_STEP / 2.).cos()) + 1e-4 * radius.max(1.);
+    let padding = Vec3::new(padding, padding, padding);
+    AABB::new(min - padding, max + padding)
+}
+
+impl Hitable for AnimatedTransform {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let pose = self.pose(ray.time);
+        let inverse_rotation = pose.rotation.conjugate();
+        let object_ray = Ray::new(
+            inverse_rotation.rotate(ray.origin - pose.translation) / pose.scale,
+            inverse_rotation.rotate(ray.direction) / pose.scale,
+            ray.time,
+        );
+        self.hitable
+            .hit(&object_ray, t_min, t_max, rng)
+            .map(|record| HitRecord {
+                p: pose.apply(record.p),
+                normal: pose.rotation.rotate(record.normal / pose.scale).unit_vector(),
+                geometric_normal: pose.rotation.rotate(record.geometric_normal / pose.scale).unit_vector(),
+                dpdu: pose.rotation.rotate(record.dpdu * pose.scale),
+                dpdv: pose.rotation.rotate(record.dpdv * pose.scale),
+                ..record
+            })
+    }
+
+    /// The object may move on its own as well, so its box spans both the keyframes and `t0..t1`.
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        let (first, last) = (self.keyframes[0].time, self.keyframes[self.keyframes.len() - 1].time);
+        self.hitable
+            .bounding_box(t0.min(first), t1.max(last))
+            .map(|bbox| motion_bounds(&bbox, &self.keyframes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitables::bvh::BvhBuilder;
+    use crate::hitables::spheres::{MovingSphere, Sphere};
+    use crate::matrices::Quaternion;
+    use crate::test_fixtures;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    fn unit_sphere() -> Arc<dyn Hitable> {
+        Arc::new(Sphere::new(Vec3::empty(), 1., test_fixtures::material()))
+    }
+
+    #[test]
+    fn scaled_and_rotated_sphere_has_correct_hits_normals_and_box() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        // An ellipsoid with semi-axes 2 (x), 1 (y) and 1 (z), stood upright and moved to (0, 5, 0).
+        let ellipsoid = Transform::new(unit_sphere())
+            .scale(Vec3::new(2., 1., 1.))
+            .rotate_z(90.)
+            .translate(Vec3::new(0., 5., 0.));
+        let bbox = ellipsoid.bounding_box(0., 1.).unwrap();
+        assert!((bbox.min - Vec3::new(-1., 3., -1.)).length() < 1e-4);
+        assert!((bbox.max - Vec3::new(1., 7., 1.)).length() < 1e-4);
+
+        let down = Ray::new(Vec3::new(0., 10., 0.), Vec3::new(0., -1., 0.), 0.);
+        let record = ellipsoid.hit(&down, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 3.).abs() < 1e-4);
+        assert!((record.p - Vec3::new(0., 7., 0.)).length() < 1e-4);
+        assert!((record.normal - Vec3::new(0., 1., 0.)).length() < 1e-4);
+
+        // Off the axes the normal is the inverse transpose of the sphere's, not the transformed one.
+        let side = Ray::new(
+            Vec3::new(10., 5. + 2. * 0.6, 0.),
+            Vec3::new(-1., 0., 0.),
+            0.,
+        );
+        let record = ellipsoid.hit(&side, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.p.x - 0.8).abs() < 1e-4);
+        let expected = Vec3::new(0.8, 1.2 / 4., 0.).unit_vector();
+        assert!((record.normal - expected).length() < 1e-4);
+    }
+
+    #[test]
+    fn rotate_y_matches_the_transform() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let material = test_fixtures::material();
+        let offset_sphere: Arc<dyn Hitable> =
+            Arc::new(Sphere::new(Vec3::new(3., 0., 0.), 1., material));
+        let rotated = RotateY::new(Arc::clone(&offset_sphere), 30.);
+        let transformed = Transform::new(offset_sphere).rotate_y(30.);
+        for i in 0..50 {
+            let angle = i as f32 * 0.13;
+            let ray = Ray::new(
+                Vec3::new(0., 0.5, 0.),
+                Vec3::new(angle.cos(), 0., angle.sin()),
+                0.,
+            );
+            let expected = rotated.hit(&ray, 0.001, f32::MAX, &mut rng);
+            let actual = transformed.hit(&ray, 0.001, f32::MAX, &mut rng);
+            assert_eq!(expected.is_some(), actual.is_some());
+            if let (Some(expected), Some(actual)) = (expected, actual) {
+                assert!((expected.t - actual.t).abs() < 1e-4);
+                assert!((expected.normal - actual.normal).length() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn animated_transform_moves_the_object_over_time() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let quarter_turn = Quaternion::from_axis_angle(Vec3::new(0., 0., 1.), 90.);
+        let animated = AnimatedTransform::new(
+            unit_sphere(),
+            vec![
+                Keyframe::new(
+                    0.,
+                    Vec3::new(0., 0., 0.),
+                    Quaternion::identity(),
+                    Vec3::new(1., 1., 1.),
+                ),
+                Keyframe::new(
+                    1.,
+                    Vec3::new(4., 0., 0.),
+                    quarter_turn,
+                    Vec3::new(2., 1., 1.),
+                ),
+            ],
+        );
+        let bbox = animated.bounding_box(0., 1.).unwrap();
+        for i in 0..=20 {
+            let time = i as f32 / 20.;
+            let pose = animated.pose(time);
+            // Down the middle of the object's path at this time.
+            let ray = Ray::new(
+                Vec3::new(pose.translation.x, 10., 0.),
+                Vec3::new(0., -1., 0.),
+                time,
+            );
+            let record = animated.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+            assert!(record.p.y > bbox.min.y && record.p.y < bbox.max.y);
+            // The long axis turns from x to y, so the top rises from 1 to 2.
+            assert!(record.p.y >= 1. - 1e-4 && record.p.y <= 2. + 1e-4);
+            for corner in &[
+                Vec3::new(1., 1., 1.),
+                Vec3::new(-1., 1., -1.),
+                Vec3::new(1., -1., 1.),
+            ] {
+                let point = pose.apply(*corner);
+                for c in 0..3 {
+                    assert!(point[c] >= bbox.min[c] && point[c] <= bbox.max[c]);
+                }
+            }
+        }
+        let end = animated.hit(
+            &Ray::new(Vec3::new(4., 10., 0.), Vec3::new(0., -1., 0.), 1.),
+            0.001,
+            f32::MAX,
+            &mut rng,
+        );
+        assert!((end.unwrap().p.y - 2.).abs() < 1e-4);
+        // Outside the keyframes the object holds still.
+        let late = Ray::new(Vec3::new(4., 10., 0.), Vec3::new(0., -1., 0.), 3.);
+        assert!((animated.hit(&late, 0.001, f32::MAX, &mut rng).unwrap().p.y - 2.).abs() < 1e-4);
+        let early = Ray::new(Vec3::new(4., 10., 0.), Vec3::new(0., -1., 0.), -1.);
+        assert!(animated.hit(&early, 0.001, f32::MAX, &mut rng).is_none());
+    }
+
+    #[test]
+    fn animated_boxes_cover_children_that_move_on_their_own() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        // Held still by a single keyframe, while the sphere inside moves from x = 0 to x = 10.
+        let moving = MovingSphere::new(Vec3::empty(), Vec3::new(10., 0., 0.), 0., 1., 1., test_fixtures::material());
+        let still = Keyframe::new(0., Vec3::empty(), Quaternion::identity(), Vec3::new(1., 1., 1.));
+        let animated: Arc<dyn Hitable> = Arc::new(AnimatedTransform::new(Arc::new(moving), vec![still]));
+        let bbox = animated.bounding_box(0., 1.).unwrap();
+        assert!(bbox.min.x <= -1. && bbox.max.x >= 11.);
+
+        let other: Arc<dyn Hitable> = Arc::new(Sphere::new(Vec3::new(-20., 0., 0.), 1., test_fixtures::material()));
+        let world = BvhBuilder::Sah.build(&mut [animated, other], 0., 1., &mut rng);
+        let ray = Ray::new(Vec3::new(10., 0., 5.), Vec3::new(0., 0., -1.), 1.);
+        let record = world.hit(&ray, 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 4.).abs() < 1e-4);
+    }
 }
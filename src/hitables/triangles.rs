@@ -0,0 +1,284 @@
+use crate::bboxes::AABB;
+use crate::hitables::bvh::BvhBuilder;
+use crate::hitables::linear_bvh::LinearBVH;
+use crate::hitables::{HitRecord, Hitable};
+use crate::materials::Material;
+use crate::rays::Ray;
+use crate::vectors::Vec3;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
+
+const EPSILON: f32 = 1e-8;
+
+/// Möller–Trumbore ray/triangle intersection, returning `(t, b1, b2)` where `b1` and `b2`
+/// are the barycentric weights of `p1` and `p2`.
+fn intersect(ray: &Ray, p0: Vec3, p1: Vec3, p2: Vec3, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+    let edge_1 = p1 - p0;
+    let edge_2 = p2 - p0;
+    let p_vec = ray.direction.cross(edge_2);
+    let determinant = edge_1.dot(p_vec);
+    if determinant.abs() < EPSILON {
+        return None;
+    }
+    let inv_determinant = 1. / determinant;
+    let t_vec = ray.origin - p0;
+    let b1 = t_vec.dot(p_vec) * inv_determinant;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+    let q_vec = t_vec.cross(edge_1);
+    let b2 = ray.direction.dot(q_vec) * inv_determinant;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+    let t = edge_2.dot(q_vec) * inv_determinant;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// The derivatives of the point along texture coordinates that go from `uv_0` at `p0` to `uv_1`
+/// at `p1` and `uv_2` at `p2`, or along the edges from `p0` if they don't span the triangle.
+fn triangle_tangents(
+    (p0, p1, p2): (Vec3, Vec3, Vec3),
+    uv_0: (f32, f32),
+    uv_1: (f32, f32),
+    uv_2: (f32, f32),
+) -> (Vec3, Vec3) {
+    let (edge_1, edge_2) = (p1 - p0, p2 - p0);
+    let (du_1, dv_1) = (uv_1.0 - uv_0.0, uv_1.1 - uv_0.1);
+    let (du_2, dv_2) = (uv_2.0 - uv_0.0, uv_2.1 - uv_0.1);
+    let determinant = du_1 * dv_2 - dv_1 * du_2;
+    if determinant.abs() < EPSILON {
+        return (edge_1, edge_2);
+    }
+    (
+        (edge_1 * dv_2 - edge_2 * dv_1) / determinant,
+        (edge_2 * du_1 - edge_1 * du_2) / determinant,
+    )
+}
+
+fn triangle_box(p0: Vec3, p1: Vec3, p2: Vec3) -> AABB {
+    let mut min = Vec3::new(p0.x.min(p1.x).min(p2.x), p0.y.min(p1.y).min(p2.y), p0.z.min(p1.z).min(p2.z));
+    let mut max = Vec3::new(p0.x.max(p1.x).max(p2.x), p0.y.max(p1.y).max(p2.y), p0.z.max(p1.z).max(p2.z));
+    for axis in 0..3 {
+        if max[axis] - min[axis] < 0.0001 {
+            min[axis] -= 0.0001;
+            max[axis] += 0.0001;
+        }
+    }
+    AABB::new(min, max)
+}
+
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    pub p0: Vec3,
+    pub p1: Vec3,
+    pub p2: Vec3,
+    pub material: Arc<Material>,
+}
+
+impl Triangle {
+    pub fn new(p0: Vec3, p1: Vec3, p2: Vec3, material: Arc<Material>) -> Triangle {
+        Triangle { p0, p1, p2, material }
+    }
+}
+
+impl Hitable for Triangle {
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (t, b1, b2) = intersect(ray, self.p0, self.p1, self.p2, t_min, t_max)?;
+        let normal = (self.p1 - self.p0).cross(self.p2 - self.p0).unit_vector();
+        Some(HitRecord {
+            t,
+            u: b1,
+            v: b2,
+            p: ray.point_at_parameter(t),
+            normal,
+            geometric_normal: normal,
+            front_face: ray.direction.dot(normal) < 0.,
+            dpdu: self.p1 - self.p0,
+            dpdv: self.p2 - self.p0,
+            material: Arc::clone(&self.material),
+        })
+    }
+
+    #[allow(unused_variables)]
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        Some(triangle_box(self.p0, self.p1, self.p2))
+    }
+}
+
+/// Vertex and index buffers shared by all the triangles of a mesh.
+#[derive(Clone, Debug)]
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f32, f32)>>,
+    pub indices: Vec<[usize; 3]>,
+    pub material: Arc<Material>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> (Vec3, Vec3, Vec3) {
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+        (self.mesh.positions[i0], self.mesh.positions[i1], self.mesh.positions[i2])
+    }
+}
+
+impl Hitable for MeshTriangle {
+    #[allow(unused_variables)]
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        let (p0, p1, p2) = self.vertices();
+        let (t, b1, b2) = intersect(ray, p0, p1, p2, t_min, t_max)?;
+        let b0 = 1. - b1 - b2;
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+        let ((u, v), (dpdu, dpdv)) = match &self.mesh.uvs {
+            Some(uvs) => (
+                (
+                    b0 * uvs[i0].0 + b1 * uvs[i1].0 + b2 * uvs[i2].0,
+                    b0 * uvs[i0].1 + b1 * uvs[i1].1 + b2 * uvs[i2].1,
+                ),
+                triangle_tangents((p0, p1, p2), uvs[i0], uvs[i1], uvs[i2]),
+            ),
+            None => ((b1, b2), (p1 - p0, p2 - p0)),
+        };
+        let geometric_normal = (p1 - p0).cross(p2 - p0).unit_vector();
+        let (normal, geometric_normal) = match &self.mesh.normals {
+            Some(normals) => {
+                let normal = (normals[i0] * b0 + normals[i1] * b1 + normals[i2] * b2).unit_vector();
+                // The vertex normals say which side is outside, whatever the winding.
+                if geometric_normal.dot(normal) < 0. {
+                    (normal, -geometric_normal)
+                } else {
+                    (normal, geometric_normal)
+                }
+            }
+            None => (geometric_normal, geometric_normal),
+        };
+        Some(HitRecord {
+            t,
+            u,
+            v,
+            p: ray.point_at_parameter(t),
+            normal,
+            geometric_normal,
+            front_face: ray.direction.dot(geometric_normal) < 0.,
+            dpdu,
+            dpdv,
+            material: Arc::clone(&self.mesh.material),
+        })
+    }
+
+    #[allow(unused_variables)]
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        let (p0, p1, p2) = self.vertices();
+        Some(triangle_box(p0, p1, p2))
+    }
+}
+
+/// An indexed triangle mesh with optional per-vertex normals (for smooth shading) and texture coordinates.
+#[derive(Clone, Debug)]
+pub struct TriangleMesh {
+    pub mesh: Arc<MeshData>,
+    triangles: LinearBVH,
+}
+
+impl TriangleMesh {
+    /// Builds the mesh's BVH with `bvh`, which draws from `rng` only for the random builder.
+    pub fn new<R: Rng>(mesh: MeshData, bvh: BvhBuilder, rng: &mut R) -> TriangleMesh {
+        assert!(!mesh.indices.is_empty(), "a triangle mesh needs at least one face");
+        let vertex_count = mesh.positions.len();
+        assert!(
+            mesh.indices.iter().all(|face| face.iter().all(|&i| i < vertex_count)),
+            "triangle mesh index out of range"
+        );
+        assert!(mesh.normals.as_ref().is_none_or(|normals| normals.len() == vertex_count));
+        assert!(mesh.uvs.as_ref().is_none_or(|uvs| uvs.len() == vertex_count));
+        let mesh = Arc::new(mesh);
+        let mut triangles = (0..mesh.indices.len())
+            .map(|face| {
+                Arc::new(MeshTriangle {
+                    mesh: Arc::clone(&mesh),
+                    face,
+                }) as Arc<dyn Hitable>
+            })
+            .collect::<Vec<_>>();
+        TriangleMesh {
+            triangles: bvh.build(&mut triangles, 0., 1., rng),
+            mesh,
+        }
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32, rng: &mut dyn RngCore) -> Option<HitRecord> {
+        self.triangles.hit(ray, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.triangles.bounding_box(t0, t1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{down, material};
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn triangle_reports_barycentric_coordinates() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let triangle = Triangle::new(
+            Vec3::new(0., 0., 0.),
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            material(),
+        );
+        let record = triangle.hit(&down(0.25, 0.5), 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.t - 1.).abs() < 1e-6);
+        assert!((record.u - 0.25).abs() < 1e-6 && (record.v - 0.5).abs() < 1e-6);
+        assert!((record.normal.y + 1.).abs() < 1e-6);
+        assert!(triangle.hit(&down(0.75, 0.5), 0.001, f32::MAX, &mut rng).is_none());
+        assert!(triangle.hit(&down(-0.1, 0.5), 0.001, f32::MAX, &mut rng).is_none());
+    }
+
+    #[test]
+    fn mesh_interpolates_normals_and_uvs() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mut untouched = rng.clone();
+        let up = Vec3::new(0., 1., 0.);
+        let tilted = Vec3::new(1., 1., 0.).unit_vector();
+        let mesh = TriangleMesh::new(
+            MeshData {
+                positions: vec![
+                    Vec3::new(0., 0., 0.),
+                    Vec3::new(1., 0., 0.),
+                    Vec3::new(1., 0., 1.),
+                    Vec3::new(0., 0., 1.),
+                ],
+                normals: Some(vec![up, tilted, tilted, up]),
+                uvs: Some(vec![(0., 0.), (1., 0.), (1., 1.), (0., 1.)]),
+                indices: vec![[0, 2, 1], [0, 3, 2]],
+                material: material(),
+            },
+            BvhBuilder::Sah,
+            &mut rng,
+        );
+        // The surface area heuristic doesn't draw from the generator.
+        assert_eq!(rng.next_u64(), untouched.next_u64());
+        let record = mesh.hit(&down(0.5, 0.25), 0.001, f32::MAX, &mut rng).unwrap();
+        assert!((record.u - 0.5).abs() < 1e-5 && (record.v - 0.25).abs() < 1e-5);
+        assert!(record.normal.x > 0. && (record.normal.length() - 1.).abs() < 1e-5);
+        assert!(mesh.hit(&down(1.5, 0.5), 0.001, f32::MAX, &mut rng).is_none());
+    }
+}
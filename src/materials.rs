@@ -1,17 +1,48 @@
 use crate::hitables::HitRecord;
+use crate::onb::{random_cosine_direction, ONB};
 use rand::Rng;
 use crate::rays::Ray;
+use crate::spectrum::{color_at, RefractiveIndex, Wavelengths};
 use crate::textures::Texture;
 use crate::vectors::Vec3;
-use std::rc::Rc;
+use std::f32::consts::PI;
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub enum Material {
-    Lambertian { albedo: Rc<dyn Texture> },
+    Lambertian { albedo: Arc<dyn Texture> },
     Metal { albedo: Vec3, fuzz: f32 },
-    Dielectric { ref_idx: f32 },
-    DiffuseLight { emit: Rc<dyn Texture> },
-    Isotropic { albedo: Rc<dyn Texture> },
+    /// Glass-like refraction. Without `roughness` the surface is a perfect mirror and window; with it,
+    /// a GGX microfacet BSDF. Light travelling inside is absorbed at `absorption` per unit distance.
+    Dielectric {
+        ref_idx: RefractiveIndex,
+        roughness: Option<Arc<dyn Texture>>,
+        absorption: Vec3,
+    },
+    /// Emits `emit` from both sides, or with `one_sided` only from the side its normal points to.
+    DiffuseLight { emit: Arc<dyn Texture>, one_sided: bool },
+    Isotropic { albedo: Arc<dyn Texture> },
+    /// A GGX microfacet BRDF over a diffuse base. `roughness` and `metallic` are read from the red
+    /// channel of their textures; a metallic surface tints its reflection with `albedo`, a dielectric
+    /// one reflects white light by the Fresnel equations for `ior` and shows `albedo` underneath.
+    Microfacet {
+        albedo: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+        metallic: Arc<dyn Texture>,
+        ior: f32,
+    },
+}
+
+/// The result of scattering a ray off a material.
+#[derive(Clone, Debug)]
+pub enum ScatterRecord {
+    /// A delta lobe (mirror reflection or refraction): `ray` is the only possible direction,
+    /// and its light is scaled by `attenuation`.
+    Specular { ray: Ray, attenuation: Vec3 },
+    /// A lobe spread over many directions: `ray` was sampled with density `pdf`, and `eval`
+    /// is the cosine-weighted BSDF for it. `Material::eval` and `Material::scattering_pdf`
+    /// give the same for any other direction.
+    Diffuse { ray: Ray, pdf: f32, eval: Vec3 },
 }
 
 fn random_in_unit_sphere<R: Rng>(rng: &mut R) -> Vec3 {
@@ -23,6 +54,10 @@ fn random_in_unit_sphere<R: Rng>(rng: &mut R) -> Vec3 {
     }
 }
 
+fn random_unit_vector<R: Rng>(rng: &mut R) -> Vec3 {
+    random_in_unit_sphere(rng).unit_vector()
+}
+
 fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - n * v.dot(n) * 2.
 }
@@ -44,8 +79,269 @@ fn schlick(cosine: f32, ref_idx: f32) -> f32 {
     r0 + (1. - r0) * (1. - cosine).powi(5)
 }
 
+/// Fresnel reflectance of unpolarized light arriving from outside an interface with relative index `eta`.
+fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.
+}
+
+/// Schlick's approximation for a conductor whose reflectance at normal incidence is `f0`.
+fn fresnel_conductor(cos_i: f32, f0: Vec3) -> Vec3 {
+    f0 + (Vec3::new(1., 1., 1.) - f0) * (1. - cos_i).powi(5)
+}
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals at `cos_h` from the normal.
+fn ggx_d(cos_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let denominator = cos_h * cos_h * (a2 - 1.) + 1.;
+    a2 / (PI * denominator * denominator)
+}
+
+/// Smith's auxiliary function for GGX, for a direction at `cosine` from the normal.
+fn ggx_lambda(cosine: f32, alpha: f32) -> f32 {
+    let tan2 = (1. - cosine * cosine).max(0.) / (cosine * cosine);
+    ((1. + alpha * alpha * tan2).sqrt() - 1.) / 2.
+}
+
+/// A microfacet normal, in the frame where the macro normal is +z, sampled in proportion to how
+/// much of it is visible from `view` (Heitz 2018).
+fn sample_ggx_visible_normal<R: Rng>(view: Vec3, alpha: f32, rng: &mut R) -> Vec3 {
+    let stretched = Vec3::new(alpha * view.x, alpha * view.y, view.z).unit_vector();
+    let length2 = stretched.x * stretched.x + stretched.y * stretched.y;
+    let t1 = if length2 > 0. {
+        Vec3::new(-stretched.y, stretched.x, 0.) / length2.sqrt()
+    } else {
+        Vec3::new(1., 0., 0.)
+    };
+    let t2 = stretched.cross(t1);
+    let r = rng.gen::<f32>().sqrt();
+    let phi = 2. * PI * rng.gen::<f32>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1. + stretched.z);
+    let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+    let normal = t1 * p1 + t2 * p2 + stretched * (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+    Vec3::new(alpha * normal.x, alpha * normal.y, normal.z.max(1e-6)).unit_vector()
+}
+
+/// Perceptually linear roughness to GGX alpha, kept away from a perfect mirror's infinite peak.
+fn ggx_alpha(roughness: f32) -> f32 {
+    let roughness = roughness.clamp(0., 1.);
+    (roughness * roughness).max(MIN_ALPHA)
+}
+
+/// Rough dielectrics smoother than this are treated as perfectly smooth.
+const MIN_ALPHA: f32 = 1e-3;
+
+/// Beer-Lambert transmittance of the path `ray_in` took to `record`, if it came from inside a
+/// dielectric absorbing `absorption` per unit distance.
+fn transmittance(absorption: Vec3, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+    if record.front_face {
+        return Vec3::new(1., 1., 1.);
+    }
+    let absorption = color_at(ray_in.wavelengths, absorption);
+    let distance = record.t * ray_in.direction.length();
+    Vec3::new(
+        (-absorption.x * distance).exp(),
+        (-absorption.y * distance).exp(),
+        (-absorption.z * distance).exp(),
+    )
+}
+
+/// What a dielectric lets through along a path: `transmittance`, reduced to the hero wavelength
+/// when the refraction depends on it, along with the wavelengths the path continues with.
+fn dielectric_throughput(
+    ref_idx: &RefractiveIndex,
+    absorption: Vec3,
+    ray_in: &Ray,
+    record: &HitRecord,
+) -> (Option<Wavelengths>, Vec3) {
+    let throughput = transmittance(absorption, ray_in, record);
+    match ray_in.wavelengths {
+        Some(wavelengths) if ref_idx.is_dispersive() => {
+            let (wavelengths, throughput) = wavelengths.terminate_secondary(throughput);
+            (Some(wavelengths), throughput)
+        }
+        wavelengths => (wavelengths, throughput),
+    }
+}
+
+/// `texture` at the hit, averaged over the footprint of `ray_in` there.
+fn texture_at(texture: &dyn Texture, ray_in: &Ray, record: &HitRecord) -> Vec3 {
+    let (u, v, p) = (record.u, record.v, record.p);
+    let tangent_length = record.dpdu.length().min(record.dpdv.length());
+    if ray_in.spread == 0. || tangent_length == 0. {
+        return texture.surface_value(u, v, p, record.normal, 0.);
+    }
+    let distance = record.t * ray_in.direction.length();
+    // The footprint stretches along the surface as the ray grazes it.
+    let cosine = (ray_in.direction.unit_vector().dot(record.normal)).abs().max(0.05);
+    texture.surface_value(u, v, p, record.normal, ray_in.spread * distance / (cosine * tangent_length))
+}
+
+/// The inputs of a microfacet material at a hit point.
+struct MicrofacetSurface {
+    albedo: Vec3,
+    alpha: f32,
+    metallic: f32,
+    ior: f32,
+    /// The shading normal, turned towards the viewer.
+    normal: Vec3,
+    /// Unit direction towards the viewer.
+    view: Vec3,
+}
+
+impl MicrofacetSurface {
+    /// Probability of sampling the specular lobe rather than the diffuse one.
+    fn specular_probability(&self) -> f32 {
+        let fresnel = fresnel_dielectric(self.view.dot(self.normal), self.ior);
+        self.metallic + (1. - self.metallic) * fresnel.max(0.25)
+    }
+
+    fn eval(&self, direction: Vec3) -> Vec3 {
+        let (cos_o, cos_i) = (self.view.dot(self.normal), direction.dot(self.normal));
+        if cos_o <= 0. || cos_i <= 0. {
+            return Vec3::empty();
+        }
+        let half = (self.view + direction).unit_vector();
+        let cos_d = self.view.dot(half);
+        let dielectric = fresnel_dielectric(cos_d, self.ior);
+        let fresnel = fresnel_conductor(cos_d, self.albedo) * self.metallic
+            + Vec3::new(1., 1., 1.) * (dielectric * (1. - self.metallic));
+        let masking = 1. / (1. + ggx_lambda(cos_o, self.alpha) + ggx_lambda(cos_i, self.alpha));
+        let specular = fresnel * (ggx_d(half.dot(self.normal), self.alpha) * masking / (4. * cos_o));
+        // Light enters and leaves the diffuse base through the interface, losing what it reflects.
+        let transmitted = (1. - fresnel_dielectric(cos_o, self.ior)) * (1. - fresnel_dielectric(cos_i, self.ior));
+        let diffuse = self.albedo * ((1. - self.metallic) * transmitted * cos_i / PI);
+        specular + diffuse
+    }
+
+    fn pdf(&self, direction: Vec3) -> f32 {
+        let (cos_o, cos_i) = (self.view.dot(self.normal), direction.dot(self.normal));
+        if cos_o <= 0. || cos_i <= 0. {
+            return 0.;
+        }
+        let half = (self.view + direction).unit_vector();
+        let masking = 1. / (1. + ggx_lambda(cos_o, self.alpha));
+        let specular = ggx_d(half.dot(self.normal), self.alpha) * masking / (4. * cos_o);
+        let p = self.specular_probability();
+        p * specular + (1. - p) * cos_i / PI
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> Vec3 {
+        let frame = ONB::from_w(self.normal);
+        if rng.gen::<f32>() < self.specular_probability() {
+            let view = Vec3::new(self.view.dot(frame.u), self.view.dot(frame.v), self.view.dot(frame.w));
+            let half = frame.local(sample_ggx_visible_normal(view, self.alpha, rng));
+            reflect(-self.view, half)
+        } else {
+            frame.local(random_cosine_direction(rng))
+        }
+    }
+}
+
+/// A rough dielectric interface at a hit point, reflecting and refracting off GGX microfacets
+/// (Walter et al. 2007).
+struct RoughDielectricSurface {
+    alpha: f32,
+    /// Index of refraction beyond the surface over the one on the viewer's side.
+    eta: f32,
+    /// The shading normal, turned towards the viewer.
+    normal: Vec3,
+    /// Unit direction towards the viewer.
+    view: Vec3,
+}
+
+impl RoughDielectricSurface {
+    /// The microfacet normal that reflects or refracts the view into `direction`, if it faces both.
+    fn half_vector(&self, direction: Vec3) -> Option<Vec3> {
+        let cos_i = direction.dot(self.normal);
+        let half = if cos_i > 0. {
+            self.view + direction
+        } else if cos_i < 0. {
+            self.view + direction * self.eta
+        } else {
+            return None;
+        };
+        let half = half.unit_vector();
+        let half = if half.dot(self.normal) < 0. { -half } else { half };
+        if self.view.dot(half) <= 0. || direction.dot(half) * cos_i <= 0. {
+            return None;
+        }
+        Some(half)
+    }
+
+    /// The transmitted direction's `(wi.h + wo.h / eta)^2`, from the change of variables between
+    /// microfacet normals and refracted directions.
+    fn refraction_jacobian(&self, direction: Vec3, half: Vec3) -> f32 {
+        let denominator = direction.dot(half) + self.view.dot(half) / self.eta;
+        direction.dot(half).abs() / (denominator * denominator)
+    }
+
+    fn eval(&self, direction: Vec3) -> Vec3 {
+        let (cos_o, cos_i) = (self.view.dot(self.normal), direction.dot(self.normal));
+        let half = match self.half_vector(direction) {
+            Some(half) => half,
+            None => return Vec3::empty(),
+        };
+        let fresnel = fresnel_dielectric(self.view.dot(half), self.eta);
+        let masking = 1. / (1. + ggx_lambda(cos_o, self.alpha) + ggx_lambda(cos_i, self.alpha));
+        let d = ggx_d(half.dot(self.normal), self.alpha);
+        let value = if cos_i > 0. {
+            fresnel * d * masking / (4. * cos_o)
+        } else {
+            // Radiance is compressed into the smaller solid angle on the denser side, hence the 1 / eta^2.
+            (1. - fresnel) * d * masking * self.view.dot(half) * self.refraction_jacobian(direction, half)
+                / (cos_o * self.eta * self.eta)
+        };
+        Vec3::new(value, value, value)
+    }
+
+    fn pdf(&self, direction: Vec3) -> f32 {
+        let cos_o = self.view.dot(self.normal);
+        let half = match self.half_vector(direction) {
+            Some(half) => half,
+            None => return 0.,
+        };
+        let cos_d = self.view.dot(half);
+        let fresnel = fresnel_dielectric(cos_d, self.eta);
+        let visible = ggx_d(half.dot(self.normal), self.alpha) * cos_d / (cos_o * (1. + ggx_lambda(cos_o, self.alpha)));
+        if direction.dot(self.normal) > 0. {
+            fresnel * visible / (4. * cos_d)
+        } else {
+            (1. - fresnel) * visible * self.refraction_jacobian(direction, half)
+        }
+    }
+
+    /// Picks reflection or refraction off a visible microfacet in proportion to its Fresnel
+    /// reflectance, or `None` if the direction ends up on the wrong side of the surface.
+    fn sample<R: Rng>(&self, rng: &mut R) -> Option<Vec3> {
+        let frame = ONB::from_w(self.normal);
+        let view = Vec3::new(self.view.dot(frame.u), self.view.dot(frame.v), self.view.dot(frame.w));
+        let half = frame.local(sample_ggx_visible_normal(view, self.alpha, rng));
+        let cos_d = self.view.dot(half);
+        let direction = if rng.gen::<f32>() < fresnel_dielectric(cos_d, self.eta) {
+            reflect(-self.view, half)
+        } else {
+            let cos_t = (1. - (1. - cos_d * cos_d) / (self.eta * self.eta)).max(0.).sqrt();
+            -self.view / self.eta + half * (cos_d / self.eta - cos_t)
+        };
+        let reflected = direction.dot(self.normal) > 0.;
+        if reflected == (direction.dot(half) > 0.) {
+            Some(direction)
+        } else {
+            None
+        }
+    }
+}
+
 impl Material {
-    pub fn lambertian(albedo: Rc<dyn Texture>) -> Material {
+    pub fn lambertian(albedo: Arc<dyn Texture>) -> Material {
         Material::Lambertian { albedo }
     }
     pub fn metal(albedo: Vec3, fuzz: f32) -> Material {
@@ -56,73 +352,509 @@ impl Material {
         }
     }
     pub fn dielectric(ref_idx: f32) -> Material {
-        Material::Dielectric { ref_idx }
+        Material::glass(RefractiveIndex::Constant(ref_idx), None, Vec3::empty())
     }
-    pub fn isotropic(albedo: Rc<dyn Texture>) -> Material {
+    /// A dielectric with an optional roughness texture, absorbing `absorption` per unit distance inside.
+    pub fn glass(ref_idx: RefractiveIndex, roughness: Option<Arc<dyn Texture>>, absorption: Vec3) -> Material {
+        Material::Dielectric {
+            ref_idx,
+            roughness,
+            absorption,
+        }
+    }
+    pub fn isotropic(albedo: Arc<dyn Texture>) -> Material {
         Material::Isotropic { albedo }
     }
-    pub fn diffuse_light(emit: Rc<dyn Texture>) -> Material {
-        Material::DiffuseLight { emit }
+    pub fn diffuse_light(emit: Arc<dyn Texture>) -> Material {
+        Material::DiffuseLight { emit, one_sided: false }
+    }
+    /// A light that only shines from its front face.
+    pub fn one_sided_light(emit: Arc<dyn Texture>) -> Material {
+        Material::DiffuseLight { emit, one_sided: true }
+    }
+    pub fn microfacet(
+        albedo: Arc<dyn Texture>,
+        roughness: Arc<dyn Texture>,
+        metallic: Arc<dyn Texture>,
+        ior: f32,
+    ) -> Material {
+        Material::Microfacet {
+            albedo,
+            roughness,
+            metallic,
+            ior,
+        }
+    }
+
+    fn microfacet_surface(&self, ray_in: &Ray, record: &HitRecord) -> Option<MicrofacetSurface> {
+        match self {
+            Material::Microfacet {
+                albedo,
+                roughness,
+                metallic,
+                ior,
+            } => {
+                let view = -ray_in.direction.unit_vector();
+                let normal = record.facing_normal();
+                Some(MicrofacetSurface {
+                    albedo: color_at(ray_in.wavelengths, texture_at(albedo.as_ref(), ray_in, record)),
+                    alpha: ggx_alpha(texture_at(roughness.as_ref(), ray_in, record).x),
+                    metallic: texture_at(metallic.as_ref(), ray_in, record).x.clamp(0., 1.),
+                    ior: *ior,
+                    normal,
+                    view,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The microfacet interface of a dielectric at a hit point, or `None` where it is smooth.
+    fn rough_dielectric_surface(&self, ray_in: &Ray, record: &HitRecord) -> Option<RoughDielectricSurface> {
+        match self {
+            Material::Dielectric {
+                ref_idx,
+                roughness: Some(roughness),
+                ..
+            } => {
+                let alpha = ggx_alpha(texture_at(roughness.as_ref(), ray_in, record).x);
+                if alpha <= MIN_ALPHA {
+                    return None;
+                }
+                let view = -ray_in.direction.unit_vector();
+                let ref_idx = ref_idx.for_path(ray_in.wavelengths);
+                let (normal, eta) = if record.front_face {
+                    (record.normal, ref_idx)
+                } else {
+                    (-record.normal, 1. / ref_idx)
+                };
+                Some(RoughDielectricSurface {
+                    alpha,
+                    eta,
+                    normal,
+                    view,
+                })
+            }
+            _ => None,
+        }
     }
-    pub fn scatter<R: Rng>(
-        &self,
-        ray_in: &Ray,
-        record: &HitRecord,
-        rng: &mut R,
-    ) -> Option<(Vec3, Ray)> {
+    pub fn scatter<R: Rng>(&self, ray_in: &Ray, record: &HitRecord, rng: &mut R) -> Option<ScatterRecord> {
         match self {
-            Material::Lambertian { albedo } => {
-                let target = record.p + record.normal + random_in_unit_sphere(rng);
-                Some((
-                    albedo.value(record.u, record.v, record.p),
-                    Ray::new(record.p, target - record.p, ray_in.time),
-                ))
+            Material::Lambertian { .. } => {
+                let direction = ONB::from_w(record.facing_normal()).local(random_cosine_direction(rng));
+                self.diffuse_scatter(ray_in, record, direction)
+            }
+            Material::Isotropic { .. } => self.diffuse_scatter(ray_in, record, random_unit_vector(rng)),
+            Material::Microfacet { .. } => {
+                let direction = self.microfacet_surface(ray_in, record)?.sample(rng);
+                self.diffuse_scatter(ray_in, record, direction)
             }
             Material::Metal { albedo, fuzz } => {
-                let reflected = reflect(ray_in.direction.unit_vector(), record.normal);
-                let scattered = Ray::new(record.p, reflected + random_in_unit_sphere(rng) * *fuzz, ray_in.time);
-                if scattered.direction.dot(record.normal) > 0. {
-                    Some((*albedo, scattered))
+                let normal = record.facing_normal();
+                let reflected = reflect(ray_in.direction.unit_vector(), normal);
+                let scattered = Ray::new(record.p, reflected + random_in_unit_sphere(rng) * *fuzz, ray_in.time)
+                    .with_wavelengths(ray_in.wavelengths);
+                if scattered.direction.dot(normal) > 0. {
+                    Some(ScatterRecord::Specular {
+                        ray: scattered,
+                        attenuation: color_at(ray_in.wavelengths, *albedo),
+                    })
                 } else {
                     None
                 }
             }
-            Material::Dielectric { ref_idx } => {
+            Material::Dielectric {
+                ref_idx: index,
+                absorption,
+                ..
+            } => {
+                if let Some(surface) = self.rough_dielectric_surface(ray_in, record) {
+                    let direction = surface.sample(rng)?;
+                    let (wavelengths, _) = dielectric_throughput(index, *absorption, ray_in, record);
+                    return self.diffuse_scatter(ray_in, record, direction).map(|scatter| match scatter {
+                        ScatterRecord::Diffuse { ray, pdf, eval } => ScatterRecord::Diffuse {
+                            ray: ray.with_wavelengths(wavelengths),
+                            pdf,
+                            eval,
+                        },
+                        specular => specular,
+                    });
+                }
+                let ref_idx = index.for_path(ray_in.wavelengths);
                 let reflected = reflect(ray_in.direction, record.normal);
-                let (outward_normal, ni_over_nt, cosine) = if ray_in.direction.dot(record.normal)
-                    > 0.
-                {
-                    (-record.normal, *ref_idx, ref_idx * ray_in.direction.dot(record.normal) / ray_in.direction.length())
-                } else {
+                let (outward_normal, ni_over_nt, cosine) = if record.front_face {
                     let cosine = -ray_in.direction.dot(record.normal) / ray_in.direction.length();
                     (record.normal, 1. / ref_idx, cosine)
+                } else {
+                    (-record.normal, ref_idx, ref_idx * ray_in.direction.dot(record.normal) / ray_in.direction.length())
                 };
-                let attenuation = Vec3::new(1., 1., 1.);
-                match refract(ray_in.direction, outward_normal, ni_over_nt) {
-                    Some(refracted) => {
-                        let reflect_prob = schlick(cosine, *ref_idx);
-                        if rng.gen::<f32>() < reflect_prob {
-                            Some((attenuation, Ray::new(record.p, reflected, ray_in.time)))
-                        } else {
-                            Some((attenuation, Ray::new(record.p, refracted, ray_in.time)))
-                        }
-                    }
-                    None => Some((attenuation, Ray::new(record.p, reflected, ray_in.time))),
-                }
+                let direction = match refract(ray_in.direction, outward_normal, ni_over_nt) {
+                    Some(refracted) if rng.gen::<f32>() >= schlick(cosine, ref_idx) => refracted,
+                    _ => reflected,
+                };
+                let (wavelengths, attenuation) = dielectric_throughput(index, *absorption, ray_in, record);
+                Some(ScatterRecord::Specular {
+                    ray: Ray::new(record.p, direction, ray_in.time).with_wavelengths(wavelengths),
+                    attenuation,
+                })
             }
-
             Material::DiffuseLight { .. } => None,
-            Material::Isotropic { albedo } => Some((
-                albedo.value(record.u, record.v, record.p),
-                Ray::new(record.p, random_in_unit_sphere(rng), ray_in.time),
-            )),
         }
     }
 
-    pub fn emitted(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+    fn diffuse_scatter(&self, ray_in: &Ray, record: &HitRecord, direction: Vec3) -> Option<ScatterRecord> {
+        let pdf = self.scattering_pdf(ray_in, record, direction);
+        if pdf <= 0. {
+            return None;
+        }
+        Some(ScatterRecord::Diffuse {
+            ray: Ray::new(record.p, direction, ray_in.time).with_wavelengths(ray_in.wavelengths),
+            pdf,
+            eval: self.eval(ray_in, record, direction),
+        })
+    }
+
+    /// The BSDF times the cosine of `direction` with the normal (the phase function for media),
+    /// for light leaving along `direction` and arriving along `ray_in`. Zero for the specular materials.
+    #[allow(unused_variables)]
+    pub fn eval(&self, ray_in: &Ray, record: &HitRecord, direction: Vec3) -> Vec3 {
+        match self {
+            Material::Lambertian { albedo } | Material::Isotropic { albedo } => {
+                color_at(ray_in.wavelengths, texture_at(albedo.as_ref(), ray_in, record))
+                    * self.scattering_pdf(ray_in, record, direction)
+            }
+            Material::Microfacet { .. } => match self.microfacet_surface(ray_in, record) {
+                Some(surface) => surface.eval(direction.unit_vector()),
+                None => Vec3::empty(),
+            },
+            Material::Dielectric {
+                ref_idx, absorption, ..
+            } => match self.rough_dielectric_surface(ray_in, record) {
+                Some(surface) => {
+                    surface.eval(direction.unit_vector()) * dielectric_throughput(ref_idx, *absorption, ray_in, record).1
+                }
+                None => Vec3::empty(),
+            },
+            _ => Vec3::empty(),
+        }
+    }
+
+    /// Solid-angle density with which `scatter` picks `direction`. Zero for the specular materials,
+    /// whose lobes can't be hit by a direction chosen any other way.
+    #[allow(unused_variables)]
+    pub fn scattering_pdf(&self, ray_in: &Ray, record: &HitRecord, direction: Vec3) -> f32 {
+        match self {
+            Material::Lambertian { .. } => {
+                let cosine = record.facing_normal().dot(direction.unit_vector());
+                cosine.max(0.) / PI
+            }
+            Material::Isotropic { .. } => 1. / (4. * PI),
+            Material::Microfacet { .. } => match self.microfacet_surface(ray_in, record) {
+                Some(surface) => surface.pdf(direction.unit_vector()),
+                None => 0.,
+            },
+            Material::Dielectric { .. } => match self.rough_dielectric_surface(ray_in, record) {
+                Some(surface) => surface.pdf(direction.unit_vector()),
+                None => 0.,
+            },
+            _ => 0.,
+        }
+    }
+
+    pub fn emits(&self) -> bool {
+        matches!(self, Material::DiffuseLight { .. })
+    }
+
+    /// Light given off at `record`, towards the side the ray that found it came from.
+    pub fn emitted(&self, record: &HitRecord) -> Vec3 {
         match self {
-            Material::DiffuseLight { emit } => emit.value(u, v, p),
+            Material::DiffuseLight { emit, one_sided } if record.front_face || !one_sided => {
+                emit.value(record.u, record.v, record.p)
+            }
             _ => Vec3::empty(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::REFERENCE_WAVELENGTH;
+    use crate::textures::ConstantTexture;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn lambertian_samples_are_cosine_weighted() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let albedo = Vec3::new(0.2, 0.4, 0.6);
+        let material = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(albedo))));
+        let normal = Vec3::new(1., 2., -0.5).unit_vector();
+        let record = HitRecord {
+            t: 1.,
+            u: 0.,
+            v: 0.,
+            p: Vec3::empty(),
+            normal,
+            geometric_normal: normal,
+            front_face: true,
+            dpdu: Vec3::new(1., 0., 0.),
+            dpdv: Vec3::new(0., 1., 0.),
+            material: Arc::clone(&material),
+        };
+        let ray_in = Ray::new(normal * 2., -normal, 0.);
+        let count = 100_000;
+        let mut cosine_sum = 0.;
+        for _ in 0..count {
+            match material.scatter(&ray_in, &record, &mut rng) {
+                Some(ScatterRecord::Diffuse { ray, pdf, eval }) => {
+                    let cosine = ray.direction.unit_vector().dot(normal);
+                    assert!(cosine >= 0.);
+                    assert!((pdf - cosine / PI).abs() < 1e-4);
+                    assert!((eval / pdf - albedo).length() < 1e-4);
+                    cosine_sum += cosine;
+                }
+                other => panic!("expected a diffuse lobe, got {:?}", other),
+            }
+        }
+        // The mean cosine of a cosine-weighted hemisphere is 2/3.
+        assert!((cosine_sum / count as f32 - 2. / 3.).abs() < 0.01);
+        assert_eq!(material.scattering_pdf(&ray_in, &record, -normal), 0.);
+    }
+
+    #[test]
+    fn diffuse_surfaces_are_two_sided_and_lights_can_be_one_sided() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let white = Arc::new(ConstantTexture::new(Vec3::new(1., 1., 1.)));
+        let lambertian = Arc::new(Material::lambertian(white.clone()));
+        let record = hit_record(&lambertian);
+        let from_below = Ray::new(Vec3::new(0., 0., -1.), Vec3::new(0., 0.3, 1.), 0.);
+        let back = seen_by(&record, &from_below);
+        assert!(!back.front_face);
+        for _ in 0..100 {
+            match lambertian.scatter(&from_below, &back, &mut rng) {
+                Some(ScatterRecord::Diffuse { ray, .. }) => assert!(ray.direction.z < 0.),
+                other => panic!("expected a diffuse lobe, got {:?}", other),
+            }
+        }
+
+        let above = Ray::new(Vec3::new(0., 0., 1.), Vec3::new(0., 0.3, -1.), 0.);
+        let two_sided = Material::diffuse_light(white.clone());
+        let one_sided = Material::one_sided_light(white);
+        for ray in &[above, from_below] {
+            assert!((two_sided.emitted(&seen_by(&record, ray)) - Vec3::new(1., 1., 1.)).length() < 1e-6);
+        }
+        assert!((one_sided.emitted(&seen_by(&record, &above)) - Vec3::new(1., 1., 1.)).length() < 1e-6);
+        assert_eq!(one_sided.emitted(&back).length(), 0.);
+    }
+
+    fn microfacet(albedo: Vec3, roughness: f32, metallic: f32) -> Arc<Material> {
+        let constant = |value: f32| Arc::new(ConstantTexture::new(Vec3::new(value, value, value)));
+        Arc::new(Material::microfacet(
+            Arc::new(ConstantTexture::new(albedo)),
+            constant(roughness),
+            constant(metallic),
+            1.5,
+        ))
+    }
+
+    fn hit_record(material: &Arc<Material>) -> HitRecord {
+        HitRecord {
+            t: 1.,
+            u: 0.,
+            v: 0.,
+            p: Vec3::empty(),
+            normal: Vec3::new(0., 0., 1.),
+            geometric_normal: Vec3::new(0., 0., 1.),
+            front_face: true,
+            dpdu: Vec3::new(1., 0., 0.),
+            dpdv: Vec3::new(0., 1., 0.),
+            material: Arc::clone(material),
+        }
+    }
+
+    /// `record` as hit by `ray_in`, from whichever side of the surface it comes.
+    fn seen_by(record: &HitRecord, ray_in: &Ray) -> HitRecord {
+        HitRecord {
+            front_face: ray_in.direction.dot(record.geometric_normal) < 0.,
+            ..record.clone()
+        }
+    }
+
+    /// The mean of `eval / pdf` over `count` scattered rays, the fraction of the light that is reflected.
+    fn directional_albedo(material: &Material, ray_in: &Ray, record: &HitRecord, count: usize) -> Vec3 {
+        let mut rng = XorShiftRng::seed_from_u64(1);
+        mean(count, || match material.scatter(ray_in, record, &mut rng) {
+            Some(ScatterRecord::Diffuse { pdf, eval, .. }) => eval / pdf,
+            _ => Vec3::empty(),
+        })
+    }
+
+    /// The mean of `count` samples, summed in batches so that the f32 total doesn't swamp them.
+    fn mean<F: FnMut() -> Vec3>(count: usize, mut sample: F) -> Vec3 {
+        let mut total = Vec3::empty();
+        for start in (0..count).step_by(1000) {
+            let mut sum = Vec3::empty();
+            for _ in start..count.min(start + 1000) {
+                sum += sample();
+            }
+            total += sum / count as f32;
+        }
+        total
+    }
+
+    /// The integral of `f` over the sphere by the midpoint rule on a grid of cosines and azimuths.
+    fn integrate_over_sphere<F: FnMut(Vec3) -> Vec3>(mut f: F) -> Vec3 {
+        let n = 500;
+        let mut total = Vec3::empty();
+        for i in 0..n {
+            let cos = -1. + 2. * (i as f32 + 0.5) / n as f32;
+            let sin = (1. - cos * cos).sqrt();
+            let mut row = Vec3::empty();
+            for j in 0..2 * n {
+                let phi = PI * (j as f32 + 0.5) / n as f32;
+                row += f(Vec3::new(sin * phi.cos(), sin * phi.sin(), cos));
+            }
+            total += row * (2. * PI / (n * n) as f32);
+        }
+        total
+    }
+
+    /// Integrates the pdf and the BSDF over the sphere, and compares them with how often `scatter`
+    /// succeeds and with its importance-sampled estimate.
+    fn assert_sampling_matches_pdf_and_eval(material: &Material, ray_in: &Ray, record: &HitRecord) {
+        let count = 200_000;
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let pdf_integral = integrate_over_sphere(|direction| {
+            let pdf = material.scattering_pdf(ray_in, record, direction);
+            Vec3::new(pdf, pdf, pdf)
+        })
+        .x;
+        let expected = integrate_over_sphere(|direction| material.eval(ray_in, record, direction));
+        let mut scattered = 0;
+        for _ in 0..count {
+            match material.scatter(ray_in, record, &mut rng) {
+                Some(ScatterRecord::Diffuse { ray, pdf, eval }) => {
+                    assert!((pdf - material.scattering_pdf(ray_in, record, ray.direction)).abs() < 1e-3 * pdf);
+                    assert!((eval - material.eval(ray_in, record, ray.direction)).length() < 1e-4);
+                    scattered += 1;
+                }
+                None => {}
+                other => panic!("expected a glossy lobe, got {:?}", other),
+            }
+        }
+        assert!(pdf_integral <= 1.001, "{}", pdf_integral);
+        assert!((pdf_integral - scattered as f32 / count as f32).abs() < 0.005);
+        let estimate = directional_albedo(material, ray_in, record, count);
+        let tolerance = 0.01 * expected.length().max(1.);
+        assert!((estimate - expected).length() < tolerance, "{:?} != {:?}", estimate, expected);
+    }
+
+    #[test]
+    fn microfacet_sampling_matches_its_pdf_and_eval() {
+        let material = microfacet(Vec3::new(0.9, 0.6, 0.3), 0.5, 0.4);
+        let record = hit_record(&material);
+        let ray_in = Ray::new(Vec3::new(-1., 0., 1.), Vec3::new(1., 0., -1.), 0.);
+        assert_sampling_matches_pdf_and_eval(&material, &ray_in, &record);
+    }
+
+    #[test]
+    fn rough_glass_sampling_matches_its_pdf_and_eval() {
+        let material = Arc::new(Material::glass(
+            RefractiveIndex::Constant(1.5),
+            Some(Arc::new(ConstantTexture::new(Vec3::new(0.6, 0.6, 0.6)))),
+            Vec3::empty(),
+        ));
+        let record = hit_record(&material);
+        // From outside, and from inside below the critical angle and beyond it.
+        for &(x, z) in &[(-0.5, 1.), (-0.3, -1.), (-1., -0.5)] {
+            let ray_in = Ray::new(Vec3::new(x, 0., z), Vec3::new(-x, 0., -z), 0.);
+            assert_sampling_matches_pdf_and_eval(&material, &ray_in, &seen_by(&record, &ray_in));
+        }
+    }
+
+    #[test]
+    fn glass_absorbs_light_along_the_path_inside() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let absorption = Vec3::new(0.1, 0.5, 1.);
+        let material = Arc::new(Material::glass(RefractiveIndex::Constant(1.5), None, absorption));
+        let mut record = hit_record(&material);
+        record.t = 2.;
+        for _ in 0..100 {
+            // Leaving the glass after 2 units inside, and entering it from outside.
+            let leaving = Ray::new(Vec3::new(0., 0., -2.), Vec3::new(0., 0., 1.), 0.);
+            match material.scatter(&leaving, &seen_by(&record, &leaving), &mut rng) {
+                Some(ScatterRecord::Specular { attenuation, .. }) => {
+                    let expected = Vec3::new((-0.2f32).exp(), (-1f32).exp(), (-2f32).exp());
+                    assert!((attenuation - expected).length() < 1e-5, "{:?}", attenuation);
+                }
+                other => panic!("expected a specular lobe, got {:?}", other),
+            }
+            let entering = Ray::new(Vec3::new(0., 0., 2.), Vec3::new(0., 0., -1.), 0.);
+            match material.scatter(&entering, &seen_by(&record, &entering), &mut rng) {
+                Some(ScatterRecord::Specular { attenuation, .. }) => {
+                    assert!((attenuation - Vec3::new(1., 1., 1.)).length() < 1e-6)
+                }
+                other => panic!("expected a specular lobe, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn dispersive_glass_bends_each_wavelength_differently() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        // Schott N-BK7.
+        let bk7 = RefractiveIndex::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        };
+        let material = Arc::new(Material::glass(bk7, None, Vec3::empty()));
+        let record = hit_record(&material);
+        let (sin, cos) = 60f32.to_radians().sin_cos();
+        let refracted = |wavelengths: Option<Wavelengths>, rng: &mut XorShiftRng| loop {
+            let ray_in = Ray::new(Vec3::new(-sin, 0., cos), Vec3::new(sin, 0., -cos), 0.).with_wavelengths(wavelengths);
+            match material.scatter(&ray_in, &record, rng) {
+                Some(ScatterRecord::Specular { ray, attenuation }) if ray.direction.z < 0. => {
+                    return (ray, attenuation);
+                }
+                Some(ScatterRecord::Specular { .. }) => continue,
+                other => panic!("expected a specular lobe, got {:?}", other),
+            }
+        };
+        let sin_refracted = |ray: &Ray| ray.direction.unit_vector().x;
+        let (blue, attenuation) = refracted(Some(Wavelengths::new(400.)), &mut rng);
+        let (red, _) = refracted(Some(Wavelengths::new(700.)), &mut rng);
+        assert!((sin_refracted(&blue) - sin / bk7.at(400.)).abs() < 1e-4);
+        assert!((sin_refracted(&red) - sin / bk7.at(700.)).abs() < 1e-4);
+        assert!(sin_refracted(&blue) < sin_refracted(&red) - 1e-3);
+        // Only the hero wavelength goes on, carrying the weight of all three, and only once.
+        assert!((attenuation - Vec3::new(3., 0., 0.)).length() < 1e-6, "{:?}", attenuation);
+        let terminated = blue.wavelengths.unwrap();
+        assert!(terminated.secondary_terminated);
+        let (_, attenuation) = refracted(Some(terminated), &mut rng);
+        assert!((attenuation - Vec3::new(1., 1., 1.)).length() < 1e-6, "{:?}", attenuation);
+        // In RGB the index is taken at the reference wavelength.
+        let (rgb, attenuation) = refracted(None, &mut rng);
+        assert!((sin_refracted(&rgb) - sin / bk7.at(REFERENCE_WAVELENGTH)).abs() < 1e-4);
+        assert!(rgb.wavelengths.is_none() && (attenuation - Vec3::new(1., 1., 1.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn microfacet_reflects_at_most_the_incoming_light() {
+        let white = Vec3::new(1., 1., 1.);
+        for &(roughness, metallic) in &[(0., 1.), (0.3, 1.), (1., 1.), (0., 0.), (0.5, 0.), (1., 0.5)] {
+            let material = microfacet(white, roughness, metallic);
+            let record = hit_record(&material);
+            for &angle in &[0f32, 45., 80.] {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let ray_in = Ray::new(Vec3::new(sin, 0., cos), Vec3::new(-sin, 0., -cos), 0.);
+                let albedo = directional_albedo(&material, &ray_in, &record, 20_000).x;
+                assert!(albedo <= 1.01, "roughness {} metallic {} at {}: {}", roughness, metallic, angle, albedo);
+                // A smooth white mirror loses almost nothing.
+                if roughness == 0. && metallic == 1. {
+                    assert!(albedo > 0.97, "{}", albedo);
+                }
+            }
+        }
+    }
+}
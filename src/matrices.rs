@@ -0,0 +1,347 @@
+use crate::vectors::Vec3;
+use std::f32::consts::PI;
+use std::ops::Mul;
+
+/// A 4x4 matrix acting on column vectors, used for affine transforms.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f32; 4]; 4]) -> Matrix4 {
+        Matrix4 { m }
+    }
+
+    pub fn identity() -> Matrix4 {
+        Matrix4::scaling(Vec3::new(1., 1., 1.))
+    }
+
+    pub fn translation(offset: Vec3) -> Matrix4 {
+        Matrix4::new([
+            [1., 0., 0., offset.x],
+            [0., 1., 0., offset.y],
+            [0., 0., 1., offset.z],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn scaling(factors: Vec3) -> Matrix4 {
+        Matrix4::new([
+            [factors.x, 0., 0., 0.],
+            [0., factors.y, 0., 0.],
+            [0., 0., factors.z, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Counter-clockwise rotation by `angle` degrees around the x axis.
+    pub fn rotation_x(angle: f32) -> Matrix4 {
+        let (sin, cos) = (angle * PI / 180.).sin_cos();
+        Matrix4::new([
+            [1., 0., 0., 0.],
+            [0., cos, -sin, 0.],
+            [0., sin, cos, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Counter-clockwise rotation by `angle` degrees around the y axis, matching `RotateY`.
+    pub fn rotation_y(angle: f32) -> Matrix4 {
+        let (sin, cos) = (angle * PI / 180.).sin_cos();
+        Matrix4::new([
+            [cos, 0., sin, 0.],
+            [0., 1., 0., 0.],
+            [-sin, 0., cos, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Counter-clockwise rotation by `angle` degrees around the z axis.
+    pub fn rotation_z(angle: f32) -> Matrix4 {
+        let (sin, cos) = (angle * PI / 180.).sin_cos();
+        Matrix4::new([
+            [cos, -sin, 0., 0.],
+            [sin, cos, 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Moves the origin to `from` and turns the object's +z axis towards `at`, keeping its +y axis
+    /// as close to `up` as possible.
+    pub fn look_at(from: Vec3, at: Vec3, up: Vec3) -> Matrix4 {
+        let w = (at - from).unit_vector();
+        let u = up.cross(w).unit_vector();
+        let v = w.cross(u);
+        Matrix4::new([
+            [u.x, v.x, w.x, from.x],
+            [u.y, v.y, w.y, from.y],
+            [u.z, v.z, w.z, from.z],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    /// The inverse by Gauss-Jordan elimination with partial pivoting, or `None` for a singular matrix.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inverse = Matrix4::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let scale = 1. / a[column][column];
+            for k in 0..4 {
+                a[column][k] *= scale;
+                inverse[column][k] *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[column][k];
+                        inverse[row][k] -= factor * inverse[column][k];
+                    }
+                }
+            }
+        }
+        Some(Matrix4 { m: inverse })
+    }
+
+    /// The determinant of the upper-left 3x3 block, the factor by which the transform scales volumes.
+    pub fn linear_determinant(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    /// Transforms a direction, ignoring the translation.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+/// A rotation as a unit quaternion `w + xi + yj + zk`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, o: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            x: self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            y: self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            z: self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        }
+    }
+}
+
+impl Quaternion {
+    pub fn identity() -> Quaternion {
+        Quaternion {
+            w: 1.,
+            x: 0.,
+            y: 0.,
+            z: 0.,
+        }
+    }
+
+    /// Counter-clockwise rotation by `angle` degrees around `axis`.
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Quaternion {
+        let axis = axis.unit_vector();
+        let (sin, cos) = (angle * PI / 360.).sin_cos();
+        Quaternion {
+            w: cos,
+            x: axis.x * sin,
+            y: axis.y * sin,
+            z: axis.z * sin,
+        }
+    }
+
+    pub fn dot(self, other: Quaternion) -> f32 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn conjugate(self) -> Quaternion {
+        Quaternion {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    fn scaled(self, factor: f32) -> Quaternion {
+        Quaternion {
+            w: self.w * factor,
+            x: self.x * factor,
+            y: self.y * factor,
+            z: self.z * factor,
+        }
+    }
+
+    fn add(self, other: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w + other.w,
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+
+    pub fn normalize(self) -> Quaternion {
+        self.scaled(1. / self.dot(self).sqrt())
+    }
+
+    /// The angle of the rotation from `self` to `other`, in radians.
+    pub fn angle_to(self, other: Quaternion) -> f32 {
+        2. * self.dot(other).abs().min(1.).acos()
+    }
+
+    /// Spherical linear interpolation along the shorter arc, `t` going from 0 (`self`) to 1 (`other`).
+    pub fn slerp(self, other: Quaternion, t: f32) -> Quaternion {
+        let mut cos_theta = self.dot(other);
+        let other = if cos_theta < 0. {
+            cos_theta = -cos_theta;
+            other.scaled(-1.)
+        } else {
+            other
+        };
+        if cos_theta > 0.9995 {
+            return self.scaled(1. - t).add(other.scaled(t)).normalize();
+        }
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        self.scaled(((1. - t) * theta).sin() / sin_theta)
+            .add(other.scaled((t * theta).sin() / sin_theta))
+    }
+
+    pub fn rotate(self, v: Vec3) -> Vec3 {
+        let q = Vec3::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.;
+        v + t * self.w + q.cross(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn inverse_undoes_a_composed_transform() {
+        let matrix = Matrix4::translation(Vec3::new(1., -2., 3.))
+            * Matrix4::rotation_x(30.)
+            * Matrix4::rotation_z(-45.)
+            * Matrix4::scaling(Vec3::new(2., 0.5, 3.));
+        let inverse = matrix.inverse().unwrap();
+        let p = Vec3::new(0.3, -1.2, 4.);
+        assert_close(inverse.transform_point(matrix.transform_point(p)), p);
+        assert_close((matrix * inverse).transform_point(p), p);
+        assert!(Matrix4::scaling(Vec3::new(1., 0., 1.)).inverse().is_none());
+    }
+
+    #[test]
+    fn rotations_follow_the_right_hand_rule() {
+        assert_close(
+            Matrix4::rotation_x(90.).transform_vector(Vec3::new(0., 1., 0.)),
+            Vec3::new(0., 0., 1.),
+        );
+        assert_close(
+            Matrix4::rotation_y(90.).transform_vector(Vec3::new(0., 0., 1.)),
+            Vec3::new(1., 0., 0.),
+        );
+        assert_close(
+            Matrix4::rotation_z(90.).transform_vector(Vec3::new(1., 0., 0.)),
+            Vec3::new(0., 1., 0.),
+        );
+        let quarter_turn = Quaternion::from_axis_angle(Vec3::new(0., 0., 1.), 90.);
+        assert_close(
+            quarter_turn.rotate(Vec3::new(1., 0., 0.)),
+            Vec3::new(0., 1., 0.),
+        );
+        let look_at = Matrix4::look_at(
+            Vec3::new(1., 1., 1.),
+            Vec3::new(1., 1., 5.),
+            Vec3::new(0., 1., 0.),
+        );
+        assert_close(
+            look_at.transform_point(Vec3::new(0., 0., 2.)),
+            Vec3::new(1., 1., 3.),
+        );
+    }
+
+    #[test]
+    fn slerp_rotates_at_constant_speed() {
+        let start = Quaternion::from_axis_angle(Vec3::new(0., 1., 0.), 10.);
+        let end = Quaternion::from_axis_angle(Vec3::new(0., 1., 0.), 130.);
+        let halfway = start.slerp(end, 0.5);
+        assert_close(
+            halfway.rotate(Vec3::new(0., 0., 1.)),
+            Matrix4::rotation_y(70.).transform_vector(Vec3::new(0., 0., 1.)),
+        );
+        assert!((start.angle_to(start.slerp(end, 0.25)) - 30f32.to_radians()).abs() < 1e-4);
+        // q and -q are the same rotation, so slerp must not take the long way round.
+        assert!(
+            (start
+                .slerp(end.scaled(-1.), 0.5)
+                .rotate(Vec3::new(0., 0., 1.))
+                - halfway.rotate(Vec3::new(0., 0., 1.)))
+            .length()
+                < 1e-4
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use crate::hitables::bvh::BvhBuilder;
+use crate::hitables::triangles::{MeshData, TriangleMesh};
+use crate::hitables::Hitable;
+use crate::materials::Material;
+use crate::textures::{load_image, ConstantTexture, ImageSettings, MissingTextures, Texture};
+use crate::vectors::Vec3;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::SplitWhitespace;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: String, error: io::Error },
+    Parse { path: String, line: usize, message: String },
+    NoFaces(String),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => write!(f, "{}: {}", path, error),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path, line, message),
+            ObjError::NoFaces(path) => write!(f, "{}: no faces", path),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Reports parse errors against a file name and line number.
+struct Location<'a> {
+    path: &'a str,
+    line: usize,
+}
+
+impl<'a> Location<'a> {
+    fn error<T>(&self, message: String) -> Result<T, ObjError> {
+        Err(ObjError::Parse {
+            path: self.path.to_owned(),
+            line: self.line,
+            message,
+        })
+    }
+
+    fn floats(&self, words: SplitWhitespace, count: usize, keyword: &str) -> Result<Vec<f32>, ObjError> {
+        let values = words
+            .take(count)
+            .map(|word| word.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>();
+        match values {
+            Ok(values) if values.len() == count => Ok(values),
+            _ => self.error(format!("expected {} numbers after `{}`", count, keyword)),
+        }
+    }
+
+    fn vec3(&self, words: SplitWhitespace, keyword: &str) -> Result<Vec3, ObjError> {
+        let values = self.floats(words, 3, keyword)?;
+        Ok(Vec3::new(values[0], values[1], values[2]))
+    }
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io {
+        path: path.display().to_string(),
+        error,
+    })
+}
+
+#[derive(Clone, Debug)]
+struct MtlEntry {
+    diffuse: Vec3,
+    specular: Vec3,
+    emission: Vec3,
+    shininess: f32,
+    ior: f32,
+    dissolve: f32,
+    illum: u32,
+    diffuse_map: Option<Arc<dyn Texture>>,
+}
+
+impl Default for MtlEntry {
+    fn default() -> MtlEntry {
+        MtlEntry {
+            diffuse: Vec3::new(0.8, 0.8, 0.8),
+            specular: Vec3::empty(),
+            emission: Vec3::empty(),
+            shininess: 0.,
+            ior: 1.5,
+            dissolve: 1.,
+            illum: 2,
+            diffuse_map: None,
+        }
+    }
+}
+
+fn is_black(color: Vec3) -> bool {
+    color.x <= 0. && color.y <= 0. && color.z <= 0.
+}
+
+impl MtlEntry {
+    /// Emitters (`Ke`) become diffuse lights, transparent or refractive illumination models dielectrics,
+    /// mirror illumination models metals (fuzz from `Ns`), and everything else lambertian.
+    fn material(&self) -> Material {
+        if !is_black(self.emission) {
+            Material::diffuse_light(Arc::new(ConstantTexture::new(self.emission)))
+        } else if self.dissolve < 1. || [4, 6, 7, 9].contains(&self.illum) {
+            Material::dielectric(self.ior)
+        } else if [3, 5, 8].contains(&self.illum) && !is_black(self.specular) {
+            Material::metal(self.specular, (2. / (self.shininess + 2.)).sqrt())
+        } else {
+            match &self.diffuse_map {
+                Some(texture) => Material::lambertian(Arc::clone(texture)),
+                None => Material::lambertian(Arc::new(ConstantTexture::new(self.diffuse))),
+            }
+        }
+    }
+}
+
+/// Parses a Wavefront MTL library. Texture maps are looked up relative to `base_dir`, and `missing`
+/// decides whether those that can't be loaded are errors.
+pub fn parse_mtl(
+    text: &str,
+    path: &str,
+    base_dir: &Path,
+    missing: MissingTextures,
+) -> Result<HashMap<String, Arc<Material>>, ObjError> {
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let location = Location { path, line: number + 1 };
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        if keyword == "newmtl" {
+            match words.next() {
+                Some(name) => entries.push((name.to_owned(), MtlEntry::default())),
+                None => return location.error(String::from("`newmtl` needs a name")),
+            }
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => return location.error(format!("`{}` before any `newmtl`", keyword)),
+        };
+        match keyword {
+            "Kd" => entry.diffuse = location.vec3(words, keyword)?,
+            "Ks" => entry.specular = location.vec3(words, keyword)?,
+            "Ke" => entry.emission = location.vec3(words, keyword)?,
+            "Ns" => entry.shininess = location.floats(words, 1, keyword)?[0],
+            "Ni" => entry.ior = location.floats(words, 1, keyword)?[0],
+            "d" => entry.dissolve = location.floats(words, 1, keyword)?[0],
+            "Tr" => entry.dissolve = 1. - location.floats(words, 1, keyword)?[0],
+            "illum" => {
+                entry.illum = match words.next().and_then(|word| word.parse().ok()) {
+                    Some(illum) => illum,
+                    None => return location.error(String::from("expected an integer after `illum`")),
+                }
+            }
+            "map_Kd" => {
+                // Options such as `-s 1 1 1` come before the file name, which is the last word.
+                let file = match words.last() {
+                    Some(file) => base_dir.join(file),
+                    None => return location.error(String::from("`map_Kd` needs a file name")),
+                };
+                match load_image(&file.to_string_lossy(), ImageSettings::default(), missing) {
+                    Ok(image) => entry.diffuse_map = Some(Arc::new(image)),
+                    Err(error) => return location.error(error.to_string()),
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(entries
+        .into_iter()
+        .map(|(name, entry)| (name, Arc::new(entry.material())))
+        .collect())
+}
+
+/// One corner of a face: indices into the position, texture coordinate and normal lists.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Resolves a 1-based (or negative, relative to the end) OBJ index against a list of `count` items.
+fn resolve_index(location: &Location, word: &str, count: usize) -> Result<usize, ObjError> {
+    let index = match word.parse::<i64>() {
+        Ok(index) => index,
+        Err(_) => return location.error(format!("invalid index `{}`", word)),
+    };
+    let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return location.error(format!("index {} out of range (1 to {})", index, count));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_corner(
+    location: &Location,
+    word: &str,
+    counts: (usize, usize, usize),
+) -> Result<Corner, ObjError> {
+    let mut parts = word.split('/');
+    let position = resolve_index(location, parts.next().unwrap_or(""), counts.0)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve_index(location, part, counts.1)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve_index(location, part, counts.2)?),
+    };
+    if parts.next().is_some() {
+        return location.error(format!("malformed face vertex `{}`", word));
+    }
+    Ok((position, uv, normal))
+}
+
+/// Faces that share a material, to be turned into one triangle mesh.
+#[derive(Default)]
+struct Group {
+    triangles: Vec<[Corner; 3]>,
+}
+
+impl Group {
+    fn mesh(&self, positions: &[Vec3], uvs: &[(f32, f32)], normals: &[Vec3], material: Arc<Material>) -> MeshData {
+        let corners = || self.triangles.iter().flat_map(|triangle| triangle.iter());
+        let has_uvs = corners().all(|corner| corner.1.is_some());
+        let has_normals = corners().all(|corner| corner.2.is_some());
+        let mut vertices: HashMap<Corner, usize> = HashMap::new();
+        let mut mesh = MeshData {
+            positions: Vec::new(),
+            normals: if has_normals { Some(Vec::new()) } else { None },
+            uvs: if has_uvs { Some(Vec::new()) } else { None },
+            indices: Vec::with_capacity(self.triangles.len()),
+            material,
+        };
+        for triangle in &self.triangles {
+            let mut face = [0; 3];
+            for (k, corner) in triangle.iter().enumerate() {
+                face[k] = *vertices.entry(*corner).or_insert_with(|| {
+                    mesh.positions.push(positions[corner.0]);
+                    if let (Some(mesh_uvs), Some(uv)) = (mesh.uvs.as_mut(), corner.1) {
+                        mesh_uvs.push(uvs[uv]);
+                    }
+                    if let (Some(mesh_normals), Some(normal)) = (mesh.normals.as_mut(), corner.2) {
+                        mesh_normals.push(normals[normal]);
+                    }
+                    mesh.positions.len() - 1
+                });
+            }
+            mesh.indices.push(face);
+        }
+        mesh
+    }
+}
+
+/// Parses a Wavefront OBJ file into one BVH over a triangle mesh per material.
+///
+/// Material libraries and their texture maps are looked up relative to `base_dir`, with `missing`
+/// deciding about texture maps that can't be loaded; faces without a `usemtl` get `default_material`.
+/// The BVHs are built with `bvh`.
+pub fn parse_obj<R: Rng>(
+    text: &str,
+    path: &str,
+    base_dir: &Path,
+    default_material: Arc<Material>,
+    missing: MissingTextures,
+    bvh: BvhBuilder,
+    rng: &mut R,
+) -> Result<Arc<dyn Hitable>, ObjError> {
+    let (mut positions, mut uvs, mut normals) = (Vec::new(), Vec::new(), Vec::new());
+    let mut materials: HashMap<String, Arc<Material>> = HashMap::new();
+    let mut groups: Vec<(Option<String>, Group)> = vec![(None, Group::default())];
+    for (number, line) in text.lines().enumerate() {
+        let location = Location { path, line: number + 1 };
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) if !keyword.starts_with('#') => keyword,
+            _ => continue,
+        };
+        match keyword {
+            "v" => positions.push(location.vec3(words, keyword)?),
+            "vn" => normals.push(location.vec3(words, keyword)?.unit_vector()),
+            "vt" => {
+                let values = location.floats(words, 2, keyword)?;
+                uvs.push((values[0], values[1]));
+            }
+            "f" => {
+                let counts = (positions.len(), uvs.len(), normals.len());
+                let corners = words
+                    .map(|word| parse_corner(&location, word, counts))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return location.error(format!("a face needs at least 3 vertices, got {}", corners.len()));
+                }
+                let group = &mut groups.last_mut().expect("there is always a current group").1;
+                for k in 1..corners.len() - 1 {
+                    group.triangles.push([corners[0], corners[k], corners[k + 1]]);
+                }
+            }
+            "usemtl" => {
+                let name = match words.next() {
+                    Some(name) => name.to_owned(),
+                    None => return location.error(String::from("`usemtl` needs a material name")),
+                };
+                if !materials.contains_key(&name) {
+                    return location.error(format!("unknown material `{}`", name));
+                }
+                groups.push((Some(name), Group::default()));
+            }
+            "mtllib" => {
+                for file in words {
+                    let mtl_path = base_dir.join(file);
+                    let mtl_dir = mtl_path.parent().unwrap_or(base_dir).to_owned();
+                    let library = parse_mtl(&read(&mtl_path)?, &mtl_path.display().to_string(), &mtl_dir, missing)?;
+                    materials.extend(library);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut meshes: Vec<Arc<dyn Hitable>> = Vec::new();
+    for (name, group) in groups.iter().filter(|(_, group)| !group.triangles.is_empty()) {
+        let material = match name {
+            Some(name) => Arc::clone(&materials[name]),
+            None => Arc::clone(&default_material),
+        };
+        meshes.push(Arc::new(TriangleMesh::new(group.mesh(&positions, &uvs, &normals, material), bvh, rng)));
+    }
+    match meshes.len() {
+        0 => Err(ObjError::NoFaces(path.to_owned())),
+        1 => Ok(meshes.remove(0)),
+        _ => Ok(Arc::new(bvh.build(&mut meshes, 0., 1., rng))),
+    }
+}
+
+pub fn load_obj<R: Rng>(
+    path: &str,
+    default_material: Arc<Material>,
+    missing: MissingTextures,
+    bvh: BvhBuilder,
+    rng: &mut R,
+) -> Result<Arc<dyn Hitable>, ObjError> {
+    let file = Path::new(path);
+    let base_dir = file.parent().unwrap_or_else(|| Path::new("."));
+    parse_obj(&read(file)?, path, base_dir, default_material, missing, bvh, rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::RefractiveIndex;
+    use crate::test_fixtures::{down, material};
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    fn parse(text: &str) -> Result<Arc<dyn Hitable>, ObjError> {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        parse_obj(text, "test.obj", Path::new("."), material(), MissingTextures::Fail, BvhBuilder::Sah, &mut rng)
+    }
+
+    #[test]
+    fn quads_are_triangulated_and_hit() {
+        let mesh = parse("v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 4/4 3/3 2/2\n").unwrap();
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        for &(x, z) in &[(0.2, 0.7), (0.7, 0.2)] {
+            let record = mesh.hit(&down(x, z), 0.001, f32::MAX, &mut rng).expect("quad should be hit");
+            assert!((record.u - x).abs() < 1e-5 && (record.v - z).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        assert!(parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n").is_ok());
+    }
+
+    #[test]
+    fn malformed_faces_are_errors() {
+        let message = |text: &str| parse(text).expect_err("file should be rejected").to_string();
+        assert_eq!(
+            message("v 0 0 0\nv 1 0 0\nf 1 2\n"),
+            "test.obj:3: a face needs at least 3 vertices, got 2"
+        );
+        assert_eq!(
+            message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n"),
+            "test.obj:4: index 4 out of range (1 to 3)"
+        );
+        assert_eq!(message("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 x\n"), "test.obj:4: invalid index `x`");
+        assert_eq!(message("v 0 0\n"), "test.obj:1: expected 3 numbers after `v`");
+        assert_eq!(message("v 0 0 0\nusemtl red\n"), "test.obj:2: unknown material `red`");
+        assert_eq!(message("v 0 0 0\n"), "test.obj: no faces");
+    }
+
+    #[test]
+    fn mtl_materials_map_onto_material_variants() {
+        let library = parse_mtl(
+            "newmtl matte\nKd 0.5 0.2 0.1\n\
+             newmtl mirror\nillum 3\nKs 0.9 0.9 0.9\nNs 1000\n\
+             newmtl glass\nillum 7\nNi 1.33\n\
+             newmtl lamp\nKe 4 4 4\n",
+            "test.mtl",
+            Path::new("."),
+            MissingTextures::Fail,
+        )
+        .unwrap();
+        assert!(matches!(*library["matte"], Material::Lambertian { .. }));
+        assert!(matches!(*library["mirror"], Material::Metal { .. }));
+        assert!(matches!(*library["glass"], Material::Dielectric { ref_idx: RefractiveIndex::Constant(ref_idx), .. } if (ref_idx - 1.33).abs() < 1e-6));
+        assert!(matches!(*library["lamp"], Material::DiffuseLight { .. }));
+    }
+
+    #[test]
+    fn missing_texture_maps_are_errors_unless_placeholders_are_allowed() {
+        let text = "newmtl painted\nKd 0.5 0.5 0.5\nmap_Kd missing.png\n";
+        let error = parse_mtl(text, "test.mtl", Path::new("."), MissingTextures::Fail).unwrap_err();
+        assert!(error.to_string().starts_with("test.mtl:3: could not load ./missing.png"), "{}", error);
+        let library = parse_mtl(text, "test.mtl", Path::new("."), MissingTextures::Placeholder).unwrap();
+        assert!(matches!(*library["painted"], Material::Lambertian { .. }));
+    }
+}
@@ -0,0 +1,38 @@
+use crate::vectors::Vec3;
+use rand::Rng;
+use std::f32::consts::PI;
+
+/// A direction on the hemisphere around +z, with density `cos(theta) / PI`.
+pub fn random_cosine_direction<R: Rng>(rng: &mut R) -> Vec3 {
+    let (r1, r2) = (rng.gen::<f32>(), rng.gen::<f32>());
+    let phi = 2. * PI * r1;
+    let (x, y) = (phi.cos() * r2.sqrt(), phi.sin() * r2.sqrt());
+    Vec3::new(x, y, (1. - r2).sqrt())
+}
+
+/// An orthonormal basis with `w` along a given direction, for sampling around it.
+#[derive(Clone, Copy, Debug)]
+pub struct ONB {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl ONB {
+    pub fn from_w(n: Vec3) -> ONB {
+        let w = n.unit_vector();
+        let a = if w.x.abs() > 0.9 {
+            Vec3::new(0., 1., 0.)
+        } else {
+            Vec3::new(1., 0., 0.)
+        };
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+        ONB { u, v, w }
+    }
+
+    /// Converts `a`, given in the basis' coordinates, to world space.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        self.u * a.x + self.v * a.y + self.w * a.z
+    }
+}
@@ -0,0 +1,150 @@
+use crate::framebuffer::FrameBuffer;
+use image::{DynamicImage, ImageError, Rgb32FImage, RgbImage};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Ppm,
+    Exr,
+    Hdr,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &str) -> Option<OutputFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(OutputFormat::Png),
+            "ppm" => Some(OutputFormat::Ppm),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            _ => None,
+        }
+    }
+
+    /// Whether the format stores the linear radiance rather than gamma-corrected 8-bit values.
+    pub fn is_hdr(self) -> bool {
+        matches!(self, OutputFormat::Exr | OutputFormat::Hdr)
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    UnsupportedFormat(String),
+    Io(io::Error),
+    Image(ImageError),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputError::UnsupportedFormat(path) => write!(
+                f,
+                "cannot infer an image format from `{}` (expected .png, .ppm, .exr or .hdr)",
+                path
+            ),
+            OutputError::Io(error) => write!(f, "{}", error),
+            OutputError::Image(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for OutputError {}
+
+impl From<io::Error> for OutputError {
+    fn from(error: io::Error) -> OutputError {
+        OutputError::Io(error)
+    }
+}
+
+impl From<ImageError> for OutputError {
+    fn from(error: ImageError) -> OutputError {
+        OutputError::Image(error)
+    }
+}
+
+/// Writes a binary (P6) PPM.
+pub fn write_ppm<W: Write>(framebuffer: &FrameBuffer, out: &mut W) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", framebuffer.width, framebuffer.height)?;
+    out.write_all(&framebuffer.to_rgb8())?;
+    out.flush()
+}
+
+/// Writes `framebuffer` to `path` in the format given by its extension, or as PPM to stdout for `-`.
+pub fn write_image(framebuffer: &FrameBuffer, path: &str) -> Result<(), OutputError> {
+    if path == "-" {
+        let stdout = io::stdout();
+        return Ok(write_ppm(framebuffer, &mut stdout.lock())?);
+    }
+    let format = OutputFormat::from_path(path).ok_or_else(|| OutputError::UnsupportedFormat(path.to_owned()))?;
+    let (width, height) = (framebuffer.width as u32, framebuffer.height as u32);
+    match format {
+        OutputFormat::Ppm => write_ppm(framebuffer, &mut BufWriter::new(File::create(path)?))?,
+        OutputFormat::Png => RgbImage::from_raw(width, height, framebuffer.to_rgb8())
+            .expect("framebuffer size matches its dimensions")
+            .save(path)?,
+        OutputFormat::Exr | OutputFormat::Hdr => {
+            let image = Rgb32FImage::from_raw(width, height, framebuffer.to_rgb32f())
+                .expect("framebuffer size matches its dimensions");
+            DynamicImage::ImageRgb32F(image).save(path)?
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors::Vec3;
+
+    fn framebuffer() -> FrameBuffer {
+        FrameBuffer::from_pixels(2, 1, vec![Vec3::new(0.25, 1., 0.), Vec3::new(4., 0.5, 16.)])
+    }
+
+    #[test]
+    fn ppm_has_a_p6_header_and_gamma_corrected_bytes() {
+        let mut out = Vec::new();
+        write_ppm(&framebuffer(), &mut out).unwrap();
+        let header = b"P6\n2 1\n255\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], &[127, 255, 0, 255, 181, 255]);
+    }
+
+    #[test]
+    fn formats_follow_the_extension_in_any_case() {
+        assert_eq!(OutputFormat::from_path("image.png"), Some(OutputFormat::Png));
+        assert_eq!(OutputFormat::from_path("out/IMAGE.PPM"), Some(OutputFormat::Ppm));
+        assert_eq!(OutputFormat::from_path("render.Exr"), Some(OutputFormat::Exr));
+        assert_eq!(OutputFormat::from_path("sky.HDR"), Some(OutputFormat::Hdr));
+        assert_eq!(OutputFormat::from_path("image.jpg"), None);
+        assert_eq!(OutputFormat::from_path("image"), None);
+        assert_eq!(OutputFormat::from_path("png"), None);
+        assert!(OutputFormat::Exr.is_hdr() && OutputFormat::Hdr.is_hdr());
+        assert!(!OutputFormat::Png.is_hdr() && !OutputFormat::Ppm.is_hdr());
+        match write_image(&framebuffer(), "image.jpg") {
+            Err(OutputError::UnsupportedFormat(path)) => assert_eq!(path, "image.jpg"),
+            other => panic!("expected an unsupported format, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn float_formats_keep_values_above_one_linear() {
+        let framebuffer = framebuffer();
+        for extension in &["exr", "hdr"] {
+            let path = std::env::temp_dir().join(format!("output-test-{}.{}", std::process::id(), extension));
+            let path = path.to_str().unwrap();
+            write_image(&framebuffer, path).unwrap();
+            let read = image::open(path).unwrap().to_rgb32f();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(read.dimensions(), (2, 1));
+            for (value, expected) in read.into_raw().iter().zip(framebuffer.to_rgb32f()) {
+                // Radiance HDR shares an 8-bit exponent between the channels of a pixel.
+                let tolerance = 0.01 * expected.max(1.);
+                assert!((value - expected).abs() <= tolerance, "{}: {} != {}", extension, value, expected);
+            }
+        }
+    }
+}
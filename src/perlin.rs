@@ -1,94 +1,129 @@
-use rand::Rng;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
 use crate::vectors::Vec3;
 
-fn perlin_interpolate(c: &[[[Vec3; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
-    let (uu, vv, ww) = (
-        u * u * (3. - 2. * u),
-        v * v * (3. - 2. * v),
-        w * w * (3. - 2. * w),
-    );
-    let mut accumulator = 0.;
-    for i in 0..2 {
-        for j in 0..2 {
-            for k in 0..2 {
-                let weight = Vec3::new(u - (i as f32), v - (j as f32), w - (k as f32));
-                accumulator += (i as f32 * uu + (1. - i as f32) * (1. - uu))
-                    * (j as f32 * vv + (1. - j as f32) * (1. - vv))
-                    * (k as f32 * ww + (1. - k as f32) * (1. - ww))
-                    * c[i][j][k].dot(weight);
-            }
-        }
-    }
-    accumulator
+/// Lattice points per axis before the noise repeats, unless `Perlin::with_period` says otherwise.
+pub const DEFAULT_PERIOD: usize = 256;
+
+/// Squared radius of the falloff around each simplex corner. At 0.5 a corner's influence has
+/// died out before the next simplex, so the noise has no seams.
+const SIMPLEX_RADIUS_SQUARED: f32 = 0.5;
+
+/// Brings simplex noise in 2, 3 and 4 dimensions to about -1 to 1.
+const SIMPLEX_SCALE: [f32; 3] = [95., 100., 100.];
+
+/// Which kind of gradient noise a `Perlin` makes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseBasis {
+    /// Ken Perlin's original noise, interpolated over the corners of a square or cube lattice.
+    Perlin,
+    /// Simplex noise, summed over the corners of a triangle or tetrahedron: cheaper in higher
+    /// dimensions and without the lattice's axis-aligned streaks.
+    Simplex,
+}
+
+/// Which coordinates the noise varies over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseDimensions {
+    /// x and y, the same all along z.
+    Two,
+    Three,
+    /// x, y, z and a fixed `time`, which moves the noise smoothly from frame to frame.
+    Four { time: f32 },
 }
 
 #[derive(Clone, Debug)]
 pub struct Perlin {
-    pub random_vector: Vec<Vec3>,
-    pub perm_x: Vec<i64>,
-    pub perm_y: Vec<i64>,
-    pub perm_z: Vec<i64>,
+    pub basis: NoiseBasis,
+    pub dimensions: NoiseDimensions,
+    seed: u64,
+    /// Lattice points per axis before the noise repeats.
+    period: usize,
+    /// Random unit gradients for 2, 3 and 4 dimensions, padded with zeros.
+    gradients: [Vec<[f32; 4]>; 3],
+    /// A permutation of the lattice indices for each axis.
+    permutations: [Vec<usize>; 4],
 }
 
 impl Perlin {
+    /// Three-dimensional Perlin noise from a seed drawn from `rng`.
     pub fn new<R: Rng>(rng: &mut R) -> Perlin {
+        Perlin::with_seed(rng.gen())
+    }
+
+    /// Three-dimensional Perlin noise that depends only on `seed`, so the pattern doesn't change
+    /// when the rest of the scene does.
+    pub fn with_seed(seed: u64) -> Perlin {
+        Perlin::generate(seed, DEFAULT_PERIOD)
+    }
+
+    /// The noise of the same seed, repeating every `period` units along each axis (every `period`
+    /// cells of the skewed lattice for simplex noise), for textures that tile. Panics if `period` is zero.
+    pub fn with_period(self, period: usize) -> Perlin {
+        assert!(period > 0, "the noise period must be at least 1");
         Perlin {
-            random_vector: Perlin::perlin_generate(rng),
-            perm_x: Perlin::perlin_generate_perm(rng),
-            perm_y: Perlin::perlin_generate_perm(rng),
-            perm_z: Perlin::perlin_generate_perm(rng),
+            basis: self.basis,
+            dimensions: self.dimensions,
+            ..Perlin::generate(self.seed, period)
         }
     }
 
-    pub fn noise(&self, p: &Vec3) -> f32 {
-        let (u, v, w) = (p.x - p.x.floor(), p.y - p.y.floor(), p.z - p.z.floor());
-        let (i, j, k) = (
-            p.x.floor() as usize,
-            p.y.floor() as usize,
-            p.z.floor() as usize,
-        );
-        let mut c = [[[Vec3::new(0., 0., 0.); 2]; 2]; 2];
-        for di in 0..2 {
-            for dj in 0..2 {
-                for dk in 0..2 {
-                    c[di][dj][dk] = self.random_vector[(self.perm_x[((i + di) & 255) as usize]
-                                                           ^ self.perm_y[((j + dj) & 255) as usize]
-                                                           ^ self.perm_z[((k + dk) & 255) as usize])
-                                                           as usize];
-                }
-            }
-        }
-        perlin_interpolate(&c, u, v, w).abs()
-    }
-
-    fn perlin_generate<R: Rng>(rng: &mut R) -> Vec<Vec3> {
-        let mut p = Vec::with_capacity(256);
-        for _ in 0..256 {
-            p.push(
-                Vec3::new(
-                    -1. + 2. * rng.gen::<f32>(),
-                    -1. + 2. * rng.gen::<f32>(),
-                    -1. + 2. * rng.gen::<f32>(),
-                ).unit_vector(),
-            );
+    fn generate(seed: u64, period: usize) -> Perlin {
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        let gradients = [
+            Perlin::generate_gradients(2, period, &mut rng),
+            Perlin::generate_gradients(3, period, &mut rng),
+            Perlin::generate_gradients(4, period, &mut rng),
+        ];
+        let permutations = [
+            Perlin::generate_permutation(period, &mut rng),
+            Perlin::generate_permutation(period, &mut rng),
+            Perlin::generate_permutation(period, &mut rng),
+            Perlin::generate_permutation(period, &mut rng),
+        ];
+        Perlin {
+            basis: NoiseBasis::Perlin,
+            dimensions: NoiseDimensions::Three,
+            seed,
+            period,
+            gradients,
+            permutations,
         }
-        p
     }
 
-    fn permute<R: Rng>(p: &mut Vec<i64>, n: usize, rng: &mut R) {
-        for i in (0..n).rev() {
-            let target = (rng.gen::<f32>() * ((i + 1) as f32)) as usize;
-            p.swap(i, target);
-        }
+    pub fn with_basis(self, basis: NoiseBasis) -> Perlin {
+        Perlin { basis, ..self }
     }
 
-    fn perlin_generate_perm<R: Rng>(rng: &mut R) -> Vec<i64> {
-        let mut p = Vec::with_capacity(256);
-        for i in 0..256 {
-            p.push(i);
+    pub fn with_dimensions(self, dimensions: NoiseDimensions) -> Perlin {
+        Perlin { dimensions, ..self }
+    }
+
+    pub fn noise(&self, p: &Vec3) -> f32 {
+        self.signed_noise(p).abs()
+    }
+
+    /// Gradient noise between about -1 and 1, zero on the lattice points.
+    pub fn signed_noise(&self, p: &Vec3) -> f32 {
+        match (self.basis, self.dimensions) {
+            (NoiseBasis::Perlin, NoiseDimensions::Two) => self.lattice_noise([p.x, p.y]),
+            (NoiseBasis::Perlin, NoiseDimensions::Three) => self.lattice_noise([p.x, p.y, p.z]),
+            (NoiseBasis::Perlin, NoiseDimensions::Four { time }) => self.lattice_noise([p.x, p.y, p.z, time]),
+            (NoiseBasis::Simplex, NoiseDimensions::Two) => self.simplex_noise([p.x, p.y]),
+            (NoiseBasis::Simplex, NoiseDimensions::Three) => self.simplex_noise([p.x, p.y, p.z]),
+            (NoiseBasis::Simplex, NoiseDimensions::Four { time }) => self.simplex_noise([p.x, p.y, p.z, time]),
         }
-        Perlin::permute(&mut p, 256, rng);
-        p
+    }
+
+    /// A point in the unit cell at `(i, j, k)`, the same for every call, for cellular noise.
+    pub fn cell_point(&self, i: i64, j: i64, k: i64) -> Vec3 {
+        let hash = self.hash(&[i, j, k]);
+        let period = self.period as f32;
+        Vec3::new(
+            (self.permutations[0][hash] as f32 + 0.5) / period,
+            (self.permutations[1][hash] as f32 + 0.5) / period,
+            (self.permutations[2][hash] as f32 + 0.5) / period,
+        )
     }
 
     pub fn turbulence(&self, p: &Vec3, depth: usize) -> f32 {
@@ -100,4 +135,215 @@ impl Perlin {
         }
         accumulator.abs()
     }
+
+    /// Mixes the lattice coordinates into an index below the period. Negative coordinates wrap
+    /// around like positive ones, so the noise repeats every `period` cells in both directions.
+    fn hash(&self, index: &[i64]) -> usize {
+        let period = self.period as i64;
+        index.iter().zip(self.permutations.iter()).fold(0, |hash, (&i, permutation)| {
+            permutation[(hash as i64 + i.rem_euclid(period)).rem_euclid(period) as usize]
+        })
+    }
+
+    /// The dot product of the gradient at lattice point `index` with `offset`, from that point.
+    fn gradient_dot<const N: usize>(&self, index: &[i64; N], offset: &[f32; N]) -> f32 {
+        let gradient = &self.gradients[N - 2][self.hash(index)];
+        offset.iter().zip(gradient.iter()).map(|(o, g)| o * g).sum()
+    }
+
+    fn lattice_noise<const N: usize>(&self, p: [f32; N]) -> f32 {
+        let cell = p.map(|x| x.floor());
+        let mut accumulator = 0.;
+        for corner in 0..(1 << N) {
+            let (mut weight, mut index, mut offset) = (1., [0; N], [0.; N]);
+            for axis in 0..N {
+                let side = (corner >> axis) & 1;
+                let t = p[axis] - cell[axis];
+                let smooth = t * t * (3. - 2. * t);
+                weight *= if side == 1 { smooth } else { 1. - smooth };
+                index[axis] = cell[axis] as i64 + side as i64;
+                offset[axis] = t - side as f32;
+            }
+            accumulator += weight * self.gradient_dot(&index, &offset);
+        }
+        accumulator
+    }
+
+    fn simplex_noise<const N: usize>(&self, p: [f32; N]) -> f32 {
+        let n = N as f32;
+        // Skew space so the simplices become the corner-to-corner slices of unit hypercubes.
+        let skew = ((n + 1.).sqrt() - 1.) / n;
+        let unskew = (1. - 1. / (n + 1.).sqrt()) / n;
+        let skewed = p.iter().sum::<f32>() * skew;
+        let cell = p.map(|x| (x + skewed).floor());
+        let unskewed = cell.iter().sum::<f32>() * unskew;
+        let mut offset = [0.; N];
+        for axis in 0..N {
+            offset[axis] = p[axis] - (cell[axis] - unskewed);
+        }
+        // The simplex holding `p` steps from the cell's origin along the axes in order of the
+        // offset, largest first.
+        let mut order = [0; N];
+        for (axis, slot) in order.iter_mut().enumerate() {
+            *slot = axis;
+        }
+        order.sort_by(|&a, &b| offset[b].total_cmp(&offset[a]));
+        let (mut index, mut accumulator) = (cell.map(|x| x as i64), 0.);
+        for corner in 0..=N {
+            if corner > 0 {
+                index[order[corner - 1]] += 1;
+            }
+            let mut corner_offset = [0.; N];
+            for axis in 0..N {
+                corner_offset[axis] = p[axis] - (index[axis] as f32 - index.iter().sum::<i64>() as f32 * unskew);
+            }
+            let falloff = SIMPLEX_RADIUS_SQUARED - corner_offset.iter().map(|x| x * x).sum::<f32>();
+            if falloff > 0. {
+                accumulator += falloff.powi(4) * self.gradient_dot(&index, &corner_offset);
+            }
+        }
+        SIMPLEX_SCALE[N - 2] * accumulator
+    }
+
+    fn generate_gradients<R: Rng>(dimensions: usize, period: usize, rng: &mut R) -> Vec<[f32; 4]> {
+        let mut gradients = Vec::with_capacity(period);
+        while gradients.len() < period {
+            let mut gradient = [0.; 4];
+            for component in gradient.iter_mut().take(dimensions) {
+                *component = -1. + 2. * rng.gen::<f32>();
+            }
+            // Only directions within the unit ball, so that they are evenly spread.
+            let length = gradient.iter().map(|g| g * g).sum::<f32>().sqrt();
+            if length > 0.01 && length <= 1. {
+                gradients.push(gradient.map(|g| g / length));
+            }
+        }
+        gradients
+    }
+
+    fn generate_permutation<R: Rng>(period: usize, rng: &mut R) -> Vec<usize> {
+        let mut p = (0..period).collect::<Vec<_>>();
+        for i in (0..period).rev() {
+            let target = (rng.gen::<f32>() * ((i + 1) as f32)) as usize;
+            p.swap(i, target);
+        }
+        p
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_kinds() -> Vec<Perlin> {
+        let mut kinds = Vec::new();
+        for &basis in &[NoiseBasis::Perlin, NoiseBasis::Simplex] {
+            for &dimensions in &[NoiseDimensions::Two, NoiseDimensions::Three, NoiseDimensions::Four { time: 0.3 }] {
+                kinds.push(Perlin::with_seed(7).with_basis(basis).with_dimensions(dimensions));
+            }
+        }
+        kinds
+    }
+
+    fn points(count: usize, spread: f32) -> Vec<Vec3> {
+        let mut rng = XorShiftRng::seed_from_u64(11);
+        (0..count)
+            .map(|_| (Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 2. - 1.) * spread)
+            .collect()
+    }
+
+    #[test]
+    fn noise_is_continuous_across_cell_boundaries() {
+        for noise in all_kinds() {
+            for p in points(200, 20.) {
+                // Straddle the lattice planes, including the ones at and below zero.
+                let on_plane = Vec3::new(p.x.round(), p.y.round(), p.z.round());
+                for &epsilon in &[1e-4, -1e-4] {
+                    let step = Vec3::new(epsilon, epsilon, epsilon);
+                    let difference = noise.signed_noise(&(on_plane + step)) - noise.signed_noise(&(on_plane - step));
+                    assert!(difference.abs() < 0.005, "{:?} jumps by {} at {:?}", noise.basis, difference, on_plane);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn negative_coordinates_get_their_own_noise() {
+        let noise = Perlin::with_seed(3);
+        let negative = points(200, 50.).into_iter().map(|p| Vec3::new(-p.x.abs(), -p.y.abs(), -p.z.abs()));
+        let mut values = Vec::new();
+        for p in negative {
+            let value = noise.signed_noise(&p);
+            // The lattice repeats every `DEFAULT_PERIOD` cells on both sides of the origin.
+            let period = DEFAULT_PERIOD as f32;
+            let shifted = p + Vec3::new(period, period, period);
+            assert!((value - noise.signed_noise(&shifted)).abs() < 1e-3, "{:?}", p);
+            // Not a mirror image of the positive side.
+            values.push((value, noise.signed_noise(&-p)));
+        }
+        let mirrored = values.iter().filter(|(a, b)| (a - b).abs() < 1e-4 || (a + b).abs() < 1e-4).count();
+        assert!(mirrored < 10);
+        let lowest = values.iter().map(|v| v.0).fold(f32::MAX, f32::min);
+        let highest = values.iter().map(|v| v.0).fold(f32::MIN, f32::max);
+        assert!(highest - lowest > 0.5);
+    }
+
+    #[test]
+    fn noise_stays_in_range_and_vanishes_on_the_lattice() {
+        for noise in all_kinds() {
+            for p in points(2000, 10.) {
+                assert!(noise.signed_noise(&p).abs() <= 1., "{:?} {:?} at {:?}", noise.basis, noise.dimensions, p);
+            }
+        }
+        let perlin = Perlin::with_seed(5);
+        assert_eq!(perlin.signed_noise(&Vec3::new(-3., 4., -17.)), 0.);
+    }
+
+    #[test]
+    fn seeds_and_dimensions_pick_the_pattern() {
+        let p = Vec3::new(1.3, -2.7, 0.4);
+        assert_eq!(Perlin::with_seed(9).signed_noise(&p), Perlin::with_seed(9).signed_noise(&p));
+        assert_ne!(Perlin::with_seed(9).signed_noise(&p), Perlin::with_seed(10).signed_noise(&p));
+
+        let flat = Perlin::with_seed(9).with_dimensions(NoiseDimensions::Two);
+        assert_eq!(flat.signed_noise(&p), flat.signed_noise(&Vec3::new(p.x, p.y, 12.)));
+
+        let at = |time: f32| {
+            let noise = Perlin::with_seed(9).with_dimensions(NoiseDimensions::Four { time });
+            points(50, 5.).iter().map(|p| noise.signed_noise(p)).collect::<Vec<_>>()
+        };
+        let change = |a: Vec<f32>, b: Vec<f32>| a.iter().zip(b.iter()).map(|(a, b)| (a - b).abs()).fold(0., f32::max);
+        assert!(change(at(0.5), at(0.501)) < 0.01);
+        assert!(change(at(0.5), at(1.2)) > 0.1);
+    }
+
+    #[test]
+    fn noise_repeats_at_its_period() {
+        for noise in all_kinds().into_iter().filter(|noise| noise.basis == NoiseBasis::Perlin) {
+            let noise = noise.with_period(5);
+            let mut values = Vec::new();
+            for p in points(200, 10.) {
+                let value = noise.signed_noise(&p);
+                for &shift in &[Vec3::new(5., 0., 0.), Vec3::new(0., -10., 0.), Vec3::new(0., 0., 15.)] {
+                    if noise.dimensions == NoiseDimensions::Two && shift.z != 0. {
+                        continue;
+                    }
+                    let difference = value - noise.signed_noise(&(p + shift));
+                    assert!(difference.abs() < 1e-3, "{:?} at {:?}", noise.dimensions, p);
+                }
+                values.push(value);
+            }
+            // Not flat, and not repeating any sooner.
+            let lowest = values.iter().fold(f32::MAX, |a, &b| a.min(b));
+            let highest = values.iter().fold(f32::MIN, |a, &b| a.max(b));
+            assert!(highest - lowest > 0.5);
+            let p = Vec3::new(0.3, 0.6, 0.2);
+            assert!((noise.signed_noise(&p) - noise.signed_noise(&(p + Vec3::new(1., 0., 0.)))).abs() > 1e-4);
+        }
+        // Simplex noise only gets a period along its skewed lattice, but it stays sound.
+        let simplex = Perlin::with_seed(2).with_basis(NoiseBasis::Simplex).with_period(3);
+        assert!(points(500, 10.).iter().all(|p| simplex.signed_noise(p).abs() <= 1.));
+        // A coordinate that is not a number must not bring down the render.
+        simplex.signed_noise(&Vec3::new(f32::NAN, 0., 0.));
+    }
 }
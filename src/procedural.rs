@@ -0,0 +1,421 @@
+use crate::perlin::Perlin;
+use crate::textures::Texture;
+use crate::vectors::Vec3;
+use std::sync::Arc;
+
+/// How layers of noise are summed: `octaves` layers, each `lacunarity` times finer and `gain`
+/// times weaker than the one before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Octaves {
+    pub octaves: usize,
+    pub lacunarity: f32,
+    pub gain: f32,
+}
+
+impl Default for Octaves {
+    fn default() -> Octaves {
+        Octaves {
+            octaves: 6,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Octaves {
+    /// The weighted sum of `layer` over the octaves, starting at `p` with weight 1.
+    fn sum<F: Fn(Vec3) -> f32>(&self, p: Vec3, layer: F) -> f32 {
+        let (mut accumulator, mut p, mut weight) = (0., p, 1.);
+        for _ in 0..self.octaves {
+            accumulator += weight * layer(p);
+            weight *= self.gain;
+            p *= self.lacunarity;
+        }
+        accumulator
+    }
+
+    fn total_weight(&self) -> f32 {
+        self.sum(Vec3::empty(), |_| 1.)
+    }
+
+    /// The fractal sum of signed noise, scaled back to about -1 to 1.
+    fn fbm(&self, noise: &Perlin, p: Vec3) -> f32 {
+        let total = self.total_weight();
+        if total == 0. {
+            return 0.;
+        }
+        self.sum(p, |p| noise.signed_noise(&p)) / total
+    }
+}
+
+fn grey(value: f32) -> Vec3 {
+    let value = value.clamp(0., 1.);
+    Vec3::new(value, value, value)
+}
+
+fn mix(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    a * (1. - t) + b * t
+}
+
+/// Fractal Brownian motion: soft clouds of noise with detail at every scale, from black to white
+/// around a mid grey.
+#[derive(Clone, Debug)]
+pub struct Fbm {
+    pub noise: Arc<Perlin>,
+    pub scale: f32,
+    pub octaves: Octaves,
+}
+
+impl Fbm {
+    pub fn new(noise: Arc<Perlin>, scale: f32, octaves: Octaves) -> Fbm {
+        Fbm { noise, scale, octaves }
+    }
+
+    /// The noise at `p`, between about -1 and 1.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        self.octaves.fbm(&self.noise, p * self.scale)
+    }
+}
+
+impl Texture for Fbm {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        grey(0.5 + 0.5 * self.sample(p))
+    }
+}
+
+/// Ridged multifractal noise: sharp bright crests where the noise crosses zero, for veins,
+/// cracks and mountain ranges.
+#[derive(Clone, Debug)]
+pub struct RidgedNoise {
+    pub noise: Arc<Perlin>,
+    pub scale: f32,
+    pub octaves: Octaves,
+}
+
+impl RidgedNoise {
+    pub fn new(noise: Arc<Perlin>, scale: f32, octaves: Octaves) -> RidgedNoise {
+        RidgedNoise { noise, scale, octaves }
+    }
+
+    /// The ridges at `p`, from 0 to 1 on the crests.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        let total = self.octaves.total_weight();
+        if total == 0. {
+            return 0.;
+        }
+        let ridges = self.octaves.sum(p * self.scale, |p| {
+            let ridge = 1. - self.noise.signed_noise(&p).abs();
+            ridge * ridge
+        });
+        ridges / total
+    }
+}
+
+impl Texture for RidgedNoise {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        grey(self.sample(p))
+    }
+}
+
+/// Which distance cellular noise shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorleyFeature {
+    /// To the nearest feature point: dark spots in bright cells.
+    Nearest,
+    /// To the second nearest feature point: rounded bulges.
+    SecondNearest,
+    /// The difference of the two: dark lines along the cell borders.
+    Edges,
+}
+
+/// Worley (cellular) noise: distances to one random feature point per unit cell, for stones,
+/// scales, cells and cracked mud.
+#[derive(Clone, Debug)]
+pub struct WorleyNoise {
+    pub noise: Arc<Perlin>,
+    pub scale: f32,
+    pub feature: WorleyFeature,
+}
+
+impl WorleyNoise {
+    pub fn new(noise: Arc<Perlin>, scale: f32, feature: WorleyFeature) -> WorleyNoise {
+        WorleyNoise { noise, scale, feature }
+    }
+
+    /// The distance picked by `feature` at `p`, in cells.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        let p = p * self.scale;
+        let (i, j, k) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+        let (mut nearest, mut second) = (f32::MAX, f32::MAX);
+        // The nearest two points are always within the neighbouring cells.
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let corner = Vec3::new(ci as f32, cj as f32, ck as f32);
+                    let distance = (corner + self.noise.cell_point(ci, cj, ck) - p).length();
+                    if distance < nearest {
+                        second = nearest;
+                        nearest = distance;
+                    } else if distance < second {
+                        second = distance;
+                    }
+                }
+            }
+        }
+        match self.feature {
+            WorleyFeature::Nearest => nearest,
+            WorleyFeature::SecondNearest => second,
+            WorleyFeature::Edges => second - nearest,
+        }
+    }
+}
+
+impl Texture for WorleyNoise {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        grey(self.sample(p))
+    }
+}
+
+/// Marble: sine stripes across `stripes` bent by `turbulence` times the summed absolute noise,
+/// from `dark` in the veins to `light`. `NoiseTexture` is this with 7 octaves, a turbulence of 5
+/// and stripes along z.
+#[derive(Clone, Debug)]
+pub struct Marble {
+    pub noise: Arc<Perlin>,
+    pub scale: f32,
+    pub octaves: Octaves,
+    pub turbulence: f32,
+    /// The direction across the stripes, whose length sets how close they are.
+    pub stripes: Vec3,
+    pub light: Vec3,
+    pub dark: Vec3,
+}
+
+impl Marble {
+    pub fn new(noise: Arc<Perlin>, scale: f32, octaves: Octaves, turbulence: f32, light: Vec3, dark: Vec3) -> Marble {
+        Marble {
+            noise,
+            scale,
+            octaves,
+            turbulence,
+            stripes: Vec3::new(0., 0., 1.),
+            light,
+            dark,
+        }
+    }
+
+    /// How light the marble is at `p`, from 0 to 1.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        let p = p * self.scale;
+        let turbulence = self.octaves.sum(p, |p| self.noise.signed_noise(&p).abs());
+        0.5 * (1. + (self.stripes.dot(p) + self.turbulence * turbulence).sin())
+    }
+}
+
+impl Texture for Marble {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        mix(self.dark, self.light, self.sample(p))
+    }
+}
+
+/// Wood: `rings` growth rings per unit of distance from a line through the origin along `axis`,
+/// wobbled by `turbulence` rings of fractal noise. Each ring fades from `light` to `dark`.
+#[derive(Clone, Debug)]
+pub struct Wood {
+    pub noise: Arc<Perlin>,
+    pub scale: f32,
+    pub octaves: Octaves,
+    pub rings: f32,
+    pub turbulence: f32,
+    /// The direction of the trunk, as a unit vector.
+    pub axis: Vec3,
+    pub light: Vec3,
+    pub dark: Vec3,
+}
+
+impl Wood {
+    pub fn new(
+        noise: Arc<Perlin>,
+        scale: f32,
+        octaves: Octaves,
+        rings: f32,
+        turbulence: f32,
+        light: Vec3,
+        dark: Vec3,
+    ) -> Wood {
+        Wood {
+            noise,
+            scale,
+            octaves,
+            rings,
+            turbulence,
+            axis: Vec3::new(0., 1., 0.),
+            light,
+            dark,
+        }
+    }
+
+    /// Where `p` is within its ring, from 0 at the light start to 1 at the dark end.
+    pub fn sample(&self, p: Vec3) -> f32 {
+        let radius = (p - self.axis * self.axis.dot(p)).length();
+        let rings = radius * self.rings + self.turbulence * self.octaves.fbm(&self.noise, p * self.scale);
+        let t = rings - rings.floor();
+        t * t * (3. - 2. * t)
+    }
+}
+
+impl Texture for Wood {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        mix(self.light, self.dark, self.sample(p))
+    }
+}
+
+/// Domain warping: looks `texture` up at points pushed around by fractal noise, up to about
+/// `strength` world units, which swirls any texture into flowing, organic shapes.
+#[derive(Clone, Debug)]
+pub struct DomainWarp {
+    pub texture: Arc<dyn Texture>,
+    pub noise: Arc<Perlin>,
+    pub scale: f32,
+    pub octaves: Octaves,
+    pub strength: f32,
+}
+
+impl DomainWarp {
+    pub fn new(
+        texture: Arc<dyn Texture>,
+        noise: Arc<Perlin>,
+        scale: f32,
+        octaves: Octaves,
+        strength: f32,
+    ) -> DomainWarp {
+        DomainWarp {
+            texture,
+            noise,
+            scale,
+            octaves,
+            strength,
+        }
+    }
+
+    /// The point `texture` is looked up at in place of `p`.
+    pub fn warp(&self, p: Vec3) -> Vec3 {
+        let q = p * self.scale;
+        // Far apart samples of the same noise, so the three components look unrelated.
+        let offset = Vec3::new(
+            self.octaves.fbm(&self.noise, q),
+            self.octaves.fbm(&self.noise, q + Vec3::new(5.2, 1.3, 7.9)),
+            self.octaves.fbm(&self.noise, q + Vec3::new(9.2, 2.8, 3.4)),
+        );
+        p + offset * self.strength
+    }
+}
+
+impl Texture for DomainWarp {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        self.texture.surface_value(u, v, self.warp(p), normal, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textures::NoiseTexture;
+    use rand::prng::XorShiftRng;
+    use rand::{Rng, SeedableRng};
+
+    fn noise() -> Arc<Perlin> {
+        Arc::new(Perlin::new(&mut XorShiftRng::seed_from_u64(3)))
+    }
+
+    fn points(count: usize) -> Vec<Vec3> {
+        let mut rng = XorShiftRng::seed_from_u64(4);
+        (0..count)
+            .map(|_| Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>()) * 8.)
+            .collect()
+    }
+
+    #[test]
+    fn fractal_noise_stays_in_range_and_varies() {
+        let fbm = Fbm::new(noise(), 1.3, Octaves::default());
+        let ridged = RidgedNoise::new(noise(), 1.3, Octaves::default());
+        let (fbm_samples, ridged_samples): (Vec<f32>, Vec<f32>) =
+            points(500).into_iter().map(|p| (fbm.sample(p), ridged.sample(p))).unzip();
+        assert!(fbm_samples.iter().all(|s| s.abs() <= 1.));
+        assert!(ridged_samples.iter().all(|s| (0. ..=1.).contains(s)));
+        let spread = |samples: &[f32]| {
+            samples.iter().cloned().fold(f32::MIN, f32::max) - samples.iter().cloned().fold(f32::MAX, f32::min)
+        };
+        assert!(spread(&fbm_samples) > 0.3);
+        assert!(spread(&ridged_samples) > 0.3);
+    }
+
+    #[test]
+    fn worley_distances_are_ordered_and_vanish_at_feature_points() {
+        let (nearest, second, edges) = (
+            WorleyNoise::new(noise(), 1., WorleyFeature::Nearest),
+            WorleyNoise::new(noise(), 1., WorleyFeature::SecondNearest),
+            WorleyNoise::new(noise(), 1., WorleyFeature::Edges),
+        );
+        for p in points(200) {
+            assert!(nearest.sample(p) <= second.sample(p));
+            assert!((edges.sample(p) - (second.sample(p) - nearest.sample(p))).abs() < 1e-6);
+        }
+        let feature = Vec3::new(2., 3., 4.) + nearest.noise.cell_point(2, 3, 4);
+        assert!(nearest.sample(feature) < 1e-5);
+    }
+
+    #[test]
+    fn plain_wood_rings_repeat_along_the_radius() {
+        let white = Vec3::new(1., 1., 1.);
+        let wood = Wood::new(noise(), 1., Octaves::default(), 4., 0., white, Vec3::empty());
+        for &radius in &[0.1, 0.3, 1.05] {
+            let p = Vec3::new(radius, 2., 0.);
+            let next = Vec3::new(0., -1., radius + 0.25);
+            assert!((wood.sample(p) - wood.sample(next)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn marble_with_the_book_settings_matches_noise_texture() {
+        let scale = 4.;
+        let book = NoiseTexture::new(&mut XorShiftRng::seed_from_u64(3), scale);
+        let octaves = Octaves {
+            octaves: 7,
+            ..Octaves::default()
+        };
+        let marble = Marble::new(noise(), scale, octaves, 5., Vec3::new(1., 1., 1.), Vec3::empty());
+        for p in points(50) {
+            assert!((marble.value(0., 0., p) - book.value(0., 0., p)).length() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn unwarped_domain_leaves_the_texture_alone() {
+        let wood = Arc::new(Wood::new(noise(), 1., Octaves::default(), 4., 0.3, Vec3::new(1., 1., 1.), Vec3::empty()));
+        let still = DomainWarp::new(wood.clone(), noise(), 2., Octaves::default(), 0.);
+        let warped = DomainWarp::new(wood.clone(), noise(), 2., Octaves::default(), 0.5);
+        let mut moved = 0;
+        for p in points(100) {
+            assert!((still.value(0., 0., p) - wood.value(0., 0., p)).length() < 1e-6);
+            if (warped.warp(p) - p).length() > 1e-3 {
+                moved += 1;
+            }
+        }
+        assert!(moved > 90);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::spectrum::Wavelengths;
 use crate::vectors::Vec3;
 
 #[derive(Clone, Copy, Debug)]
@@ -7,6 +8,11 @@ pub struct Ray {
     pub inv_direction: Vec3,
     pub sign: [usize; 3],
     pub time: f32,
+    /// The wavelengths the ray carries in spectral mode.
+    pub wavelengths: Option<Wavelengths>,
+    /// How much wider the ray's footprint grows per unit of distance travelled, for filtering
+    /// textures: about a pixel for camera rays, 0 for rays that sample a single point.
+    pub spread: f32,
 }
 
 impl Ray {
@@ -23,8 +29,16 @@ impl Ray {
             inv_direction,
             sign,
             time,
+            wavelengths: None,
+            spread: 0.,
         }
     }
+    pub fn with_wavelengths(self, wavelengths: Option<Wavelengths>) -> Ray {
+        Ray { wavelengths, ..self }
+    }
+    pub fn with_spread(self, spread: f32) -> Ray {
+        Ray { spread, ..self }
+    }
     pub fn point_at_parameter(&self, t: f32) -> Vec3 {
         self.origin + self.direction * t
     }
@@ -0,0 +1,323 @@
+use crate::camera::{Camera, CameraSettings};
+use crate::framebuffer::FrameBuffer;
+use crate::hitables::bvh::BvhBuilder;
+use crate::hitables::{collect_lights, color_world, Hitable, HitableList};
+use crate::scenes::Scene;
+use crate::spectrum::Wavelengths;
+use crate::textures::{MissingTextures, TextureError};
+use crate::vectors::Vec3;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub const TILE_SIZE: usize = 32;
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub max_depth: i32,
+    pub external_light: bool,
+    pub threads: usize,
+    pub bvh: BvhBuilder,
+    /// Trace sampled wavelengths instead of red, green and blue, for dispersion.
+    pub spectral: bool,
+}
+
+/// A rectangle of pixels in image space, with `y0` counted from the top row.
+#[derive(Clone, Copy, Debug)]
+pub struct Tile {
+    pub x0: usize,
+    pub x1: usize,
+    pub y0: usize,
+    pub y1: usize,
+}
+
+pub fn tiles(width: usize, height: usize, tile_size: usize) -> Vec<Tile> {
+    let mut tiles = Vec::with_capacity(width.div_ceil(tile_size) * height.div_ceil(tile_size));
+    for y0 in (0..height).step_by(tile_size) {
+        for x0 in (0..width).step_by(tile_size) {
+            tiles.push(Tile {
+                x0,
+                x1: (x0 + tile_size).min(width),
+                y0,
+                y1: (y0 + tile_size).min(height),
+            });
+        }
+    }
+    tiles
+}
+
+/// Seed for the generator of a single pixel (a SplitMix64 step), so every pixel gets
+/// its own stream no matter which tile or thread renders it.
+pub fn pixel_seed(seed: u64, index: usize) -> u64 {
+    let mut z = seed.wrapping_add((index as u64).wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn render_tile<H: Hitable>(
+    world: &H,
+    lights: &HitableList,
+    camera: &Camera,
+    settings: &RenderSettings,
+    tile: &Tile,
+    seed: u64,
+) -> Vec<Vec3> {
+    let (nx, ny) = (settings.width as f32, settings.height as f32);
+    let spread = camera.pixel_spread(settings.height);
+    let mut pixels = Vec::with_capacity((tile.x1 - tile.x0) * (tile.y1 - tile.y0));
+    for row in tile.y0..tile.y1 {
+        let j = settings.height - 1 - row;
+        for i in tile.x0..tile.x1 {
+            let mut rng = XorShiftRng::seed_from_u64(pixel_seed(seed, row * settings.width + i));
+            let mut color = Vec3::empty();
+            for _ in 0..settings.samples {
+                let s = ((i as f32) + rng.gen::<f32>()) / nx;
+                let t = ((j as f32) + rng.gen::<f32>()) / ny;
+                let ray = camera.get_ray(s, t, &mut rng).with_spread(spread);
+                if settings.spectral {
+                    let wavelengths = Wavelengths::sample(&mut rng);
+                    let ray = ray.with_wavelengths(Some(wavelengths));
+                    let radiance = color_world(&ray, world, lights, settings.max_depth, settings.external_light, &mut rng);
+                    color += wavelengths.rgb(radiance);
+                } else {
+                    color += color_world(&ray, world, lights, settings.max_depth, settings.external_light, &mut rng);
+                }
+            }
+            pixels.push(color / settings.samples as f32);
+        }
+    }
+    pixels
+}
+
+/// Renders the image tile by tile on `settings.threads` worker threads, sampling `lights` directly.
+///
+/// Every pixel draws from its own generator seeded from `seed` and the pixel index,
+/// so the same seed gives a bit-identical image for any thread count.
+pub fn render<H: Hitable>(
+    world: &H,
+    lights: &HitableList,
+    camera: &Camera,
+    settings: &RenderSettings,
+    seed: u64,
+) -> FrameBuffer {
+    let tiles = tiles(settings.width, settings.height, TILE_SIZE);
+    let next_tile = AtomicUsize::new(0);
+    let rendered: Vec<(usize, Vec<Vec3>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..settings.threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if index >= tiles.len() {
+                            return done;
+                        }
+                        done.push((index, render_tile(world, lights, camera, settings, &tiles[index], seed)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("render thread panicked"))
+            .collect()
+    });
+
+    let mut framebuffer = FrameBuffer::new(settings.width, settings.height);
+    for (index, pixels) in rendered {
+        let tile = &tiles[index];
+        let tile_width = tile.x1 - tile.x0;
+        for (k, color) in pixels.into_iter().enumerate() {
+            framebuffer.set(tile.x0 + k % tile_width, tile.y0 + k / tile_width, color);
+        }
+    }
+    framebuffer
+}
+
+/// Wraps `objects` in a BVH built with `settings.bvh` and renders them with their lights sampled
+/// directly, drawing the sampling seed (and the BVH splits, for the random builder) from `rng`.
+pub fn render_world<R: Rng>(
+    objects: &mut Vec<Arc<dyn Hitable>>,
+    camera_settings: &CameraSettings,
+    settings: &RenderSettings,
+    rng: &mut R,
+) -> FrameBuffer {
+    let camera = camera_settings.build((settings.width as f32) / (settings.height as f32));
+    let lights = collect_lights(objects);
+    let world = settings.bvh.build(objects, camera_settings.time_0, camera_settings.time_1, rng);
+    render(&world, &lights, &camera, settings, rng.gen())
+}
+
+/// Builds `scene` and renders it, with `seed` driving both the scene construction and the sampling.
+/// Images that can't be loaded fail the render or are replaced, as `missing_textures` says.
+pub fn render_scene(
+    scene: Scene,
+    camera_settings: &CameraSettings,
+    settings: &RenderSettings,
+    missing_textures: MissingTextures,
+    seed: u64,
+) -> Result<FrameBuffer, TextureError> {
+    let mut rng = XorShiftRng::seed_from_u64(seed);
+    let mut objects = scene.build(settings.bvh, missing_textures, &mut rng)?;
+    Ok(render_world(&mut objects, camera_settings, settings, &mut rng))
+}
+
+pub fn available_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitables::rectangles::XZRectangle;
+    use crate::hitables::transforms::{AnimatedTransform, Keyframe, Transform};
+    use crate::matrices::Quaternion;
+    use crate::materials::Material;
+    use crate::scenes::cornell_box;
+    use crate::textures::ConstantTexture;
+
+    fn settings(threads: usize) -> RenderSettings {
+        RenderSettings {
+            width: 40,
+            height: 30,
+            samples: 4,
+            max_depth: 10,
+            external_light: false,
+            threads,
+            bvh: BvhBuilder::Sah,
+            spectral: false,
+        }
+    }
+
+    fn render_bits(scene: Scene, threads: usize, seed: u64) -> Vec<u32> {
+        render_scene(scene, &scene.default_camera(), &settings(threads), MissingTextures::Fail, seed)
+            .unwrap()
+            .to_rgb32f()
+            .iter()
+            .map(|value| value.to_bits())
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_identical_image_for_any_thread_count() {
+        let single = render_bits(Scene::CornellSmoke, 1, 7);
+        assert_eq!(single, render_bits(Scene::CornellSmoke, 1, 7));
+        assert_eq!(single, render_bits(Scene::CornellSmoke, 4, 7));
+    }
+
+    #[test]
+    fn different_seeds_give_different_images() {
+        assert_ne!(render_bits(Scene::CornellSmoke, 2, 7), render_bits(Scene::CornellSmoke, 2, 8));
+    }
+
+    /// The mean color of the Cornell box camera's view of `objects`, sampling their lights directly if
+    /// `sample_lights`.
+    fn mean_color(
+        mut objects: Vec<Arc<dyn Hitable>>,
+        settings: &RenderSettings,
+        sample_lights: bool,
+        seed: u64,
+    ) -> Vec3 {
+        let lights = if sample_lights {
+            collect_lights(&objects)
+        } else {
+            HitableList::empty()
+        };
+        let world = settings.bvh.build(&mut objects, 0., 1., &mut XorShiftRng::seed_from_u64(seed));
+        let camera = Scene::CornellBox.default_camera().build(1.);
+        let framebuffer = render(&world, &lights, &camera, settings, seed);
+        framebuffer.pixels.iter().fold(Vec3::empty(), |sum, pixel| sum + *pixel) / framebuffer.pixels.len() as f32
+    }
+
+    /// The mean brightness of `objects`, which have one light, rendered with and without sampling it.
+    fn sampled_and_unsampled_means(objects: Vec<Arc<dyn Hitable>>) -> (f32, f32) {
+        assert_eq!(collect_lights(&objects).objects.len(), 1);
+        let settings = RenderSettings {
+            width: 8,
+            height: 8,
+            samples: 1000,
+            ..settings(4)
+        };
+        let brightness = |color: Vec3| (color.x + color.y + color.z) / 3.;
+        (
+            brightness(mean_color(objects.clone(), &settings, true, 2)),
+            brightness(mean_color(objects, &settings, false, 3)),
+        )
+    }
+
+    fn cornell_box_scene() -> Vec<Arc<dyn Hitable>> {
+        let mut rng = XorShiftRng::seed_from_u64(1);
+        Scene::CornellBox.build(BvhBuilder::Sah, MissingTextures::Fail, &mut rng).unwrap()
+    }
+
+    #[test]
+    fn light_sampling_converges_to_the_same_image() {
+        let (sampled, unsampled) = sampled_and_unsampled_means(cornell_box_scene());
+        assert!((sampled - unsampled).abs() < 0.05 * unsampled, "{} vs {}", sampled, unsampled);
+    }
+
+    #[test]
+    fn transformed_lights_are_sampled_without_bias() {
+        let emit = Arc::new(ConstantTexture::new(Vec3::new(15., 15., 15.)));
+        let square = XZRectangle::new(-0.5, 0.5, -0.5, 0.5, 0., Arc::new(Material::diffuse_light(emit)));
+        let light = Transform::new(Arc::new(square))
+            .scale(Vec3::new(160., 1., 90.))
+            .rotate_x(20.)
+            .rotate_y(30.)
+            .translate(Vec3::new(278., 500., 279.));
+        let mut objects: Vec<Arc<dyn Hitable>> =
+            cornell_box().into_iter().filter(|object| !object.is_light()).collect();
+        objects.push(Arc::new(light));
+        let (sampled, unsampled) = sampled_and_unsampled_means(objects);
+        assert!((sampled - unsampled).abs() < 0.05 * unsampled, "{} vs {}", sampled, unsampled);
+    }
+
+    #[test]
+    fn moving_lights_are_left_out_of_light_sampling_without_bias() {
+        let emit = Arc::new(ConstantTexture::new(Vec3::new(15., 15., 15.)));
+        // Hiding the ceiling light, so the rays that hit it would also have reached the sampled light.
+        let panel = XZRectangle::new(190., 350., 210., 350., 540., Arc::new(Material::diffuse_light(emit)));
+        let still = Keyframe::new(0., Vec3::empty(), Quaternion::identity(), Vec3::new(1., 1., 1.));
+        let moved = Keyframe::new(1., Vec3::new(20., 0., 0.), Quaternion::identity(), Vec3::new(1., 1., 1.));
+        let mut objects = cornell_box();
+        objects.push(Arc::new(AnimatedTransform::new(Arc::new(panel), vec![still, moved])));
+        assert!(!objects[objects.len() - 1].is_light());
+        let (sampled, unsampled) = sampled_and_unsampled_means(objects);
+        assert!((sampled - unsampled).abs() < 0.05 * unsampled, "{} vs {}", sampled, unsampled);
+    }
+
+    #[test]
+    fn spectral_rendering_keeps_the_colors() {
+        let settings = RenderSettings {
+            width: 4,
+            height: 4,
+            samples: 2000,
+            ..settings(4)
+        };
+        let objects = cornell_box_scene();
+        let rgb = mean_color(objects.clone(), &settings, true, 2);
+        let spectral = mean_color(objects, &RenderSettings { spectral: true, ..settings }, true, 3);
+        // Light bouncing between colored walls differs a little from its RGB product, and
+        // there is noise from the wavelengths.
+        assert!((rgb - spectral).length() < 0.08 * rgb.length(), "{:?} vs {:?}", rgb, spectral);
+    }
+
+    #[test]
+    fn tiles_cover_the_image_once() {
+        let mut covered = vec![0; 70 * 45];
+        for tile in tiles(70, 45, TILE_SIZE) {
+            for y in tile.y0..tile.y1 {
+                for x in tile.x0..tile.x1 {
+                    covered[y * 70 + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+}
@@ -0,0 +1,1212 @@
+use crate::camera::CameraSettings;
+use crate::hitables::bump_maps::{BumpMap, NormalMap};
+use crate::hitables::bvh::BvhBuilder;
+use crate::hitables::cuboids::Cuboid;
+use crate::hitables::cutouts::AlphaCutout;
+use crate::hitables::mediums::ConstantMedium;
+use crate::hitables::rectangles::{XYRectangle, XZRectangle, YZRectangle};
+use crate::hitables::spheres::{MovingSphere, Sphere};
+use crate::hitables::transforms::{AnimatedTransform, FlipNormals, Keyframe, RotateY, Transform, Translate};
+use crate::hitables::triangles::{MeshData, Triangle, TriangleMesh};
+use crate::hitables::Hitable;
+use crate::materials::Material;
+use crate::matrices::{Matrix4, Quaternion};
+use crate::obj;
+use crate::perlin::{NoiseBasis, NoiseDimensions, Perlin};
+use crate::procedural::{DomainWarp, Fbm, Marble, Octaves, RidgedNoise, Wood, WorleyFeature, WorleyNoise};
+use crate::spectrum::RefractiveIndex;
+use crate::texture_nodes::{BlendMode, BlendTexture, ColorRamp, MixTexture, Triplanar, UvTransform, WorldTransform};
+use crate::textures::{
+    load_image, AlphaTexture, CheckerTexture, ColorSpace, ConstantTexture, Filter, ImageSettings, MissingTextures,
+    NoiseTexture, Texture, Wrap,
+};
+use crate::vectors::Vec3;
+use rand::Rng;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A scene read from a TOML or JSON description.
+#[derive(Debug)]
+pub struct SceneFile {
+    pub camera: CameraSettings,
+    pub external_light: bool,
+    pub objects: Vec<Arc<dyn Hitable>>,
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(String),
+    Invalid { path: String, message: String },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneFileError::Io(error) => write!(f, "{}", error),
+            SceneFileError::Parse(message) => write!(f, "{}", message),
+            SceneFileError::Invalid { path, message } => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl From<io::Error> for SceneFileError {
+    fn from(error: io::Error) -> SceneFileError {
+        SceneFileError::Io(error)
+    }
+}
+
+type Result<T> = std::result::Result<T, SceneFileError>;
+
+/// A value in the parsed document together with its path from the root, for error messages.
+#[derive(Clone)]
+struct Node<'a> {
+    value: &'a Value,
+    path: String,
+}
+
+impl<'a> Node<'a> {
+    fn error<T>(&self, message: String) -> Result<T> {
+        Err(SceneFileError::Invalid {
+            path: if self.path.is_empty() { String::from("<root>") } else { self.path.clone() },
+            message,
+        })
+    }
+
+    fn child(&self, key: &str, value: &'a Value) -> Node<'a> {
+        let path = if self.path.is_empty() { key.to_owned() } else { format!("{}.{}", self.path, key) };
+        Node { value, path }
+    }
+
+    fn table(&self) -> Result<&'a Map<String, Value>> {
+        match self.value {
+            Value::Object(map) => Ok(map),
+            _ => self.error(String::from("expected a table")),
+        }
+    }
+
+    fn get_opt(&self, key: &str) -> Result<Option<Node<'a>>> {
+        Ok(self.table()?.get(key).map(|value| self.child(key, value)))
+    }
+
+    fn get(&self, key: &str) -> Result<Node<'a>> {
+        match self.get_opt(key)? {
+            Some(node) => Ok(node),
+            None => self.error(format!("missing key `{}`", key)),
+        }
+    }
+
+    /// Rejects keys outside `allowed`, which catches typos that would otherwise be silently ignored.
+    fn check_keys(&self, allowed: &[&str]) -> Result<()> {
+        for key in self.table()?.keys() {
+            if !allowed.contains(&key.as_str()) {
+                return self
+                    .child(key, &Value::Null)
+                    .error(format!("unknown key (expected one of: {})", allowed.join(", ")));
+            }
+        }
+        Ok(())
+    }
+
+    fn as_f32(&self) -> Result<f32> {
+        match self.value.as_f64() {
+            Some(number) => Ok(number as f32),
+            None => self.error(String::from("expected a number")),
+        }
+    }
+
+    fn as_bool(&self) -> Result<bool> {
+        match self.value.as_bool() {
+            Some(flag) => Ok(flag),
+            None => self.error(String::from("expected true or false")),
+        }
+    }
+
+    fn as_str(&self) -> Result<&'a str> {
+        match self.value.as_str() {
+            Some(text) => Ok(text),
+            None => self.error(String::from("expected a string")),
+        }
+    }
+
+    fn as_array(&self) -> Result<Vec<Node<'a>>> {
+        match self.value {
+            Value::Array(items) => Ok(items
+                .iter()
+                .enumerate()
+                .map(|(i, value)| Node {
+                    value,
+                    path: format!("{}[{}]", self.path, i),
+                })
+                .collect()),
+            _ => self.error(String::from("expected an array")),
+        }
+    }
+
+    fn as_vec3(&self) -> Result<Vec3> {
+        let items = match self.value {
+            Value::Array(items) if items.len() == 3 => self.as_array()?,
+            _ => return self.error(String::from("expected an array of three numbers")),
+        };
+        Ok(Vec3::new(items[0].as_f32()?, items[1].as_f32()?, items[2].as_f32()?))
+    }
+
+    fn as_index(&self, count: usize) -> Result<usize> {
+        match self.value.as_u64() {
+            Some(index) if (index as usize) < count => Ok(index as usize),
+            _ => self.error(format!("expected a vertex index below {}", count)),
+        }
+    }
+
+    fn as_uv(&self) -> Result<(f32, f32)> {
+        let items = match self.value {
+            Value::Array(items) if items.len() == 2 => self.as_array()?,
+            _ => return self.error(String::from("expected an array of two numbers")),
+        };
+        Ok((items[0].as_f32()?, items[1].as_f32()?))
+    }
+
+    fn f32(&self, key: &str) -> Result<f32> {
+        self.get(key)?.as_f32()
+    }
+
+    fn f32_or(&self, key: &str, default: f32) -> Result<f32> {
+        match self.get_opt(key)? {
+            Some(node) => node.as_f32(),
+            None => Ok(default),
+        }
+    }
+
+    /// The value named by the string at `key`, out of `choices`, or `default` if there is none.
+    fn choice_or<T: Copy>(&self, key: &str, choices: &[(&str, T)], default: T) -> Result<T> {
+        let node = match self.get_opt(key)? {
+            Some(node) => node,
+            None => return Ok(default),
+        };
+        let name = node.as_str()?;
+        match choices.iter().find(|(choice, _)| *choice == name) {
+            Some((_, value)) => Ok(*value),
+            None => {
+                let names = choices.iter().map(|(choice, _)| *choice).collect::<Vec<_>>();
+                node.error(format!("unknown value `{}` (expected one of: {})", name, names.join(", ")))
+            }
+        }
+    }
+
+    fn vec3(&self, key: &str) -> Result<Vec3> {
+        self.get(key)?.as_vec3()
+    }
+
+    fn vec3_or(&self, key: &str, default: Vec3) -> Result<Vec3> {
+        match self.get_opt(key)? {
+            Some(node) => node.as_vec3(),
+            None => Ok(default),
+        }
+    }
+
+    /// The `octaves`, `lacunarity` and `gain` of a fractal noise texture.
+    fn octaves(&self) -> Result<Octaves> {
+        let defaults = Octaves::default();
+        let octaves = match self.get_opt("octaves")? {
+            Some(node) => match node.value.as_u64() {
+                Some(count) => count as usize,
+                None => return node.error(String::from("expected a whole number")),
+            },
+            None => defaults.octaves,
+        };
+        Ok(Octaves {
+            octaves,
+            lacunarity: self.f32_or("lacunarity", defaults.lacunarity)?,
+            gain: self.f32_or("gain", defaults.gain)?,
+        })
+    }
+
+    fn kind(&self) -> Result<&'a str> {
+        self.get("type")?.as_str()
+    }
+}
+
+struct Loader<'a, 'r, R: Rng> {
+    base_dir: &'a Path,
+    texture_defs: Option<Node<'a>>,
+    material_defs: Option<Node<'a>>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<Material>>,
+    resolving: HashSet<String>,
+    time_0: f32,
+    time_1: f32,
+    bvh: BvhBuilder,
+    missing_textures: MissingTextures,
+    rng: &'r mut R,
+}
+
+impl<'a, 'r, R: Rng> Loader<'a, 'r, R> {
+    fn named_texture(&mut self, node: &Node<'a>, name: &str) -> Result<Arc<dyn Texture>> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(Arc::clone(texture));
+        }
+        let definition = match &self.texture_defs {
+            Some(defs) => defs.get_opt(name)?,
+            None => None,
+        };
+        let definition = match definition {
+            Some(definition) => definition,
+            None => return node.error(format!("unknown texture `{}`", name)),
+        };
+        if !self.resolving.insert(name.to_owned()) {
+            return node.error(format!("texture `{}` refers to itself", name));
+        }
+        let texture = self.texture(&definition)?;
+        self.resolving.remove(name);
+        self.textures.insert(name.to_owned(), Arc::clone(&texture));
+        Ok(texture)
+    }
+
+    /// A texture given as a color `[r, g, b]`, the name of a texture in `[textures]`, or an inline table.
+    fn texture_ref(&mut self, node: &Node<'a>) -> Result<Arc<dyn Texture>> {
+        match node.value {
+            Value::Array(_) => Ok(Arc::new(ConstantTexture::new(node.as_vec3()?))),
+            Value::String(name) => self.named_texture(node, name),
+            Value::Object(_) => self.texture(node),
+            _ => node.error(String::from("expected a color, a texture name or a texture table")),
+        }
+    }
+
+    /// Like `texture_ref`, but a single number is also accepted, as a grey constant.
+    fn scalar_texture_ref(&mut self, node: &Node<'a>) -> Result<Arc<dyn Texture>> {
+        match node.value {
+            Value::Number(_) => {
+                let value = node.as_f32()?;
+                Ok(Arc::new(ConstantTexture::new(Vec3::new(value, value, value))))
+            }
+            _ => self.texture_ref(node),
+        }
+    }
+
+    /// The noise of a noise texture: its `basis`, `dimensions`, `period` and the `time` of
+    /// four-dimensional noise, from its own `seed` if there is one and from the scene's random numbers
+    /// otherwise.
+    fn noise(&mut self, node: &Node<'a>) -> Result<Perlin> {
+        let noise = match node.get_opt("seed")? {
+            Some(seed) => match seed.value.as_u64() {
+                Some(seed) => Perlin::with_seed(seed),
+                None => return seed.error(String::from("expected a whole number")),
+            },
+            None => Perlin::new(self.rng),
+        };
+        let noise = match node.get_opt("period")? {
+            Some(period) => match period.value.as_u64() {
+                Some(period) if period > 0 => noise.with_period(period as usize),
+                _ => return period.error(String::from("expected a whole number above 0")),
+            },
+            None => noise,
+        };
+        let basis = node.choice_or(
+            "basis",
+            &[("perlin", NoiseBasis::Perlin), ("simplex", NoiseBasis::Simplex)],
+            NoiseBasis::Perlin,
+        )?;
+        let time = node.f32_or("time", 0.)?;
+        let dimensions = match node.get_opt("dimensions")? {
+            None => NoiseDimensions::Three,
+            Some(dimensions) => match dimensions.value.as_u64() {
+                Some(2) => NoiseDimensions::Two,
+                Some(3) => NoiseDimensions::Three,
+                Some(4) => NoiseDimensions::Four { time },
+                _ => return dimensions.error(String::from("expected 2, 3 or 4")),
+            },
+        };
+        if dimensions != (NoiseDimensions::Four { time }) {
+            if let Some(time) = node.get_opt("time")? {
+                return time.error(String::from("only four-dimensional noise has a time"));
+            }
+        }
+        Ok(noise.with_basis(basis).with_dimensions(dimensions))
+    }
+
+    fn texture(&mut self, node: &Node<'a>) -> Result<Arc<dyn Texture>> {
+        let texture: Arc<dyn Texture> = match node.kind()? {
+            "constant" => {
+                node.check_keys(&["type", "color"])?;
+                Arc::new(ConstantTexture::new(node.vec3("color")?))
+            }
+            "checker" => {
+                node.check_keys(&["type", "even", "odd"])?;
+                let even = self.texture_ref(&node.get("even")?)?;
+                let odd = self.texture_ref(&node.get("odd")?)?;
+                Arc::new(CheckerTexture::new(even, odd))
+            }
+            "noise" => {
+                node.check_keys(&["type", "scale", "seed", "period", "basis", "dimensions", "time"])?;
+                Arc::new(NoiseTexture::with_noise(self.noise(node)?, node.f32("scale")?))
+            }
+            "fbm" | "ridged" => {
+                node.check_keys(&[
+                    "type", "scale", "octaves", "lacunarity", "gain", "seed", "period", "basis", "dimensions", "time",
+                ])?;
+                let noise = Arc::new(self.noise(node)?);
+                let (scale, octaves) = (node.f32_or("scale", 1.)?, node.octaves()?);
+                if node.kind()? == "fbm" {
+                    Arc::new(Fbm::new(noise, scale, octaves))
+                } else {
+                    Arc::new(RidgedNoise::new(noise, scale, octaves))
+                }
+            }
+            "worley" => {
+                node.check_keys(&["type", "scale", "feature", "seed", "period"])?;
+                let feature = node.choice_or(
+                    "feature",
+                    &[
+                        ("nearest", WorleyFeature::Nearest),
+                        ("second_nearest", WorleyFeature::SecondNearest),
+                        ("edges", WorleyFeature::Edges),
+                    ],
+                    WorleyFeature::Nearest,
+                )?;
+                Arc::new(WorleyNoise::new(Arc::new(self.noise(node)?), node.f32_or("scale", 1.)?, feature))
+            }
+            "marble" => {
+                node.check_keys(&[
+                    "type", "scale", "octaves", "lacunarity", "gain", "seed", "period", "basis", "dimensions", "time",
+                    "turbulence", "stripes", "light", "dark",
+                ])?;
+                let mut marble = Marble::new(
+                    Arc::new(self.noise(node)?),
+                    node.f32_or("scale", 1.)?,
+                    node.octaves()?,
+                    node.f32_or("turbulence", 5.)?,
+                    node.vec3_or("light", Vec3::new(1., 1., 1.))?,
+                    node.vec3_or("dark", Vec3::empty())?,
+                );
+                marble.stripes = node.vec3_or("stripes", marble.stripes)?;
+                Arc::new(marble)
+            }
+            "wood" => {
+                node.check_keys(&[
+                    "type", "scale", "octaves", "lacunarity", "gain", "seed", "period", "basis", "dimensions", "time",
+                    "rings", "turbulence", "axis", "light", "dark",
+                ])?;
+                let mut wood = Wood::new(
+                    Arc::new(self.noise(node)?),
+                    node.f32_or("scale", 1.)?,
+                    node.octaves()?,
+                    node.f32_or("rings", 4.)?,
+                    node.f32_or("turbulence", 0.5)?,
+                    node.vec3_or("light", Vec3::new(0.8, 0.6, 0.4))?,
+                    node.vec3_or("dark", Vec3::new(0.45, 0.25, 0.1))?,
+                );
+                if let Some(axis) = node.get_opt("axis")? {
+                    let direction = axis.as_vec3()?;
+                    if direction.length() == 0. {
+                        return axis.error(String::from("expected a non-zero direction"));
+                    }
+                    wood.axis = direction.unit_vector();
+                }
+                Arc::new(wood)
+            }
+            "warp" => {
+                node.check_keys(&[
+                    "type", "texture", "scale", "octaves", "lacunarity", "gain", "seed", "period", "basis",
+                    "dimensions", "time", "strength",
+                ])?;
+                let texture = self.texture_ref(&node.get("texture")?)?;
+                Arc::new(DomainWarp::new(
+                    texture,
+                    Arc::new(self.noise(node)?),
+                    node.f32_or("scale", 1.)?,
+                    node.octaves()?,
+                    node.f32_or("strength", 1.)?,
+                ))
+            }
+            "mix" => {
+                node.check_keys(&["type", "a", "b", "factor"])?;
+                let a = self.scalar_texture_ref(&node.get("a")?)?;
+                let b = self.scalar_texture_ref(&node.get("b")?)?;
+                let factor = self.scalar_texture_ref(&node.get("factor")?)?;
+                Arc::new(MixTexture::new(a, b, factor))
+            }
+            kind @ ("add" | "subtract" | "multiply") => {
+                node.check_keys(&["type", "a", "b"])?;
+                let a = self.scalar_texture_ref(&node.get("a")?)?;
+                let b = self.scalar_texture_ref(&node.get("b")?)?;
+                let mode = match kind {
+                    "add" => BlendMode::Add,
+                    "subtract" => BlendMode::Subtract,
+                    _ => BlendMode::Multiply,
+                };
+                Arc::new(BlendTexture::new(a, b, mode))
+            }
+            "ramp" => {
+                node.check_keys(&["type", "input", "stops"])?;
+                let input = self.scalar_texture_ref(&node.get("input")?)?;
+                let stops_node = node.get("stops")?;
+                let mut stops = Vec::new();
+                for stop in stops_node.as_array()? {
+                    stop.check_keys(&["at", "color"])?;
+                    stops.push((stop.f32("at")?, stop.vec3("color")?));
+                }
+                if stops.is_empty() {
+                    return stops_node.error(String::from("a ramp needs at least one stop"));
+                }
+                Arc::new(ColorRamp::new(input, stops))
+            }
+            "uv_transform" => {
+                node.check_keys(&["type", "texture", "scale", "rotate", "offset"])?;
+                let texture = self.texture_ref(&node.get("texture")?)?;
+                let scale = match node.get_opt("scale")? {
+                    Some(scale) => match scale.as_f32() {
+                        Ok(factor) => (factor, factor),
+                        Err(_) => scale.as_uv()?,
+                    },
+                    None => (1., 1.),
+                };
+                let offset = match node.get_opt("offset")? {
+                    Some(offset) => offset.as_uv()?,
+                    None => (0., 0.),
+                };
+                Arc::new(UvTransform::new(texture, scale, node.f32_or("rotate", 0.)?, offset))
+            }
+            "transform" => {
+                node.check_keys(&["type", "texture", "steps"])?;
+                let matrix = transform_steps(&node.get("steps")?)?;
+                Arc::new(WorldTransform::new(self.texture_ref(&node.get("texture")?)?, matrix))
+            }
+            "triplanar" => {
+                node.check_keys(&["type", "texture", "scale", "sharpness"])?;
+                let texture = self.texture_ref(&node.get("texture")?)?;
+                Arc::new(Triplanar::new(texture, node.f32_or("scale", 1.)?, node.f32_or("sharpness", 4.)?))
+            }
+            "image" => {
+                node.check_keys(&["type", "file", "alpha", "wrap", "filter", "color_space"])?;
+                let file = node.get("file")?;
+                let path = self.base_dir.join(file.as_str()?);
+                let defaults = ImageSettings::default();
+                let settings = ImageSettings {
+                    wrap: node.choice_or(
+                        "wrap",
+                        &[("repeat", Wrap::Repeat), ("clamp", Wrap::Clamp), ("mirror", Wrap::Mirror)],
+                        defaults.wrap,
+                    )?,
+                    filter: node.choice_or(
+                        "filter",
+                        &[
+                            ("nearest", Filter::Nearest),
+                            ("bilinear", Filter::Bilinear),
+                            ("trilinear", Filter::Trilinear),
+                        ],
+                        defaults.filter,
+                    )?,
+                    color_space: node.choice_or(
+                        "color_space",
+                        &[("srgb", ColorSpace::Srgb), ("linear", ColorSpace::Linear)],
+                        defaults.color_space,
+                    )?,
+                };
+                let image = match load_image(&path.to_string_lossy(), settings, self.missing_textures) {
+                    Ok(image) => image,
+                    Err(error) => return file.error(error.to_string()),
+                };
+                match node.get_opt("alpha")? {
+                    Some(alpha) if alpha.as_bool()? => Arc::new(AlphaTexture::new(Arc::new(image))),
+                    _ => Arc::new(image),
+                }
+            }
+            other => {
+                return node.get("type")?.error(format!(
+                    "unknown texture type `{}` (expected constant, checker, noise, fbm, ridged, worley, marble, wood, \
+                     warp, mix, add, subtract, multiply, ramp, uv_transform, transform, triplanar or image)",
+                    other
+                ))
+            }
+        };
+        Ok(texture)
+    }
+
+    fn named_material(&mut self, node: &Node<'a>, name: &str) -> Result<Arc<Material>> {
+        if let Some(material) = self.materials.get(name) {
+            return Ok(Arc::clone(material));
+        }
+        let definition = match &self.material_defs {
+            Some(defs) => defs.get_opt(name)?,
+            None => None,
+        };
+        match definition {
+            Some(definition) => {
+                let material = self.material(&definition)?;
+                self.materials.insert(name.to_owned(), Arc::clone(&material));
+                Ok(material)
+            }
+            None => node.error(format!("unknown material `{}`", name)),
+        }
+    }
+
+    /// A material given as the name of a material in `[materials]` or as an inline table.
+    fn material_ref(&mut self, node: &Node<'a>) -> Result<Arc<Material>> {
+        match node.value {
+            Value::String(name) => self.named_material(node, name),
+            Value::Object(_) => self.material(node),
+            _ => node.error(String::from("expected a material name or a material table")),
+        }
+    }
+
+    fn material(&mut self, node: &Node<'a>) -> Result<Arc<Material>> {
+        let material = match node.kind()? {
+            "lambertian" => {
+                node.check_keys(&["type", "albedo"])?;
+                Material::lambertian(self.texture_ref(&node.get("albedo")?)?)
+            }
+            "metal" => {
+                node.check_keys(&["type", "albedo", "fuzz"])?;
+                Material::metal(node.vec3("albedo")?, node.f32_or("fuzz", 0.)?)
+            }
+            "dielectric" => {
+                node.check_keys(&["type", "ref_idx", "roughness", "absorption"])?;
+                let roughness = match node.get_opt("roughness")? {
+                    Some(roughness) => Some(self.scalar_texture_ref(&roughness)?),
+                    None => None,
+                };
+                let absorption = match node.get_opt("absorption")? {
+                    Some(absorption) => absorption.as_vec3()?,
+                    None => Vec3::empty(),
+                };
+                Material::glass(refractive_index(&node.get("ref_idx")?)?, roughness, absorption)
+            }
+            "diffuse_light" => {
+                node.check_keys(&["type", "emit", "one_sided"])?;
+                let emit = self.texture_ref(&node.get("emit")?)?;
+                match node.get_opt("one_sided")? {
+                    Some(one_sided) if one_sided.as_bool()? => Material::one_sided_light(emit),
+                    _ => Material::diffuse_light(emit),
+                }
+            }
+            "isotropic" => {
+                node.check_keys(&["type", "albedo"])?;
+                Material::isotropic(self.texture_ref(&node.get("albedo")?)?)
+            }
+            "microfacet" => {
+                node.check_keys(&["type", "albedo", "roughness", "metallic", "ior"])?;
+                let metallic = match node.get_opt("metallic")? {
+                    Some(metallic) => self.scalar_texture_ref(&metallic)?,
+                    None => Arc::new(ConstantTexture::new(Vec3::empty())),
+                };
+                Material::microfacet(
+                    self.texture_ref(&node.get("albedo")?)?,
+                    self.scalar_texture_ref(&node.get("roughness")?)?,
+                    metallic,
+                    node.f32_or("ior", 1.5)?,
+                )
+            }
+            other => {
+                return node.get("type")?.error(format!(
+                    "unknown material type `{}` (expected lambertian, metal, dielectric, diffuse_light, isotropic \
+                     or microfacet)",
+                    other
+                ))
+            }
+        };
+        Ok(Arc::new(material))
+    }
+
+    fn objects(&mut self, node: &Node<'a>) -> Result<Vec<Arc<dyn Hitable>>> {
+        node.as_array()?.iter().map(|item| self.object(item)).collect()
+    }
+
+    fn object(&mut self, node: &Node<'a>) -> Result<Arc<dyn Hitable>> {
+        let object: Arc<dyn Hitable> = match node.kind()? {
+            "sphere" => {
+                node.check_keys(&["type", "center", "radius", "material"])?;
+                Arc::new(Sphere::new(
+                    node.vec3("center")?,
+                    node.f32("radius")?,
+                    self.material_ref(&node.get("material")?)?,
+                ))
+            }
+            "moving_sphere" => {
+                node.check_keys(&["type", "center0", "center1", "time0", "time1", "radius", "material"])?;
+                Arc::new(MovingSphere::new(
+                    node.vec3("center0")?,
+                    node.vec3("center1")?,
+                    node.f32_or("time0", 0.)?,
+                    node.f32_or("time1", 1.)?,
+                    node.f32("radius")?,
+                    self.material_ref(&node.get("material")?)?,
+                ))
+            }
+            "xy_rect" => {
+                node.check_keys(&["type", "x0", "x1", "y0", "y1", "k", "material"])?;
+                Arc::new(XYRectangle::new(
+                    node.f32("x0")?,
+                    node.f32("x1")?,
+                    node.f32("y0")?,
+                    node.f32("y1")?,
+                    node.f32("k")?,
+                    self.material_ref(&node.get("material")?)?,
+                ))
+            }
+            "xz_rect" => {
+                node.check_keys(&["type", "x0", "x1", "z0", "z1", "k", "material"])?;
+                Arc::new(XZRectangle::new(
+                    node.f32("x0")?,
+                    node.f32("x1")?,
+                    node.f32("z0")?,
+                    node.f32("z1")?,
+                    node.f32("k")?,
+                    self.material_ref(&node.get("material")?)?,
+                ))
+            }
+            "yz_rect" => {
+                node.check_keys(&["type", "y0", "y1", "z0", "z1", "k", "material"])?;
+                Arc::new(YZRectangle::new(
+                    node.f32("y0")?,
+                    node.f32("y1")?,
+                    node.f32("z0")?,
+                    node.f32("z1")?,
+                    node.f32("k")?,
+                    self.material_ref(&node.get("material")?)?,
+                ))
+            }
+            "cuboid" => {
+                node.check_keys(&["type", "min", "max", "material"])?;
+                let material = self.material_ref(&node.get("material")?)?;
+                Arc::new(Cuboid::new(node.vec3("min")?, node.vec3("max")?, &material))
+            }
+            "triangle" => {
+                node.check_keys(&["type", "vertices", "material"])?;
+                let vertices_node = node.get("vertices")?;
+                let vertices = vertices_node.as_array()?;
+                if vertices.len() != 3 {
+                    return vertices_node.error(String::from("a triangle needs exactly three vertices"));
+                }
+                Arc::new(Triangle::new(
+                    vertices[0].as_vec3()?,
+                    vertices[1].as_vec3()?,
+                    vertices[2].as_vec3()?,
+                    self.material_ref(&node.get("material")?)?,
+                ))
+            }
+            "mesh" => {
+                node.check_keys(&["type", "vertices", "faces", "normals", "uvs", "material"])?;
+                let positions = node
+                    .get("vertices")?
+                    .as_array()?
+                    .iter()
+                    .map(Node::as_vec3)
+                    .collect::<Result<Vec<_>>>()?;
+                let count = positions.len();
+                let faces_node = node.get("faces")?;
+                let mut indices = Vec::new();
+                for face in faces_node.as_array()? {
+                    let corners = face.as_array()?;
+                    if corners.len() != 3 {
+                        return face.error(String::from("expected three vertex indices"));
+                    }
+                    indices.push([
+                        corners[0].as_index(count)?,
+                        corners[1].as_index(count)?,
+                        corners[2].as_index(count)?,
+                    ]);
+                }
+                if indices.is_empty() {
+                    return faces_node.error(String::from("a mesh needs at least one face"));
+                }
+                let normals = match node.get_opt("normals")? {
+                    Some(normals_node) => {
+                        let normals = normals_node.as_array()?.iter().map(Node::as_vec3).collect::<Result<Vec<_>>>()?;
+                        if normals.len() != count {
+                            return normals_node.error(format!("expected one normal per vertex ({})", count));
+                        }
+                        Some(normals)
+                    }
+                    None => None,
+                };
+                let uvs = match node.get_opt("uvs")? {
+                    Some(uvs_node) => {
+                        let uvs = uvs_node.as_array()?.iter().map(Node::as_uv).collect::<Result<Vec<_>>>()?;
+                        if uvs.len() != count {
+                            return uvs_node.error(format!("expected one texture coordinate per vertex ({})", count));
+                        }
+                        Some(uvs)
+                    }
+                    None => None,
+                };
+                let mesh = MeshData {
+                    positions,
+                    normals,
+                    uvs,
+                    indices,
+                    material: self.material_ref(&node.get("material")?)?,
+                };
+                Arc::new(TriangleMesh::new(mesh, self.bvh, self.rng))
+            }
+            "obj" => {
+                node.check_keys(&["type", "file", "material"])?;
+                let file = node.get("file")?;
+                let default_material = match node.get_opt("material")? {
+                    Some(material) => self.material_ref(&material)?,
+                    None => Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73))))),
+                };
+                let path = self.base_dir.join(file.as_str()?);
+                let path = path.to_string_lossy();
+                match obj::load_obj(&path, default_material, self.missing_textures, self.bvh, self.rng) {
+                    Ok(mesh) => mesh,
+                    Err(error) => return file.error(error.to_string()),
+                }
+            }
+            "translate" => {
+                node.check_keys(&["type", "offset", "object"])?;
+                Arc::new(Translate::new(self.object(&node.get("object")?)?, node.vec3("offset")?))
+            }
+            "rotate_y" => {
+                node.check_keys(&["type", "angle", "object"])?;
+                Arc::new(RotateY::new(self.object(&node.get("object")?)?, node.f32("angle")?))
+            }
+            "transform" => {
+                node.check_keys(&["type", "steps", "object"])?;
+                let matrix = transform_steps(&node.get("steps")?)?;
+                Arc::new(Transform::with_matrix(self.object(&node.get("object")?)?, matrix))
+            }
+            "animated" => {
+                node.check_keys(&["type", "keyframes", "object"])?;
+                let keyframes_node = node.get("keyframes")?;
+                let keyframes = keyframes_node
+                    .as_array()?
+                    .iter()
+                    .map(keyframe)
+                    .collect::<Result<Vec<_>>>()?;
+                if keyframes.is_empty() {
+                    return keyframes_node.error(String::from("an animated object needs at least one keyframe"));
+                }
+                Arc::new(AnimatedTransform::new(self.object(&node.get("object")?)?, keyframes))
+            }
+            "flip_normals" => {
+                node.check_keys(&["type", "object"])?;
+                Arc::new(FlipNormals::new(self.object(&node.get("object")?)?))
+            }
+            "bump_map" => {
+                node.check_keys(&["type", "height", "scale", "object"])?;
+                Arc::new(BumpMap::new(
+                    self.object(&node.get("object")?)?,
+                    self.scalar_texture_ref(&node.get("height")?)?,
+                    node.f32_or("scale", 1.)?,
+                ))
+            }
+            "normal_map" => {
+                node.check_keys(&["type", "map", "object"])?;
+                Arc::new(NormalMap::new(self.object(&node.get("object")?)?, self.texture_ref(&node.get("map")?)?))
+            }
+            "cutout" => {
+                node.check_keys(&["type", "alpha", "threshold", "object"])?;
+                Arc::new(AlphaCutout::new(
+                    self.object(&node.get("object")?)?,
+                    self.texture_ref(&node.get("alpha")?)?,
+                    node.f32_or("threshold", 0.5)?,
+                ))
+            }
+            "constant_medium" => {
+                node.check_keys(&["type", "boundary", "density", "albedo"])?;
+                Arc::new(ConstantMedium::new(
+                    self.object(&node.get("boundary")?)?,
+                    node.f32("density")?,
+                    self.texture_ref(&node.get("albedo")?)?,
+                ))
+            }
+            "bvh" => {
+                node.check_keys(&["type", "objects"])?;
+                let objects_node = node.get("objects")?;
+                let mut objects = self.objects(&objects_node)?;
+                if objects.is_empty() {
+                    return objects_node.error(String::from("a bvh needs at least one object"));
+                }
+                Arc::new(self.bvh.build(&mut objects, self.time_0, self.time_1, self.rng))
+            }
+            other => {
+                return node.get("type")?.error(format!(
+                    "unknown object type `{}` (expected sphere, moving_sphere, xy_rect, xz_rect, yz_rect, \
+                     cuboid, triangle, mesh, obj, translate, rotate_y, transform, animated, flip_normals, \
+                     cutout, bump_map, normal_map, constant_medium or bvh)",
+                    other
+                ))
+            }
+        };
+        Ok(object)
+    }
+}
+
+/// The `steps` of a transform, applied in order, which must leave the transform invertible.
+fn transform_steps(node: &Node) -> Result<Matrix4> {
+    let mut matrix = Matrix4::identity();
+    for step in node.as_array()? {
+        matrix = transform_step(&step)? * matrix;
+    }
+    if matrix.inverse().is_none() {
+        return node.error(String::from("the transform is not invertible"));
+    }
+    Ok(matrix)
+}
+
+/// One step of a `transform` object: a table with a single key naming the operation.
+fn transform_step(node: &Node) -> Result<Matrix4> {
+    let table = node.table()?;
+    if table.len() != 1 {
+        return node.error(String::from(
+            "expected a single key: translate, scale, rotate_x, rotate_y, rotate_z, look_at or matrix",
+        ));
+    }
+    let (key, value) = table.iter().next().unwrap();
+    let value = node.child(key, value);
+    Ok(match key.as_str() {
+        "translate" => Matrix4::translation(value.as_vec3()?),
+        "scale" => match value.as_f32() {
+            Ok(factor) => Matrix4::scaling(Vec3::new(factor, factor, factor)),
+            Err(_) => Matrix4::scaling(value.as_vec3()?),
+        },
+        "rotate_x" => Matrix4::rotation_x(value.as_f32()?),
+        "rotate_y" => Matrix4::rotation_y(value.as_f32()?),
+        "rotate_z" => Matrix4::rotation_z(value.as_f32()?),
+        "look_at" => {
+            value.check_keys(&["from", "at", "up"])?;
+            let up = match value.get_opt("up")? {
+                Some(up) => up.as_vec3()?,
+                None => Vec3::new(0., 1., 0.),
+            };
+            Matrix4::look_at(value.vec3("from")?, value.vec3("at")?, up)
+        }
+        "matrix" => {
+            let rows = value.as_array()?;
+            if rows.len() != 4 {
+                return value.error(String::from("expected four rows of four numbers"));
+            }
+            let mut m = [[0.; 4]; 4];
+            for (i, row) in rows.iter().enumerate() {
+                let items = row.as_array()?;
+                if items.len() != 4 {
+                    return row.error(String::from("expected four numbers"));
+                }
+                for (j, item) in items.iter().enumerate() {
+                    m[i][j] = item.as_f32()?;
+                }
+            }
+            Matrix4::new(m)
+        }
+        other => {
+            return value.error(format!(
+                "unknown transform `{}` (expected translate, scale, rotate_x, rotate_y, rotate_z, look_at or matrix)",
+                other
+            ))
+        }
+    })
+}
+
+/// A dielectric's `ref_idx`: a number, or a `cauchy` or `sellmeier` table of dispersion coefficients.
+fn refractive_index(node: &Node) -> Result<RefractiveIndex> {
+    if let Ok(index) = node.as_f32() {
+        return Ok(RefractiveIndex::Constant(index));
+    }
+    match node.kind()? {
+        "cauchy" => {
+            node.check_keys(&["type", "a", "b"])?;
+            Ok(RefractiveIndex::Cauchy {
+                a: node.f32("a")?,
+                b: node.f32("b")?,
+            })
+        }
+        "sellmeier" => {
+            node.check_keys(&["type", "b", "c"])?;
+            let (b, c) = (node.vec3("b")?, node.vec3("c")?);
+            Ok(RefractiveIndex::Sellmeier {
+                b: [b.x, b.y, b.z],
+                c: [c.x, c.y, c.z],
+            })
+        }
+        other => node
+            .get("type")?
+            .error(format!("unknown refractive index type `{}` (expected cauchy or sellmeier)", other)),
+    }
+}
+
+fn keyframe(node: &Node) -> Result<Keyframe> {
+    node.check_keys(&["time", "translate", "rotate", "scale"])?;
+    let translation = match node.get_opt("translate")? {
+        Some(translate) => translate.as_vec3()?,
+        None => Vec3::empty(),
+    };
+    let rotation = match node.get_opt("rotate")? {
+        Some(rotate) => {
+            rotate.check_keys(&["axis", "angle"])?;
+            let axis = rotate.vec3("axis")?;
+            if axis.length() == 0. {
+                return rotate.get("axis")?.error(String::from("expected a non-zero direction"));
+            }
+            Quaternion::from_axis_angle(axis, rotate.f32("angle")?)
+        }
+        None => Quaternion::identity(),
+    };
+    let scale = match node.get_opt("scale")? {
+        Some(scale) => match scale.as_f32() {
+            Ok(factor) => Vec3::new(factor, factor, factor),
+            Err(_) => scale.as_vec3()?,
+        },
+        None => Vec3::new(1., 1., 1.),
+    };
+    if scale.x * scale.y * scale.z == 0. {
+        return node.get("scale")?.error(String::from("scale factors must not be zero"));
+    }
+    Ok(Keyframe::new(node.f32("time")?, translation, rotation, scale))
+}
+
+fn camera(node: &Node) -> Result<CameraSettings> {
+    node.check_keys(&["look_from", "look_at", "vup", "vfov", "aperture", "focus_dist", "time0", "time1"])?;
+    let defaults = CameraSettings::default();
+    Ok(CameraSettings {
+        look_from: node.vec3("look_from")?,
+        look_at: node.vec3("look_at")?,
+        vup: match node.get_opt("vup")? {
+            Some(vup) => vup.as_vec3()?,
+            None => defaults.vup,
+        },
+        vfov: node.f32_or("vfov", defaults.vfov)?,
+        aperture: node.f32_or("aperture", defaults.aperture)?,
+        focus_dist: node.f32_or("focus_dist", defaults.focus_dist)?,
+        time_0: node.f32_or("time0", defaults.time_0)?,
+        time_1: node.f32_or("time1", defaults.time_1)?,
+    })
+}
+
+/// Builds a scene from an already parsed document. Relative image paths are resolved against `base_dir`,
+/// images that can't be loaded are handled as `missing_textures` says, and `bvh` objects are built with `bvh`.
+pub fn build_scene<R: Rng>(
+    document: &Value,
+    base_dir: &Path,
+    bvh: BvhBuilder,
+    missing_textures: MissingTextures,
+    rng: &mut R,
+) -> Result<SceneFile> {
+    let root = Node {
+        value: document,
+        path: String::new(),
+    };
+    root.check_keys(&["camera", "external_light", "textures", "materials", "objects"])?;
+    let camera = camera(&root.get("camera")?)?;
+    let external_light = match root.get_opt("external_light")? {
+        Some(node) => node.as_bool()?,
+        None => false,
+    };
+    let texture_defs = root.get_opt("textures")?;
+    let material_defs = root.get_opt("materials")?;
+    for defs in texture_defs.iter().chain(material_defs.iter()) {
+        defs.table()?;
+    }
+    let mut loader = Loader {
+        base_dir,
+        texture_defs,
+        material_defs,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        resolving: HashSet::new(),
+        time_0: camera.time_0,
+        time_1: camera.time_1,
+        bvh,
+        missing_textures,
+        rng,
+    };
+    let objects_node = root.get("objects")?;
+    let objects = loader.objects(&objects_node)?;
+    if objects.is_empty() {
+        return objects_node.error(String::from("the scene has no objects"));
+    }
+    Ok(SceneFile {
+        camera,
+        external_light,
+        objects,
+    })
+}
+
+/// Parses a `.toml` or `.json` scene description.
+pub fn parse(text: &str, format: &str) -> Result<Value> {
+    match format {
+        "toml" => toml::from_str(text).map_err(|error| SceneFileError::Parse(error.to_string())),
+        "json" => serde_json::from_str(text).map_err(|error| SceneFileError::Parse(error.to_string())),
+        _ => Err(SceneFileError::Parse(format!(
+            "unknown scene format `{}` (expected .toml or .json)",
+            format
+        ))),
+    }
+}
+
+pub fn load<R: Rng>(path: &str, bvh: BvhBuilder, missing_textures: MissingTextures, rng: &mut R) -> Result<SceneFile> {
+    let path = Path::new(path);
+    let format = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let document = parse(&fs::read_to_string(path)?, format)?;
+    build_scene(&document, path.parent().unwrap_or_else(|| Path::new(".")), bvh, missing_textures, rng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    fn build(text: &str) -> Result<SceneFile> {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        build_scene(&parse(text, "toml")?, Path::new("."), BvhBuilder::Sah, MissingTextures::Fail, &mut rng)
+    }
+
+    fn error_message(text: &str) -> String {
+        build(text).expect_err("scene should be rejected").to_string()
+    }
+
+    const CAMERA: &str = "[camera]\nlook_from = [0.0, 0.0, 0.0]\nlook_at = [0.0, 0.0, -1.0]\n";
+
+    #[test]
+    fn example_scenes_load() {
+        for entry in fs::read_dir("scenes").unwrap() {
+            let path = entry.unwrap().path();
+            if !path.is_file() {
+                continue;
+            }
+            // final.toml needs earth.png, which is not part of the repository, so it gets a placeholder.
+            let mut rng = XorShiftRng::seed_from_u64(0);
+            let scene = load(path.to_str().unwrap(), BvhBuilder::Sah, MissingTextures::Placeholder, &mut rng)
+                .unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+            assert!(!scene.objects.is_empty());
+        }
+    }
+
+    #[test]
+    fn named_materials_are_shared() {
+        let scene = build(&format!(
+            "{}[materials.white]\ntype = \"lambertian\"\nalbedo = [1.0, 1.0, 1.0]\n\
+             [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -2.0]\nradius = 1.0\nmaterial = \"white\"\n\
+             [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -5.0]\nradius = 1.0\nmaterial = \"white\"\n",
+            CAMERA
+        ))
+        .unwrap();
+        assert_eq!(scene.objects.len(), 2);
+    }
+
+    #[test]
+    fn errors_name_the_offending_key() {
+        let sphere = "[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -2.0]\n";
+        assert_eq!(
+            error_message(&format!("{}{}material = \"missing\"\nradius = 1.0\n", CAMERA, sphere)),
+            "objects[0].material: unknown material `missing`"
+        );
+        assert_eq!(
+            error_message(&format!("{}{}material = {{ type = \"dielectric\", ref_idx = 1.5 }}\n", CAMERA, sphere)),
+            "objects[0]: missing key `radius`"
+        );
+        assert_eq!(
+            error_message(&format!(
+                "{}{}radius = \"big\"\nmaterial = {{ type = \"dielectric\", ref_idx = 1.5 }}\n",
+                CAMERA, sphere
+            )),
+            "objects[0].radius: expected a number"
+        );
+        assert!(error_message(&format!(
+            "{}{}radius = 1.0\nmaterial = {{ type = \"dielectric\", ref_idx = 1.5 }}\nradious = 2.0\n",
+            CAMERA, sphere
+        ))
+        .starts_with("objects[0].radious: unknown key"));
+    }
+
+    #[test]
+    fn texture_cycles_are_reported() {
+        let message = error_message(&format!(
+            "{}[textures.a]\ntype = \"checker\"\neven = \"b\"\nodd = [1.0, 1.0, 1.0]\n\
+             [textures.b]\ntype = \"checker\"\neven = \"a\"\nodd = [1.0, 1.0, 1.0]\n\
+             [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -2.0]\nradius = 1.0\n\
+             material = {{ type = \"lambertian\", albedo = \"a\" }}\n",
+            CAMERA
+        ));
+        assert_eq!(message, "textures.b.even: texture `a` refers to itself");
+    }
+
+    #[test]
+    fn missing_images_are_reported_or_replaced() {
+        let text = format!(
+            "{}[textures.earth]\ntype = \"image\"\nfile = \"no_such_earth.png\"\n\
+             [[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -2.0]\nradius = 1.0\n\
+             material = {{ type = \"lambertian\", albedo = \"earth\" }}\n",
+            CAMERA
+        );
+        assert!(error_message(&text).starts_with("textures.earth.file: could not load ./no_such_earth.png: "));
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let document = parse(&text, "toml").unwrap();
+        let scene = build_scene(&document, Path::new("."), BvhBuilder::Sah, MissingTextures::Placeholder, &mut rng);
+        assert_eq!(scene.unwrap().objects.len(), 1);
+    }
+
+    #[test]
+    fn texture_nodes_check_their_inputs() {
+        let scene_with = |texture: &str| {
+            format!(
+                "{}[textures.node]\n{}\n[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -2.0]\nradius = 1.0\n\
+                 material = {{ type = \"lambertian\", albedo = \"node\" }}\n",
+                CAMERA, texture
+            )
+        };
+        assert_eq!(
+            error_message(&scene_with("type = \"ramp\"\ninput = 0.5\nstops = []")),
+            "textures.node.stops: a ramp needs at least one stop"
+        );
+        assert_eq!(
+            error_message(&scene_with("type = \"transform\"\ntexture = [1.0, 0.0, 0.0]\nsteps = [{ scale = 0.0 }]")),
+            "textures.node.steps: the transform is not invertible"
+        );
+        assert!(build(&scene_with("type = \"mix\"\na = [1.0, 0.0, 0.0]\nb = 0.5\nfactor = 0.2")).is_ok());
+    }
+
+    #[test]
+    fn noise_options_are_checked() {
+        let scene_with = |options: &str| {
+            format!(
+                "{}[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -2.0]\nradius = 1.0\n\
+                 material = {{ type = \"lambertian\", albedo = {{ type = \"fbm\", {} }} }}\n",
+                CAMERA, options
+            )
+        };
+        assert!(build(&scene_with("seed = 4, basis = \"simplex\", dimensions = 4, time = 0.5")).is_ok());
+        assert_eq!(
+            error_message(&scene_with("dimensions = 5")),
+            "objects[0].material.albedo.dimensions: expected 2, 3 or 4"
+        );
+        assert_eq!(
+            error_message(&scene_with("time = 0.5")),
+            "objects[0].material.albedo.time: only four-dimensional noise has a time"
+        );
+        assert_eq!(error_message(&scene_with("seed = -1")), "objects[0].material.albedo.seed: expected a whole number");
+        assert!(build(&scene_with("seed = 4, period = 16")).is_ok());
+        assert_eq!(
+            error_message(&scene_with("period = 0")),
+            "objects[0].material.albedo.period: expected a whole number above 0"
+        );
+    }
+
+    #[test]
+    fn keyframes_are_checked() {
+        let scene_with = |keyframe: &str| {
+            format!(
+                "{}[[objects]]\ntype = \"animated\"\nkeyframes = [{{ time = 0.0 }}, {{ time = 1.0, {} }}]\n\
+                 object = {{ type = \"sphere\", center = [0.0, 0.0, -2.0], radius = 1.0, material = \"white\" }}\n\
+                 [materials.white]\ntype = \"lambertian\"\nalbedo = [1.0, 1.0, 1.0]\n",
+                CAMERA, keyframe
+            )
+        };
+        assert!(build(&scene_with("rotate = { axis = [0.0, 1.0, 0.0], angle = 90.0 }, scale = 2.0")).is_ok());
+        assert_eq!(
+            error_message(&scene_with("rotate = { axis = [0.0, 0.0, 0.0], angle = 90.0 }")),
+            "objects[0].keyframes[1].rotate.axis: expected a non-zero direction"
+        );
+        assert_eq!(
+            error_message(&scene_with("scale = [1.0, 0.0, 1.0]")),
+            "objects[0].keyframes[1].scale: scale factors must not be zero"
+        );
+    }
+}
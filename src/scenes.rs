@@ -0,0 +1,459 @@
+use crate::camera::CameraSettings;
+use crate::hitables::bvh::BvhBuilder;
+use crate::hitables::cuboids::Cuboid;
+use crate::hitables::mediums::ConstantMedium;
+use crate::hitables::rectangles::*;
+use crate::hitables::spheres::{MovingSphere, Sphere};
+use crate::hitables::transforms::{FlipNormals, RotateY, Translate};
+use crate::hitables::Hitable;
+use crate::materials::Material;
+use crate::textures::{load_image, CheckerTexture, ConstantTexture, ImageSettings, MissingTextures, NoiseTexture, TextureError};
+use crate::vectors::Vec3;
+use rand::Rng;
+use std::sync::Arc;
+
+pub fn random_scene<R: Rng>(rng: &mut R) -> Vec<Arc<dyn Hitable>> {
+    let n = 500;
+    let mut world: Vec<Arc<dyn Hitable>> = Vec::with_capacity(n + 1);
+    let checker = Arc::new(CheckerTexture::new(
+        Arc::new(ConstantTexture::new(Vec3::new(0.2, 0.3, 0.1))),
+        Arc::new(ConstantTexture::new(Vec3::new(0.9, 0.9, 0.9))),
+    ));
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(0., -1000., 0.),
+        1000.,
+        Arc::new(Material::lambertian(checker)),
+    )));
+    for a in -10..10 {
+        for b in -10..10 {
+            let choose_material = rng.gen::<f32>();
+            let center = Vec3::new(
+                (a as f32) + 0.9 * rng.gen::<f32>(),
+                0.2,
+                (b as f32) + 0.9 * rng.gen::<f32>(),
+            );
+            if (center - Vec3::new(4., 0.2, 0.)).length() > 0.9 {
+                if choose_material < 0.8 {
+                    world.push(Arc::new(MovingSphere::new(
+                        center,
+                        center + Vec3::new(0., 0.5 * rng.gen::<f32>(), 0.),
+                        0.,
+                        1.,
+                        0.2,
+                        Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                            rng.gen::<f32>() * rng.gen::<f32>(),
+                        ))))),
+                    )));
+                } else if choose_material < 0.95 {
+                    world.push(Arc::new(Sphere::new(
+                        center,
+                        0.2,
+                        Arc::new(Material::metal(
+                            Vec3::new(
+                                0.5 * (1. + rng.gen::<f32>()),
+                                0.5 * (1. + rng.gen::<f32>()),
+                                0.5 * (1. + rng.gen::<f32>()),
+                            ),
+                            0.5 * rng.gen::<f32>(),
+                        ),
+                    ))));
+                } else {
+                    world.push(Arc::new(Sphere::new(
+                        center,
+                        0.2,
+                        Arc::new(Material::dielectric(1.5)),
+                    )));
+                }
+            }
+        }
+    }
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(0., 1., 0.),
+        1.,
+        Arc::new(Material::dielectric(1.5)),
+    )));
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(-4., 1., 0.),
+        1.,
+        Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.4, 0.2, 0.1))))),
+    )));
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(4., 1., 0.),
+        1.,
+        Arc::new(Material::metal(Vec3::new(0.7, 0.6, 0.5), 0.)),
+    )));
+    world
+}
+
+pub fn two_spheres() -> Vec<Arc<dyn Hitable>> {
+    let checker = Arc::new(CheckerTexture::new(
+        Arc::new(ConstantTexture::new(Vec3::new(0.2, 0.3, 0.1))),
+        Arc::new(ConstantTexture::new(Vec3::new(0.9, 0.9, 0.9))),
+    ));
+    let n = 50;
+    let mut world = Vec::with_capacity(n + 1);
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(0., -10., 0.),
+        10.,
+        Arc::new(Material::lambertian(checker.clone())),
+    )) as Arc<dyn Hitable>);
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(0., 10., 0.),
+        10.,
+        Arc::new(Material::lambertian(checker)),
+    )) as Arc<dyn Hitable>);
+    world
+}
+
+pub fn two_perlin_spheres<R: Rng>(rng: &mut R) -> Vec<Arc<dyn Hitable>> {
+    let perlin_texture = Arc::new(NoiseTexture::new(rng, 5.));
+    vec![
+        Arc::new(Sphere::new(
+            Vec3::new(0., -1000., 0.),
+            1000.,
+            Arc::new(Material::lambertian(perlin_texture.clone())),
+        )) as Arc<dyn Hitable>,
+        Arc::new(Sphere::new(
+            Vec3::new(0., 2., 0.),
+            2.,
+            Arc::new(Material::lambertian(perlin_texture)),
+        )) as Arc<dyn Hitable>,
+    ]
+}
+
+pub fn simple_light<R: Rng>(rng: &mut R) -> Vec<Arc<dyn Hitable>> {
+    let perlin_texture = Arc::new(NoiseTexture::new(rng, 4.));
+    vec![
+        Arc::new(Sphere::new(
+            Vec3::new(0., -1000., 0.),
+            1000.,
+            Arc::new(Material::lambertian(perlin_texture.clone())),
+        )) as Arc<dyn Hitable>,
+        Arc::new(Sphere::new(
+            Vec3::new(0., 2., 0.),
+            2.,
+            Arc::new(Material::lambertian(perlin_texture)),
+        )) as Arc<dyn Hitable>,
+        //    Arc::new(Sphere::new(Vec3::new(0., 2., 0.), 2.,
+        //                        Material::diffuse_light(Arc::new(ConstantTexture::new(Vec3::new(4., 4., 4.)))))) as Arc<dyn Hitable>,
+        Arc::new(Sphere::new(
+            Vec3::new(0., 7., 0.),
+            2.,
+            Arc::new(Material::diffuse_light(Arc::new(ConstantTexture::new(Vec3::new(4., 4., 4.))))),
+        )) as Arc<dyn Hitable>,
+        Arc::new(XYRectangle::new(
+            3.,
+            5.,
+            1.,
+            3.,
+            -2.,
+            Arc::new(Material::diffuse_light(Arc::new(ConstantTexture::new(Vec3::new(4., 4., 4.))))),
+        )) as Arc<dyn Hitable>,
+    ]
+}
+
+pub fn cornell_box() -> Vec<Arc<dyn Hitable>> {
+    let mut world = Vec::with_capacity(6);
+    let red = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
+    let white = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
+    let green = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.12, 0.45, 0.15)))));
+    let light = Arc::new(Material::one_sided_light(Arc::new(ConstantTexture::new(Vec3::new(15., 15., 15.)))));
+    world.push(Arc::new(YZRectangle::new(0., 555., 0., 555., 555., green)) as Arc<dyn Hitable>);
+    world.push(Arc::new(YZRectangle::new(0., 555., 0., 555., 0., red)) as Arc<dyn Hitable>);
+    // Facing down into the box.
+    let ceiling_light = XZRectangle::new(213., 343., 227., 332., 554., light);
+    world.push(Arc::new(FlipNormals::new(Arc::new(ceiling_light))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XZRectangle::new(0., 555., 0., 555., 555., Arc::clone(&white))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XZRectangle::new(0., 555., 0., 555., 0., Arc::clone(&white))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XYRectangle::new(0., 555., 0., 555., 555., Arc::clone(&white))) as Arc<dyn Hitable>);
+    world
+}
+
+pub fn cornell_box_with_cuboids() -> Vec<Arc<dyn Hitable>> {
+    let mut world = Vec::with_capacity(8);
+    let red = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
+    let white = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
+    let green = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.12, 0.45, 0.15)))));
+    let light = Arc::new(Material::one_sided_light(Arc::new(ConstantTexture::new(Vec3::new(15., 15., 15.)))));
+    world.push(Arc::new(YZRectangle::new(0., 555., 0., 555., 555., green)) as Arc<dyn Hitable>);
+    world.push(Arc::new(YZRectangle::new(0., 555., 0., 555., 0., red)) as Arc<dyn Hitable>);
+    // Facing down into the box.
+    let ceiling_light = XZRectangle::new(213., 343., 227., 332., 554., light);
+    world.push(Arc::new(FlipNormals::new(Arc::new(ceiling_light))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XZRectangle::new(0., 555., 0., 555., 555., Arc::clone(&white))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XZRectangle::new(0., 555., 0., 555., 0., Arc::clone(&white))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XYRectangle::new(0., 555., 0., 555., 555., Arc::clone(&white))) as Arc<dyn Hitable>);
+
+    world.push(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(Cuboid::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(165., 165., 165.),
+                &Arc::clone(&white),
+            )),
+            -18.,
+        )),
+        Vec3::new(130., 0., 65.),
+    )) as Arc<dyn Hitable>);
+    world.push(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(Cuboid::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(165., 330., 165.),
+                &Arc::clone(&white),
+            )),
+            15.,
+        )),
+        Vec3::new(265., 0., 295.),
+    )) as Arc<dyn Hitable>);
+    world
+}
+
+pub fn cornell_smoke() -> Vec<Arc<dyn Hitable>> {
+    let mut world = Vec::with_capacity(8);
+    let red = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
+    let white = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
+    let green = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.12, 0.45, 0.15)))));
+    let light = Arc::new(Material::one_sided_light(Arc::new(ConstantTexture::new(Vec3::new(15., 15., 15.)))));
+    world.push(Arc::new(YZRectangle::new(0., 555., 0., 555., 555., green)) as Arc<dyn Hitable>);
+    world.push(Arc::new(YZRectangle::new(0., 555., 0., 555., 0., red)) as Arc<dyn Hitable>);
+    // Facing down into the box.
+    let ceiling_light = XZRectangle::new(113., 443., 127., 432., 554., light);
+    world.push(Arc::new(FlipNormals::new(Arc::new(ceiling_light))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XZRectangle::new(0., 555., 0., 555., 555., Arc::clone(&white))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XZRectangle::new(0., 555., 0., 555., 0., Arc::clone(&white))) as Arc<dyn Hitable>);
+    world.push(Arc::new(XYRectangle::new(0., 555., 0., 555., 555., Arc::clone(&white))) as Arc<dyn Hitable>);
+
+    let b1 = Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(Cuboid::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(165., 165., 165.),
+                &Arc::clone(&white),
+            )),
+            -18.,
+        )),
+        Vec3::new(130., 0., 65.),
+    )) as Arc<dyn Hitable>;
+    let b2 = Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(Cuboid::new(
+                Vec3::new(0., 0., 0.),
+                Vec3::new(165., 330., 165.),
+                &Arc::clone(&white),
+            )),
+            15.,
+        )),
+        Vec3::new(265., 0., 295.),
+    )) as Arc<dyn Hitable>;
+    world.push(Arc::new(ConstantMedium::new(
+        b1,
+        0.01,
+        Arc::new(ConstantTexture::new(Vec3::new(1., 1., 1.))),
+    )) as Arc<dyn Hitable>);
+    world.push(Arc::new(ConstantMedium::new(
+        b2,
+        0.01,
+        Arc::new(ConstantTexture::new(Vec3::new(0., 0., 0.))),
+    )) as Arc<dyn Hitable>);
+    world
+}
+
+pub fn final_scene<R: Rng>(
+    bvh: BvhBuilder,
+    missing_textures: MissingTextures,
+    rng: &mut R,
+) -> Result<Vec<Arc<dyn Hitable>>, TextureError> {
+    let nb = 20;
+    let mut world = Vec::with_capacity(30);
+    let mut boxlist = Vec::with_capacity(10000);
+    let mut boxlist_2 = Vec::with_capacity(10000);
+    let white = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
+    let ground = Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.43, 0.83, 0.53)))));
+    for i in 0..nb {
+        for j in 0..nb {
+            let w = 100.;
+            let (x0, y0, z0) = (-1000. + (i as f32) * w, 0., -1000. + (j as f32) * w);
+            let (x1, y1, z1) = (x0 + w, 100. * (rng.gen::<f32>() + 0.01), z0 + w);
+            boxlist.push(Arc::new(Cuboid::new(
+                Vec3::new(x0, y0, z0),
+                Vec3::new(x1, y1, z1),
+                &Arc::clone(&ground),
+            )) as Arc<dyn Hitable>);
+        }
+    }
+    world.push(Arc::new(bvh.build(&mut boxlist, 0., 1., rng)) as Arc<dyn Hitable>);
+    let light = Arc::new(Material::diffuse_light(Arc::new(ConstantTexture::new(Vec3::new(7., 7., 7.)))));
+    world.push(Arc::new(XZRectangle::new(
+        123.,
+        423.,
+        147.,
+        412.,
+        554.,
+        Arc::clone(&light),
+    )) as Arc<dyn Hitable>);
+    let center = Vec3::new(400., 400., 200.);
+    world.push(Arc::new(MovingSphere::new(
+        center,
+        center + Vec3::new(30., 0., 0.),
+        0.,
+        1.,
+        50.,
+        Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.7, 0.3, 0.1))))),
+    )) as Arc<dyn Hitable>);
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(260., 150., 45.),
+        50.,
+        Arc::new(Material::dielectric(1.5)),
+    )) as Arc<dyn Hitable>);
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(0., 150., 145.),
+        50.,
+        Arc::new(Material::metal(Vec3::new(0.8, 0.8, 0.9), 10.0)),
+    )) as Arc<dyn Hitable>);
+    let boundary = Arc::new(Sphere::new(
+        Vec3::new(360., 150., 145.),
+        70.,
+        Arc::new(Material::dielectric(1.5)),
+    )) as Arc<dyn Hitable>;
+    world.push(Arc::clone(&boundary));
+    world.push(Arc::new(ConstantMedium::new(
+        boundary,
+        0.2,
+        Arc::new(ConstantTexture::new(Vec3::new(0.2, 0.4, 0.9))),
+    )) as Arc<dyn Hitable>);
+    let boundary =
+        Arc::new(Sphere::new(Vec3::empty(), 5000., Arc::new(Material::dielectric(1.5)))) as Arc<dyn Hitable>;
+    world.push(Arc::new(ConstantMedium::new(
+        boundary,
+        0.0001,
+        Arc::new(ConstantTexture::new(Vec3::new(1.0, 1.0, 1.0))),
+    )) as Arc<dyn Hitable>);
+    let earth = Arc::new(Material::lambertian(Arc::new(load_image("earth.png", ImageSettings::default(), missing_textures)?)));
+    world.push(Arc::new(Sphere::new(Vec3::new(400., 200., 400.), 100., earth)) as Arc<dyn Hitable>);
+    let perlin_texture = NoiseTexture::new(rng, 0.1);
+    world.push(Arc::new(Sphere::new(
+        Vec3::new(220., 280., 300.),
+        80.,
+        Arc::new(Material::lambertian(Arc::new(perlin_texture))),
+    )) as Arc<dyn Hitable>);
+    let ns = 1000;
+    for _ in 0..ns {
+        boxlist_2.push(Arc::new(Sphere::new(
+            Vec3::new(
+                165. * rng.gen::<f32>(),
+                165. * rng.gen::<f32>(),
+                165. * rng.gen::<f32>(),
+            ),
+            10.,
+            Arc::clone(&white),
+        )) as Arc<dyn Hitable>);
+    }
+    world.push(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(bvh.build(&mut boxlist_2, 0., 1., rng)),
+            15.,
+        )),
+        Vec3::new(-100., 270., 395.),
+    )) as Arc<dyn Hitable>);
+    Ok(world)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scene {
+    Random,
+    TwoSpheres,
+    TwoPerlinSpheres,
+    SimpleLight,
+    CornellBox,
+    CornellBoxWithCuboids,
+    CornellSmoke,
+    Final,
+}
+
+impl Scene {
+    pub const ALL: [Scene; 8] = [
+        Scene::Random,
+        Scene::TwoSpheres,
+        Scene::TwoPerlinSpheres,
+        Scene::SimpleLight,
+        Scene::CornellBox,
+        Scene::CornellBoxWithCuboids,
+        Scene::CornellSmoke,
+        Scene::Final,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scene::Random => "random",
+            Scene::TwoSpheres => "two_spheres",
+            Scene::TwoPerlinSpheres => "two_perlin_spheres",
+            Scene::SimpleLight => "simple_light",
+            Scene::CornellBox => "cornell_box",
+            Scene::CornellBoxWithCuboids => "cornell_box_with_cuboids",
+            Scene::CornellSmoke => "cornell_smoke",
+            Scene::Final => "final",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Scene> {
+        Scene::ALL.iter().cloned().find(|scene| scene.name() == name)
+    }
+
+    /// Builds the scene's objects, using `bvh` for any hierarchies nested inside the scene and
+    /// handling image textures that can't be loaded as `missing_textures` says.
+    pub fn build<R: Rng>(
+        self,
+        bvh: BvhBuilder,
+        missing_textures: MissingTextures,
+        rng: &mut R,
+    ) -> Result<Vec<Arc<dyn Hitable>>, TextureError> {
+        let objects = match self {
+            Scene::Random => random_scene(rng),
+            Scene::TwoSpheres => two_spheres(),
+            Scene::TwoPerlinSpheres => two_perlin_spheres(rng),
+            Scene::SimpleLight => simple_light(rng),
+            Scene::CornellBox => cornell_box(),
+            Scene::CornellBoxWithCuboids => cornell_box_with_cuboids(),
+            Scene::CornellSmoke => cornell_smoke(),
+            Scene::Final => final_scene(bvh, missing_textures, rng)?,
+        };
+        Ok(objects)
+    }
+
+    /// Whether rays escaping the scene pick up the sky gradient (true) or black (false).
+    pub fn external_light(self) -> bool {
+        matches!(self, Scene::Random | Scene::TwoSpheres | Scene::TwoPerlinSpheres)
+    }
+
+    pub fn default_camera(self) -> CameraSettings {
+        match self {
+            Scene::Random | Scene::TwoSpheres | Scene::TwoPerlinSpheres => CameraSettings {
+                look_from: Vec3::new(13., 2., 3.),
+                look_at: Vec3::new(0., 0., 0.),
+                vfov: 20.,
+                ..CameraSettings::default()
+            },
+            Scene::SimpleLight => CameraSettings {
+                look_from: Vec3::new(26., 3., 6.),
+                look_at: Vec3::new(0., 2., 0.),
+                vfov: 20.,
+                ..CameraSettings::default()
+            },
+            Scene::CornellBox | Scene::CornellBoxWithCuboids | Scene::CornellSmoke => CameraSettings {
+                look_from: Vec3::new(278., 278., -800.),
+                look_at: Vec3::new(278., 278., 0.),
+                vfov: 40.,
+                ..CameraSettings::default()
+            },
+            Scene::Final => CameraSettings {
+                look_from: Vec3::new(478., 278., -600.),
+                look_at: Vec3::new(278., 278., 0.),
+                vfov: 40.,
+                ..CameraSettings::default()
+            },
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use crate::matrices::Matrix4;
+use crate::vectors::Vec3;
+use rand::Rng;
+use std::sync::OnceLock;
+
+/// The range of sampled wavelengths, in nanometres.
+pub const LAMBDA_MIN: f32 = 380.;
+pub const LAMBDA_MAX: f32 = 780.;
+/// Where dispersive materials are evaluated when rendering in RGB: the sodium D line, at which
+/// refractive indices are usually quoted.
+pub const REFERENCE_WAVELENGTH: f32 = 589.3;
+
+const XYZ_TO_LINEAR_SRGB: [[f32; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.969_266, 1.8760108, 0.041556],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+/// A piecewise Gaussian with different widths on either side of its peak.
+fn lobe(lambda: f32, peak: f32, below: f32, above: f32) -> f32 {
+    let t = (lambda - peak) / if lambda < peak { below } else { above };
+    (-0.5 * t * t).exp()
+}
+
+/// The CIE 1931 standard observer's color matching functions, by the multi-lobe fit of
+/// Wyman, Sloan and Shirley (2013).
+fn color_matching(lambda: f32) -> Vec3 {
+    Vec3::new(
+        1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    )
+}
+
+fn smoothstep(edge_0: f32, edge_1: f32, x: f32) -> f32 {
+    let t = ((x - edge_0) / (edge_1 - edge_0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// Smooth red, green and blue reflectance spectra summing to one at every wavelength, so white
+/// upsamples to a flat spectrum and any color in the unit cube to a reflectance between 0 and 1.
+fn basis(lambda: f32) -> Vec3 {
+    let blue = 1. - smoothstep(470., 510., lambda);
+    let red = smoothstep(570., 610., lambda);
+    Vec3::new(red, 1. - red - blue, blue)
+}
+
+/// Maps the CIE XYZ color of a spectrum to the RGB color whose upsampled spectrum looks the same.
+///
+/// This is the standard XYZ to linear sRGB conversion, followed by the inverse of the sRGB colors
+/// the film sees for the three basis spectra, so that colors survive the round trip exactly.
+fn film_matrix() -> &'static Matrix4 {
+    static MATRIX: OnceLock<Matrix4> = OnceLock::new();
+    MATRIX.get_or_init(|| {
+        let mut srgb = Matrix4::identity();
+        for (row, source) in srgb.m.iter_mut().zip(&XYZ_TO_LINEAR_SRGB) {
+            row[..3].copy_from_slice(source);
+        }
+        let mut basis_xyz = [Vec3::empty(); 3];
+        let mut lambda = LAMBDA_MIN + 0.5;
+        while lambda < LAMBDA_MAX {
+            let (cmf, weights) = (color_matching(lambda), basis(lambda));
+            for (channel, xyz) in basis_xyz.iter_mut().enumerate() {
+                *xyz += cmf * weights[channel];
+            }
+            lambda += 1.;
+        }
+        // Normalized so that the flat spectrum of value 1 has luminance 1.
+        let white_y = basis_xyz.iter().map(|xyz| xyz.y).sum::<f32>();
+        let mut basis_rgb = Matrix4::identity();
+        for (channel, xyz) in basis_xyz.iter().enumerate() {
+            let rgb = srgb.transform_vector(*xyz / white_y);
+            for (row, value) in basis_rgb.m.iter_mut().zip(&[rgb.x, rgb.y, rgb.z]) {
+                row[channel] = *value;
+            }
+        }
+        let correction = basis_rgb.inverse().expect("the basis spectra have independent colors");
+        correction * srgb * Matrix4::scaling(Vec3::new(1., 1., 1.) / white_y)
+    })
+}
+
+/// The three wavelengths, in nanometres, a camera sample carries in spectral mode.
+///
+/// Radiance `Vec3`s along its path then hold the values at these wavelengths instead of red,
+/// green and blue. The first, hero wavelength is uniformly distributed and the other two follow
+/// it at a third of the range, so the three together cover the spectrum evenly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Wavelengths {
+    pub lambda: [f32; 3],
+    /// Whether the path has gone through a dispersive material, and so only carries the hero.
+    pub secondary_terminated: bool,
+}
+
+impl Wavelengths {
+    /// The hero wavelength `hero` and its two companions.
+    pub fn new(hero: f32) -> Wavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let at = |offset: f32| LAMBDA_MIN + (hero - LAMBDA_MIN + offset * range) % range;
+        Wavelengths {
+            lambda: [at(0.), at(1. / 3.), at(2. / 3.)],
+            secondary_terminated: false,
+        }
+    }
+
+    pub fn sample<R: Rng>(rng: &mut R) -> Wavelengths {
+        Wavelengths::new(LAMBDA_MIN + rng.gen::<f32>() * (LAMBDA_MAX - LAMBDA_MIN))
+    }
+
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// The values at these wavelengths of the spectrum upsampled from the RGB color `rgb`.
+    pub fn upsample(&self, rgb: Vec3) -> Vec3 {
+        let value = |lambda: f32| basis(lambda).dot(rgb);
+        Vec3::new(value(self.lambda[0]), value(self.lambda[1]), value(self.lambda[2]))
+    }
+
+    /// For a path whose direction depends on the wavelength from here on: the wavelengths it
+    /// continues with, and `values` reduced to the hero wavelength, scaled so that the image
+    /// stays unbiased.
+    pub fn terminate_secondary(self, values: Vec3) -> (Wavelengths, Vec3) {
+        if self.secondary_terminated {
+            return (self, values);
+        }
+        let wavelengths = Wavelengths {
+            secondary_terminated: true,
+            ..self
+        };
+        (wavelengths, Vec3::new(3. * values.x, 0., 0.))
+    }
+
+    /// The linear RGB color of a sample whose radiance at these wavelengths is `radiance`.
+    pub fn rgb(&self, radiance: Vec3) -> Vec3 {
+        let pdf = 1. / (LAMBDA_MAX - LAMBDA_MIN);
+        let xyz = self
+            .lambda
+            .iter()
+            .enumerate()
+            .fold(Vec3::empty(), |xyz, (k, &lambda)| xyz + color_matching(lambda) * (radiance[k] / (3. * pdf)));
+        film_matrix().transform_vector(xyz)
+    }
+}
+
+/// `rgb` as seen by a path: unchanged in RGB mode, or its spectrum's values at the path's wavelengths.
+pub fn color_at(wavelengths: Option<Wavelengths>, rgb: Vec3) -> Vec3 {
+    match wavelengths {
+        Some(wavelengths) => wavelengths.upsample(rgb),
+        None => rgb,
+    }
+}
+
+/// A refractive index, constant or varying with the wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefractiveIndex {
+    Constant(f32),
+    /// `a + b / lambda^2`, with the wavelength in micrometres.
+    Cauchy { a: f32, b: f32 },
+    /// `n^2 = 1 + sum(b_i lambda^2 / (lambda^2 - c_i))`, with the wavelength in micrometres.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl RefractiveIndex {
+    /// The index at `lambda` nanometres.
+    pub fn at(&self, lambda: f32) -> f32 {
+        let micrometres = lambda / 1000.;
+        let l2 = micrometres * micrometres;
+        match self {
+            RefractiveIndex::Constant(index) => *index,
+            RefractiveIndex::Cauchy { a, b } => a + b / l2,
+            RefractiveIndex::Sellmeier { b, c } => {
+                (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
+
+    /// The index seen by a path with `wavelengths`, or at the reference wavelength in RGB mode.
+    pub fn for_path(&self, wavelengths: Option<Wavelengths>) -> f32 {
+        self.at(wavelengths.map_or(REFERENCE_WAVELENGTH, |wavelengths| wavelengths.hero()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prng::XorShiftRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn upsampled_colors_come_back_unchanged() {
+        let colors = [
+            Vec3::new(1., 1., 1.),
+            Vec3::new(0.73, 0.73, 0.73),
+            Vec3::new(0.65, 0.05, 0.05),
+            Vec3::new(0.12, 0.45, 0.15),
+            Vec3::new(0.2, 0.4, 0.9),
+            Vec3::new(15., 15., 15.),
+        ];
+        // Hero wavelengths spread evenly over a third of the range cover the whole range once.
+        let count = 2000;
+        let range = (LAMBDA_MAX - LAMBDA_MIN) / 3.;
+        for &color in &colors {
+            let mut sum = Vec3::empty();
+            for i in 0..count {
+                let wavelengths = Wavelengths::new(LAMBDA_MIN + (i as f32 + 0.5) / count as f32 * range);
+                sum += wavelengths.rgb(wavelengths.upsample(color));
+            }
+            let mean = sum / count as f32;
+            assert!((mean - color).length() < 1e-3 * color.length(), "{:?} came back as {:?}", color, mean);
+        }
+    }
+
+    #[test]
+    fn wavelengths_are_spread_over_the_visible_range() {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let wavelengths = Wavelengths::sample(&mut rng);
+            let mut sorted = wavelengths.lambda;
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            assert!(sorted[0] >= LAMBDA_MIN && sorted[2] < LAMBDA_MAX);
+            let third = (LAMBDA_MAX - LAMBDA_MIN) / 3.;
+            assert!((sorted[1] - sorted[0] - third).abs() < 1e-3 && (sorted[2] - sorted[1] - third).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn refractive_indices_match_published_values() {
+        // Schott N-BK7.
+        let sellmeier = RefractiveIndex::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        };
+        assert!((sellmeier.at(587.6) - 1.5168).abs() < 1e-4);
+        assert!((sellmeier.at(486.1) - 1.5224).abs() < 1e-4);
+        assert!((sellmeier.at(656.3) - 1.5143).abs() < 1e-4);
+        let cauchy = RefractiveIndex::Cauchy { a: 1.5046, b: 0.0042 };
+        assert!(cauchy.at(400.) > cauchy.at(700.));
+        assert!((cauchy.at(587.6) - 1.5168).abs() < 1e-3);
+        assert_eq!(RefractiveIndex::Constant(1.5).for_path(None), 1.5);
+        assert!(!RefractiveIndex::Constant(1.5).is_dispersive() && sellmeier.is_dispersive());
+    }
+}
@@ -0,0 +1,17 @@
+//! Objects that several modules' tests build their scenes from.
+
+use crate::materials::Material;
+use crate::rays::Ray;
+use crate::textures::ConstantTexture;
+use crate::vectors::Vec3;
+use std::sync::Arc;
+
+/// A mid-grey Lambertian.
+pub fn material() -> Arc<Material> {
+    Arc::new(Material::lambertian(Arc::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))))
+}
+
+/// A ray straight down onto the point `(x, 0, z)`, starting one unit above it.
+pub fn down(x: f32, z: f32) -> Ray {
+    Ray::new(Vec3::new(x, 1., z), Vec3::new(0., -1., 0.), 0.)
+}
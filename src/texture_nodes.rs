@@ -0,0 +1,327 @@
+use crate::matrices::Matrix4;
+use crate::textures::Texture;
+use crate::vectors::Vec3;
+use std::sync::Arc;
+
+/// `a` where the first channel of `factor` is 0, `b` where it is 1, and a blend of the two between.
+#[derive(Clone, Debug)]
+pub struct MixTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+    pub factor: Arc<dyn Texture>,
+}
+
+impl MixTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, factor: Arc<dyn Texture>) -> MixTexture {
+        MixTexture { a, b, factor }
+    }
+}
+
+impl Texture for MixTexture {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        let t = self.factor.surface_value(u, v, p, normal, width).x.clamp(0., 1.);
+        self.a.surface_value(u, v, p, normal, width) * (1. - t) + self.b.surface_value(u, v, p, normal, width) * t
+    }
+}
+
+/// How `BlendTexture` combines its two inputs, channel by channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Add,
+    Subtract,
+    Multiply,
+}
+
+/// `a` and `b` combined by `mode`, for tinting, darkening or layering textures.
+#[derive(Clone, Debug)]
+pub struct BlendTexture {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+    pub mode: BlendMode,
+}
+
+impl BlendTexture {
+    pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, mode: BlendMode) -> BlendTexture {
+        BlendTexture { a, b, mode }
+    }
+}
+
+impl Texture for BlendTexture {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        let a = self.a.surface_value(u, v, p, normal, width);
+        let b = self.b.surface_value(u, v, p, normal, width);
+        match self.mode {
+            BlendMode::Add => a + b,
+            BlendMode::Subtract => a - b,
+            BlendMode::Multiply => a * b,
+        }
+    }
+}
+
+/// Maps the first channel of `input` to a gradient through colors at increasing positions,
+/// holding the end colors beyond the first and last stop.
+#[derive(Clone, Debug)]
+pub struct ColorRamp {
+    pub input: Arc<dyn Texture>,
+    stops: Vec<(f32, Vec3)>,
+}
+
+impl ColorRamp {
+    /// Panics if there are no `stops`. They are sorted by position.
+    pub fn new(input: Arc<dyn Texture>, mut stops: Vec<(f32, Vec3)>) -> ColorRamp {
+        assert!(!stops.is_empty(), "a color ramp needs at least one stop");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ColorRamp { input, stops }
+    }
+
+    pub fn color(&self, t: f32) -> Vec3 {
+        let next = self.stops.iter().position(|&(position, _)| position > t);
+        match next {
+            Some(0) => self.stops[0].1,
+            Some(i) => {
+                let ((t0, c0), (t1, c1)) = (self.stops[i - 1], self.stops[i]);
+                let s = (t - t0) / (t1 - t0);
+                c0 * (1. - s) + c1 * s
+            }
+            None => self.stops[self.stops.len() - 1].1,
+        }
+    }
+}
+
+impl Texture for ColorRamp {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        self.color(self.input.surface_value(u, v, p, normal, width).x)
+    }
+}
+
+/// `texture` with its texture coordinates scaled, then rotated counter-clockwise by `rotation`
+/// degrees about (0, 0), then offset, for tiling and placing images.
+#[derive(Clone, Debug)]
+pub struct UvTransform {
+    pub texture: Arc<dyn Texture>,
+    pub scale: (f32, f32),
+    pub rotation: f32,
+    pub offset: (f32, f32),
+}
+
+impl UvTransform {
+    pub fn new(texture: Arc<dyn Texture>, scale: (f32, f32), rotation: f32, offset: (f32, f32)) -> UvTransform {
+        UvTransform {
+            texture,
+            scale,
+            rotation,
+            offset,
+        }
+    }
+
+    pub fn transform(&self, u: f32, v: f32) -> (f32, f32) {
+        let (u, v) = (u * self.scale.0, v * self.scale.1);
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        (cos * u - sin * v + self.offset.0, sin * u + cos * v + self.offset.1)
+    }
+}
+
+impl Texture for UvTransform {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        let (u, v) = self.transform(u, v);
+        let width = width * self.scale.0.abs().max(self.scale.1.abs());
+        self.texture.surface_value(u, v, p, normal, width)
+    }
+}
+
+/// `texture` placed in the world by an affine transform, like a `Transform` object: points are
+/// taken into the texture's space with the inverse matrix.
+#[derive(Clone, Debug)]
+pub struct WorldTransform {
+    pub texture: Arc<dyn Texture>,
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl WorldTransform {
+    /// Panics if `matrix` is not invertible.
+    pub fn new(texture: Arc<dyn Texture>, matrix: Matrix4) -> WorldTransform {
+        let inverse = matrix.inverse().expect("texture transform matrix is not invertible");
+        WorldTransform {
+            texture,
+            matrix,
+            inverse,
+        }
+    }
+}
+
+impl Texture for WorldTransform {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        // Normals go the other way, with the transpose of the inverse of the inverse.
+        let normal = self.matrix.transpose().transform_vector(normal);
+        self.texture.surface_value(u, v, self.inverse.transform_point(p), normal, width)
+    }
+}
+
+/// `texture` projected along the x, y and z axes, with its `u` and `v` taken from the world
+/// position times `scale`, and the three projections blended by how squarely the surface faces
+/// each axis. Higher `sharpness` narrows the blends; surfaces without texture coordinates, or
+/// with stretched ones, can be textured this way.
+#[derive(Clone, Debug)]
+pub struct Triplanar {
+    pub texture: Arc<dyn Texture>,
+    pub scale: f32,
+    pub sharpness: f32,
+}
+
+impl Triplanar {
+    pub fn new(texture: Arc<dyn Texture>, scale: f32, sharpness: f32) -> Triplanar {
+        Triplanar {
+            texture,
+            scale,
+            sharpness,
+        }
+    }
+
+    /// How much the projections along x, y and z count, summing to 1.
+    pub fn weights(&self, normal: Vec3) -> Vec3 {
+        let weights = Vec3::new(
+            normal.x.abs().powf(self.sharpness),
+            normal.y.abs().powf(self.sharpness),
+            normal.z.abs().powf(self.sharpness),
+        );
+        let total = weights.x + weights.y + weights.z;
+        if total > 0. && total.is_finite() {
+            weights / total
+        } else {
+            Vec3::new(1., 1., 1.) / 3.
+        }
+    }
+}
+
+impl Texture for Triplanar {
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, _u: f32, _v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        let weights = self.weights(normal);
+        let q = p * self.scale;
+        let projections = [(q.y, q.z), (q.z, q.x), (q.x, q.y)];
+        let mut color = Vec3::empty();
+        for (axis, &(u, v)) in projections.iter().enumerate() {
+            if weights[axis] > 0. {
+                color += self.texture.surface_value(u, v, p, normal, width) * weights[axis];
+            }
+        }
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::textures::ConstantTexture;
+
+    /// The texture coordinates as red and green, and the position's x as blue.
+    #[derive(Debug)]
+    struct Coordinates;
+
+    impl Texture for Coordinates {
+        fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+            Vec3::new(u, v, p.x)
+        }
+    }
+
+    fn constant(value: f32) -> Arc<dyn Texture> {
+        Arc::new(ConstantTexture::new(Vec3::new(value, value, value)))
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn mix_blend_and_ramp_combine_their_inputs() {
+        let (dark, light) = (constant(0.2), constant(0.6));
+        let p = Vec3::empty();
+        let mix = MixTexture::new(dark.clone(), light.clone(), constant(0.25));
+        assert!(close(mix.value(0., 0., p), Vec3::new(0.3, 0.3, 0.3)));
+        let product = BlendTexture::new(dark.clone(), light.clone(), BlendMode::Multiply);
+        assert!(close(product.value(0., 0., p), Vec3::new(0.12, 0.12, 0.12)));
+        let difference = BlendTexture::new(light, dark, BlendMode::Subtract);
+        assert!(close(difference.value(0., 0., p), Vec3::new(0.4, 0.4, 0.4)));
+
+        let (red, blue) = (Vec3::new(1., 0., 0.), Vec3::new(0., 0., 1.));
+        // Given out of order, and read from the red channel of the coordinates.
+        let ramp = ColorRamp::new(Arc::new(Coordinates), vec![(0.8, blue), (0.4, red)]);
+        assert!(close(ramp.value(0.1, 0., p), red));
+        assert!(close(ramp.value(0.5, 0., p), Vec3::new(0.75, 0., 0.25)));
+        assert!(close(ramp.value(0.9, 0., p), blue));
+        // A NaN position sorts after every number instead of panicking.
+        let ramp = ColorRamp::new(Arc::new(Coordinates), vec![(f32::NAN, blue), (0.4, red)]);
+        assert!(close(ramp.value(0.1, 0., p), red));
+    }
+
+    #[test]
+    fn transforms_move_the_lookups() {
+        let uv = UvTransform::new(Arc::new(Coordinates), (2., 4.), 90., (0.5, 0.));
+        // Scaled to (0.2, 0.4), rotated to (-0.4, 0.2), then offset.
+        assert!(close(uv.value(0.1, 0.1, Vec3::empty()), Vec3::new(0.1, 0.2, 0.)));
+
+        let matrix = Matrix4::translation(Vec3::new(3., 0., 0.)) * Matrix4::scaling(Vec3::new(2., 2., 2.));
+        let world = WorldTransform::new(Arc::new(Coordinates), matrix);
+        assert!(close(world.value(0., 0., Vec3::new(5., 1., 1.)), Vec3::new(0., 0., 1.)));
+    }
+
+    #[test]
+    fn triplanar_projects_along_the_facing_axis() {
+        let triplanar = Triplanar::new(Arc::new(Coordinates), 2., 4.);
+        let p = Vec3::new(0.1, 0.2, 0.3);
+        // Facing up, the texture is laid out over z and x.
+        let up = triplanar.surface_value(0.9, 0.9, p, Vec3::new(0., 1., 0.), 0.);
+        assert!(close(up, Vec3::new(0.6, 0.2, 0.1)));
+        let weights = triplanar.weights(Vec3::new(1., 1., 0.).unit_vector());
+        assert!(close(weights, Vec3::new(0.5, 0.5, 0.)));
+        assert!(close(triplanar.weights(Vec3::empty()), Vec3::new(1., 1., 1.) / 3.));
+    }
+}
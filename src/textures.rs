@@ -1,12 +1,27 @@
-use image::{DynamicImage, GenericImage};
+use image::{ColorType, DynamicImage, GenericImageView, ImageError, Rgb, RgbImage};
 use crate::perlin::Perlin;
 use rand::Rng;
 use crate::vectors::Vec3;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::fmt;
 use std::fmt::Debug;
 
-pub trait Texture: Debug {
+pub trait Texture: Debug + Send + Sync {
     fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3;
+
+    /// The value averaged over a footprint about `width` wide in texture coordinates, so that
+    /// details smaller than a pixel don't shimmer. Textures without such details ignore `width`.
+    #[allow(unused_variables)]
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.value(u, v, p)
+    }
+
+    /// The filtered value on a surface facing `normal`, for textures projected along it rather
+    /// than following `u` and `v`. Textures that combine others pass `normal` on to them.
+    #[allow(unused_variables)]
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
+        self.filtered_value(u, v, p, width)
+    }
 }
 
 
@@ -30,22 +45,30 @@ impl Texture for ConstantTexture {
 
 #[derive(Clone, Debug)]
 pub struct CheckerTexture {
-    pub even: Rc<dyn Texture>,
-    pub odd: Rc<dyn Texture>,
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
 }
 impl CheckerTexture {
-    pub fn new(even: Rc<dyn Texture>, odd: Rc<dyn Texture>) -> CheckerTexture {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> CheckerTexture {
         CheckerTexture { even, odd }
     }
 }
 
 impl Texture for CheckerTexture {
     fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), 0.)
+    }
+
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        self.surface_value(u, v, p, Vec3::empty(), width)
+    }
+
+    fn surface_value(&self, u: f32, v: f32, p: Vec3, normal: Vec3, width: f32) -> Vec3 {
         let sines = (10. * p.x).sin() * (10. * p.y).sin() * (10. * p.z).sin();
         if sines < 0. {
-            self.odd.value(u, v, p)
+            self.odd.surface_value(u, v, p, normal, width)
         } else {
-            self.even.value(u, v, p)
+            self.even.surface_value(u, v, p, normal, width)
         }
     }
 }
@@ -58,8 +81,12 @@ pub struct NoiseTexture {
 
 impl NoiseTexture {
     pub fn new<R: Rng>(rng: &mut R, scale: f32) -> NoiseTexture {
+        NoiseTexture::with_noise(Perlin::new(rng), scale)
+    }
+
+    pub fn with_noise(noise: Perlin, scale: f32) -> NoiseTexture {
         NoiseTexture {
-            noise: Box::new(Perlin::new(rng)),
+            noise: Box::new(noise),
             scale,
         }
     }
@@ -74,43 +101,389 @@ impl Texture for NoiseTexture {
     }
 }
 
+/// How texture coordinates outside 0 to 1 are mapped onto an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    /// The pixel that stands for pixel `i` of a row or column of `n`.
+    fn index(self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let index = match self {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::Clamp => i.clamp(0, n - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+        };
+        index as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /// Bilinear lookups blended between the two mipmap levels closest to the footprint's size.
+    Trilinear,
+}
+
+/// How the values of 8 and 16-bit images are encoded; float images are always linear.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    /// Colors, as image files usually store them.
+    Srgb,
+    /// Data such as normal maps and roughness, used as they are.
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageSettings {
+    pub wrap: Wrap,
+    pub filter: Filter,
+    pub color_space: ColorSpace,
+}
+
+impl Default for ImageSettings {
+    fn default() -> ImageSettings {
+        ImageSettings {
+            wrap: Wrap::Repeat,
+            filter: Filter::Trilinear,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn lerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+    let mut mixed = a;
+    for (value, other) in mixed.iter_mut().zip(&b) {
+        *value += (other - *value) * t;
+    }
+    mixed
+}
+
+/// One resolution of an image: linear red, green, blue and alpha, row by row from the top.
+#[derive(Clone, Debug)]
+struct MipLevel {
+    nx: usize,
+    ny: usize,
+    texels: Vec<[f32; 4]>,
+}
+
+impl MipLevel {
+    fn texel(&self, i: i64, j: i64, wrap: Wrap) -> [f32; 4] {
+        self.texels[wrap.index(i, self.nx) + self.nx * wrap.index(j, self.ny)]
+    }
+
+    fn nearest(&self, u: f32, v: f32, wrap: Wrap) -> [f32; 4] {
+        let i = (u * self.nx as f32).floor() as i64;
+        let j = ((1. - v) * self.ny as f32).floor() as i64;
+        self.texel(i, j, wrap)
+    }
+
+    fn bilinear(&self, u: f32, v: f32, wrap: Wrap) -> [f32; 4] {
+        // Pixel centers are at half-integer positions.
+        let x = u * self.nx as f32 - 0.5;
+        let y = (1. - v) * self.ny as f32 - 0.5;
+        let (i, j) = (x.floor(), y.floor());
+        let (s, t) = (x - i, y - j);
+        let (i, j) = (i as i64, j as i64);
+        let top = lerp(self.texel(i, j, wrap), self.texel(i + 1, j, wrap), s);
+        let bottom = lerp(self.texel(i, j + 1, wrap), self.texel(i + 1, j + 1, wrap), s);
+        lerp(top, bottom, t)
+    }
+
+    /// The level at half the resolution, each pixel the average of the (up to) four it covers.
+    fn downsampled(&self) -> MipLevel {
+        let (nx, ny) = (self.nx.div_ceil(2), self.ny.div_ceil(2));
+        let mut texels = Vec::with_capacity(nx * ny);
+        for j in 0..ny {
+            for i in 0..nx {
+                let mut sum = [0.; 4];
+                for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let texel = self.texel((2 * i + di) as i64, (2 * j + dj) as i64, Wrap::Clamp);
+                    for (total, value) in sum.iter_mut().zip(&texel) {
+                        *total += value / 4.;
+                    }
+                }
+                texels.push(sum);
+            }
+        }
+        MipLevel { nx, ny, texels }
+    }
+}
+
+/// An image file that could not be opened or decoded.
+#[derive(Debug)]
+pub struct TextureError {
+    pub path: String,
+    pub error: ImageError,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not load {}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+/// What scene construction does about image textures that can't be loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingTextures {
+    /// Stop with a `TextureError`.
+    Fail,
+    /// Warn on standard error and use `ImageTexture::placeholder` instead, so that a batch of
+    /// renders reports every missing asset rather than stopping at the first.
+    Placeholder,
+}
+
+/// The image at `image_file`, or the placeholder for it if it is missing and `missing` allows that.
+pub fn load_image(
+    image_file: &str,
+    settings: ImageSettings,
+    missing: MissingTextures,
+) -> Result<ImageTexture, TextureError> {
+    match ImageTexture::with_settings(image_file, settings) {
+        Err(error) if missing == MissingTextures::Placeholder => {
+            eprintln!("warning: {}, using a placeholder", error);
+            Ok(ImageTexture::placeholder())
+        }
+        result => result,
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ImageTexture {
     pub nx: usize,
     pub ny: usize,
-    pub data: Vec<u8>,
+    pub settings: ImageSettings,
+    /// The image at full resolution, then halved down to a single pixel for trilinear filtering.
+    levels: Vec<MipLevel>,
 }
 impl ImageTexture {
-    pub fn new(image_file: &str) -> Self {
-        let img = image::open(image_file).unwrap();
+    pub fn new(image_file: &str) -> Result<Self, TextureError> {
+        ImageTexture::with_settings(image_file, ImageSettings::default())
+    }
+
+    pub fn with_settings(image_file: &str, settings: ImageSettings) -> Result<Self, TextureError> {
+        match image::open(image_file) {
+            Ok(img) => Ok(ImageTexture::from_image(&img, settings)),
+            Err(error) => Err(TextureError {
+                path: image_file.to_owned(),
+                error,
+            }),
+        }
+    }
+
+    /// A magenta and black checkerboard of 8 by 8 squares, which stands out where an image is missing.
+    pub fn placeholder() -> Self {
+        let checker = RgbImage::from_fn(8, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([255, 0, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        });
+        let settings = ImageSettings {
+            filter: Filter::Nearest,
+            ..ImageSettings::default()
+        };
+        ImageTexture::from_image(&DynamicImage::ImageRgb8(checker), settings)
+    }
+
+    /// Decodes 8 and 16-bit images in `settings.color_space`, and float images as linear.
+    pub fn from_image(img: &DynamicImage, settings: ImageSettings) -> Self {
         let (nx, ny) = (img.dimensions().0 as usize, img.dimensions().1 as usize);
-        let data = get_img_data(&img, nx, ny);
+        let is_float = matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let decode = !is_float && settings.color_space == ColorSpace::Srgb;
+        let texels = img
+            .to_rgba32f()
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b, a] = pixel.0;
+                if decode {
+                    [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a]
+                } else {
+                    [r, g, b, a]
+                }
+            })
+            .collect();
+        let mut levels = vec![MipLevel { nx, ny, texels }];
+        if settings.filter == Filter::Trilinear {
+            while levels.last().is_some_and(|level| level.nx > 1 || level.ny > 1) {
+                let next = levels.last().unwrap().downsampled();
+                levels.push(next);
+            }
+        }
         ImageTexture {
-            nx, ny, data
+            nx,
+            ny,
+            settings,
+            levels,
         }
     }
-}
 
-fn get_img_data(img: &DynamicImage, nx: usize, ny: usize) -> Vec<u8> {
-    let mut data = Vec::with_capacity(nx * ny * 3);
-    for (_, _, pixel) in img.pixels() {
-        data.push(pixel[0]);
-        data.push(pixel[1]);
-        data.push(pixel[2]);
+    /// Red, green, blue and alpha at `(u, v)`, averaged over about `width` in texture coordinates.
+    fn lookup(&self, u: f32, v: f32, width: f32) -> [f32; 4] {
+        let wrap = self.settings.wrap;
+        match self.settings.filter {
+            Filter::Nearest => self.levels[0].nearest(u, v, wrap),
+            Filter::Bilinear => self.levels[0].bilinear(u, v, wrap),
+            Filter::Trilinear => {
+                let pixels = width * self.nx.max(self.ny) as f32;
+                let level = pixels.max(1.).log2().min((self.levels.len() - 1) as f32);
+                let finer = level.floor() as usize;
+                let sample = self.levels[finer].bilinear(u, v, wrap);
+                if finer + 1 < self.levels.len() {
+                    lerp(sample, self.levels[finer + 1].bilinear(u, v, wrap), level - finer as f32)
+                } else {
+                    sample
+                }
+            }
+        }
+    }
+
+    /// The opacity at `(u, v)`, 1 for images without an alpha channel.
+    pub fn alpha(&self, u: f32, v: f32) -> f32 {
+        self.lookup(u, v, 0.)[3]
     }
-    data
 }
 
 impl Texture for ImageTexture {
     #[allow(unused_variables)]
     fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
-        let i = ((u * self.nx as f32) as usize).max(0).min(self.nx - 1);
-        let j = (((1. - v) * self.ny as f32 - 0.001) as usize).max(0).min(self.ny - 1);
-        let (r, g, b) = (
-            f32::from(self.data[3 * i + 3 * self.nx * j]) / 255.,
-            f32::from(self.data[3 * i + 3 * self.nx * j + 1]) / 255.,
-            f32::from(self.data[3 * i + 3 * self.nx * j + 2]) / 255.,
-        );
+        self.filtered_value(u, v, p, 0.)
+    }
+
+    #[allow(unused_variables)]
+    fn filtered_value(&self, u: f32, v: f32, p: Vec3, width: f32) -> Vec3 {
+        let [r, g, b, _] = self.lookup(u, v, width);
         Vec3::new(r, g, b)
     }
 }
+
+/// The alpha channel of an image as a gray texture, for cutting shapes out of surfaces.
+#[derive(Clone, Debug)]
+pub struct AlphaTexture {
+    pub image: Arc<ImageTexture>,
+}
+
+impl AlphaTexture {
+    pub fn new(image: Arc<ImageTexture>) -> AlphaTexture {
+        AlphaTexture { image }
+    }
+}
+
+impl Texture for AlphaTexture {
+    #[allow(unused_variables)]
+    fn value(&self, u: f32, v: f32, p: Vec3) -> Vec3 {
+        let alpha = self.image.alpha(u, v);
+        Vec3::new(alpha, alpha, alpha)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Luma, Rgb32FImage};
+
+    fn settings(wrap: Wrap, filter: Filter, color_space: ColorSpace) -> ImageSettings {
+        ImageSettings {
+            wrap,
+            filter,
+            color_space,
+        }
+    }
+
+    fn red(texture: &ImageTexture, u: f32, v: f32, width: f32) -> f32 {
+        texture.filtered_value(u, v, Vec3::empty(), width).x
+    }
+
+    #[test]
+    fn wrap_modes_map_pixels_outside_the_image() {
+        assert_eq!([-1, 4, 9].map(|i| Wrap::Repeat.index(i, 4)), [3, 0, 1]);
+        assert_eq!([-1, 4, 9].map(|i| Wrap::Clamp.index(i, 4)), [0, 3, 3]);
+        assert_eq!([-1, 4, 5, 9].map(|i| Wrap::Mirror.index(i, 4)), [0, 3, 2, 1]);
+    }
+
+    #[test]
+    fn bilinear_filtering_blends_neighbouring_pixels() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(2, 1, |x, _| Rgb([255 * x as u8; 3])));
+        let clamped = ImageTexture::from_image(&image, settings(Wrap::Clamp, Filter::Bilinear, ColorSpace::Linear));
+        assert_eq!(red(&clamped, 0.25, 0.5, 0.), 0.);
+        assert!((red(&clamped, 0.5, 0.5, 0.) - 0.5).abs() < 1e-6);
+        assert!((red(&clamped, 0.625, 0.5, 0.) - 0.75).abs() < 1e-6);
+        assert_eq!(red(&clamped, 0., 0.5, 0.), 0.);
+        // Repeating, the left edge is halfway between the last pixel and the first.
+        let repeated = ImageTexture::from_image(&image, settings(Wrap::Repeat, Filter::Bilinear, ColorSpace::Linear));
+        assert!((red(&repeated, 0., 0.5, 0.) - 0.5).abs() < 1e-6);
+        assert!((red(&repeated, 1.25, 0.5, 0.) - 0.).abs() < 1e-6);
+        let nearest = ImageTexture::from_image(&image, settings(Wrap::Repeat, Filter::Nearest, ColorSpace::Linear));
+        assert_eq!(red(&nearest, 0.45, 0.5, 0.), 0.);
+        assert_eq!(red(&nearest, 0.55, 0.5, 0.), 1.);
+    }
+
+    #[test]
+    fn images_are_decoded_to_linear_values() {
+        let gray = |value: u8| DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([value; 3])));
+        let srgb = settings(Wrap::Repeat, Filter::Bilinear, ColorSpace::Srgb);
+        let linear = settings(Wrap::Repeat, Filter::Bilinear, ColorSpace::Linear);
+        // sRGB 188 is very nearly half the linear intensity.
+        assert!((red(&ImageTexture::from_image(&gray(188), srgb), 0.5, 0.5, 0.) - 0.5).abs() < 0.005);
+        assert_eq!(red(&ImageTexture::from_image(&gray(255), srgb), 0.5, 0.5, 0.), 1.);
+        assert!((red(&ImageTexture::from_image(&gray(188), linear), 0.5, 0.5, 0.) - 188. / 255.).abs() < 1e-6);
+        let deep: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_pixel(1, 1, Luma([48_195]));
+        let deep = ImageTexture::from_image(&DynamicImage::ImageLuma16(deep), srgb);
+        assert!((red(&deep, 0.5, 0.5, 0.) - 0.5).abs() < 0.001);
+        // Float images hold linear radiance, beyond 1 too.
+        let hdr = DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(1, 1, Rgb([4., 0.5, 0.25])));
+        let hdr = ImageTexture::from_image(&hdr, srgb);
+        let value = hdr.value(0.5, 0.5, Vec3::empty());
+        assert!((value - Vec3::new(4., 0.5, 0.25)).length() < 1e-6);
+        assert_eq!(hdr.alpha(0.5, 0.5), 1.);
+    }
+
+    #[test]
+    fn mipmaps_average_details_smaller_than_the_footprint() {
+        let checker = RgbImage::from_fn(64, 32, |x, y| Rgb([255 * ((x + y) % 2) as u8; 3]));
+        let image = DynamicImage::ImageRgb8(checker);
+        let texture = ImageTexture::from_image(&image, settings(Wrap::Repeat, Filter::Trilinear, ColorSpace::Linear));
+        // At a pixel center with no footprint, the pixel itself.
+        let (u, v) = (1.5 / 64., 1. - 0.5 / 32.);
+        assert_eq!(red(&texture, u, v, 0.), 1.);
+        // A footprint of several pixels sees the mean, even half-way between two levels.
+        for &width in &[4. / 64., 6. / 64., 1.] {
+            assert!((red(&texture, u, v, width) - 0.5).abs() < 1e-5, "{}", width);
+        }
+    }
+
+    #[test]
+    fn missing_images_are_errors_or_placeholders() {
+        let error = ImageTexture::new("no/such/image.png").unwrap_err();
+        assert_eq!(error.path, "no/such/image.png");
+        assert!(error.to_string().starts_with("could not load no/such/image.png: "), "{}", error);
+        let defaults = ImageSettings::default();
+        assert!(load_image("no/such/image.png", defaults, MissingTextures::Fail).is_err());
+        let placeholder = load_image("no/such/image.png", defaults, MissingTextures::Placeholder).unwrap();
+        let corner = placeholder.value(0.01, 0.99, Vec3::empty());
+        assert!((corner - Vec3::new(1., 0., 1.)).length() < 1e-6, "{:?}", corner);
+    }
+}